@@ -0,0 +1,215 @@
+-- Upgrades in place a database created with the first `tables.sql`, keeping its rows.
+-- `tables.sql` itself recreates every table empty.
+--
+-- Rows of the old schema have no provider id, they belong to the one provider each chain was
+-- configured with, passed as `(chain_id, provider_id)` pairs:
+--
+--   psql -v legacy_providers="(1, 5), (137, 2)" -f migrations/001_baseline_upgrade.sql
+--
+-- CIDs indexed before are kept as emitted, `cid_original` is set to them.
+\set ON_ERROR_STOP on
+begin;
+
+create temp table legacy_providers
+(
+    chain_id bigint not null,
+    provider_id bigint not null,
+    primary key (chain_id)
+) on commit drop;
+insert into legacy_providers (chain_id, provider_id) values :legacy_providers;
+
+-- event_update_valid_block: keyed by log, with the listing and the payment verification
+alter table event_update_valid_block
+    add column if not exists cid_original text,
+    add column if not exists claimed_end_block bigint,
+    add column if not exists paid_wei numeric,
+    add column if not exists paid_end_block bigint,
+    add column if not exists payment text,
+    add column if not exists provider_id bigint,
+    add column if not exists tx_hash text,
+    add column if not exists log_index bigint,
+    add column if not exists id bigserial;
+update event_update_valid_block set cid_original = cid where cid_original is null;
+update event_update_valid_block e set provider_id = l.provider_id
+    from legacy_providers l where e.provider_id is null and e.chain_id = l.chain_id;
+update event_update_valid_block set payment = 'unchecked'
+    where payment is null and not coalesce(manual_add, false);
+-- fails when a chain with rows is missing from `legacy_providers`
+alter table event_update_valid_block
+    alter column cid_original set not null,
+    alter column provider_id set not null,
+    drop constraint if exists event_update_valid_block_pkey,
+    add primary key (id),
+    add unique (chain_id, tx_hash, log_index);
+create unique index if not exists event_update_valid_block_manual on event_update_valid_block
+    (chain_id, provider_id, cid, donor, update_block, end_block) where manual_add;
+create index if not exists event_update_valid_block_payment on event_update_valid_block (chain_id, payment);
+
+-- event_add_provider: the old table becomes `AddProvider` rows of provider_events and a view over them
+create table if not exists provider_events
+(
+    id bigserial,
+    chain_id bigint not null,
+    provider_id bigint not null,
+    event text not null,
+    update_block bigint not null,
+    tx_hash text not null,
+    log_index bigint not null,
+    owner text,
+    block_price_gwei bigint,
+    api_url text,
+    name text,
+    primary key (id),
+    unique (chain_id, tx_hash, log_index)
+);
+create index if not exists provider_events_provider on provider_events (chain_id, provider_id, update_block);
+
+do $$
+begin
+    if exists (select 1 from pg_class where relname = 'event_add_provider' and relkind = 'r') then
+        -- the old rows have no log, they are keyed by their order within the chain
+        insert into provider_events (chain_id, provider_id, event, update_block, tx_hash, log_index,
+                                     owner, block_price_gwei, api_url, name)
+        select chain_id, provider_id, 'AddProvider', update_block, 'legacy',
+               row_number() over (partition by chain_id order by update_block, provider_id),
+               lower(owner), block_price_gwei, api_url, name
+        from event_add_provider
+        on conflict (chain_id, tx_hash, log_index) do nothing;
+        drop table event_add_provider;
+    end if;
+end $$;
+
+drop view if exists event_add_provider;
+create view event_add_provider as
+select p.chain_id,
+       p.provider_id,
+       (select max(e.update_block) from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id) as update_block,
+       (select e.owner from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.owner is not null
+        order by e.update_block desc, e.log_index desc limit 1) as owner,
+       (select e.block_price_gwei from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.block_price_gwei is not null
+        order by e.update_block desc, e.log_index desc limit 1) as block_price_gwei,
+       (select e.api_url from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.api_url is not null
+        order by e.update_block desc, e.log_index desc limit 1) as api_url,
+       (select e.name from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.name is not null
+        order by e.update_block desc, e.log_index desc limit 1) as name
+from (select distinct chain_id, provider_id from provider_events where event = 'AddProvider') p;
+
+-- pinned_cids and failed_pins: per listing, expired pins wait for the grace period
+alter table pinned_cids
+    add column if not exists provider_id bigint,
+    add column if not exists pending_unpin boolean not null default false;
+update pinned_cids p set provider_id = l.provider_id
+    from legacy_providers l where p.provider_id is null and p.chain_id = l.chain_id;
+alter table pinned_cids
+    alter column provider_id set not null,
+    drop constraint if exists pinned_cids_pkey,
+    add primary key (chain_id, provider_id, node, cid, end_block);
+
+alter table failed_pins add column if not exists provider_id bigint;
+update failed_pins f set provider_id = l.provider_id
+    from legacy_providers l where f.provider_id is null and f.chain_id = l.chain_id;
+alter table failed_pins
+    alter column provider_id set not null,
+    drop constraint if exists failed_pins_pkey,
+    add primary key (chain_id, provider_id, node, cid, end_block);
+
+-- tables added since, as in `tables.sql`
+create table if not exists drained_nodes
+(
+    node text not null,
+    drained_at timestamp without time zone not null default now(),
+    primary key (node)
+);
+
+create table if not exists auth_nonces
+(
+    nonce text not null,
+    created_at timestamp without time zone not null default now(),
+    primary key (nonce)
+);
+
+create table if not exists sessions
+(
+    token text not null,
+    address text not null,
+    chain_id bigint not null,
+    scopes text[] not null default '{}',
+    expires_at timestamp without time zone not null,
+    primary key (token)
+);
+
+create table if not exists monitoring_history
+(
+    id bigserial,
+    chain_id bigint not null,
+    kind text not null,
+    event text not null,
+    ts bigint not null,
+    update_block bigint not null,
+    duration_ms bigint not null,
+    count bigint not null,
+    primary key (id)
+);
+create index if not exists monitoring_history_chain_ts on monitoring_history (chain_id, ts);
+
+create table if not exists webhook_outbox
+(
+    id bigserial,
+    url text not null,
+    subscription text not null, -- see `webhooks::subscription_id`
+    event text not null,
+    payload text not null,
+    attempts int not null default 0,
+    next_attempt_at bigint not null,
+    last_error text,
+    delivered_at bigint,
+    primary key (id)
+);
+create index if not exists webhook_outbox_pending on webhook_outbox (next_attempt_at) where delivered_at is null;
+
+create table if not exists rejected_events
+(
+    id bigserial,
+    chain_id bigint not null,
+    event text not null,
+    update_block bigint,
+    tx_hash text,
+    data text not null,
+    reason text not null,
+    ts bigint not null,
+    primary key (id)
+);
+create index if not exists rejected_events_chain_ts on rejected_events (chain_id, ts);
+
+create table if not exists quarantined_logs
+(
+    id bigserial,
+    chain_id bigint not null,
+    event text not null,
+    block_number bigint,
+    tx_hash text,
+    topics text[] not null,
+    data text not null,
+    log jsonb not null,
+    error text not null,
+    ts bigint not null,
+    reprocessed_at bigint,
+    primary key (id)
+);
+create index if not exists quarantined_logs_pending on quarantined_logs (chain_id) where reprocessed_at is null;
+
+create table if not exists cid_sizes
+(
+    cid text not null,
+    size bigint not null,
+    num_blocks bigint not null,
+    updated_at bigint not null,
+    primary key (cid)
+);
+
+commit;
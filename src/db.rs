@@ -1,5 +1,9 @@
 use crate::types::{
-    db::{EventAddProvider, EventUpdateValidBlock},
+    db::{
+        ChainStorage, DonorStorage, EventUpdateValidBlock, NodePin, NodeStorage, PaymentEvent,
+        ProviderEvent, QuarantinedLog, RejectedEvent, StatsWindow, WebhookDelivery,
+    },
+    monitoring::HistoryEvent,
     CIDInfo,
 };
 
@@ -7,111 +11,71 @@ pub fn add_valid_block(
     client: &mut postgres::Client,
     event: EventUpdateValidBlock,
 ) -> Result<u64, postgres::Error> {
-    client.execute("
-                    INSERT INTO event_update_valid_block ( donor, update_block, end_block, cid, chain_id, manual_add) 
-                    values ( LOWER($1::TEXT), $2::BIGINT, $3::BIGINT, $4::TEXT, $5::BIGINT, $6::BOOLEAN)
-                    ON CONFLICT (donor, update_block, end_block, cid, chain_id, manual_add) DO NOTHING
-                ",
-                    &[&event.donor, &event.update_block, &event.end_block, &event.cid, &event.chain_id, &event.manual_add]
-                )
-}
-
-pub fn add_provider(
-    client: &mut postgres::Client,
-    event: EventAddProvider,
-) -> Result<u64, postgres::Error> {
-    client.execute("
-                    INSERT INTO event_add_provider (owner, update_block, provider_id, api_url, chain_id, block_price_gwei, name) 
-                    values ( LOWER($1::TEXT), $2::BIGINT, $3::BIGINT, $4::TEXT, $5::BIGINT, $6::BIGINT, $7::TEXT)
-                    ON CONFLICT (owner, update_block, provider_id, api_url, chain_id, block_price_gwei, name) DO NOTHING
-                ",
-                    &[&event.owner, &event.update_block, &event.provider_id, &event.api_url, &event.chain_id, &event.block_price_gwei, &event.name]
+    // logs are keyed like `provider_events`, manual adds have no log and their own partial index
+    let conflict = if event.tx_hash.is_some() {
+        "(chain_id, tx_hash, log_index)"
+    } else {
+        "(chain_id, provider_id, cid, donor, update_block, end_block) WHERE manual_add"
+    };
+    client.execute(&format!("
+                    INSERT INTO event_update_valid_block ( donor, update_block, end_block, cid, chain_id, manual_add, cid_original,
+                        claimed_end_block, paid_wei, paid_end_block, payment, provider_id, tx_hash, log_index) 
+                    values ( LOWER($1::TEXT), $2::BIGINT, $3::BIGINT, $4::TEXT, $5::BIGINT, $6::BOOLEAN, $7::TEXT,
+                        $8::BIGINT, $9::TEXT::NUMERIC, $10::BIGINT, $11::TEXT, $12::BIGINT, $13::TEXT, $14::BIGINT)
+                    ON CONFLICT {} DO NOTHING
+                ", conflict),
+                    &[&event.donor, &event.update_block, &event.end_block, &event.cid, &event.chain_id, &event.manual_add, &event.cid_original,
+                        &event.claimed_end_block, &event.paid_wei, &event.paid_end_block, &event.payment, &event.provider_id,
+                        &event.tx_hash, &event.log_index]
                 )
 }
 
-pub fn update_provider_block_price(
+pub fn add_provider_event(
     client: &mut postgres::Client,
-    chain_id: i64,
-    update_block: i64,
-    provider_id: i64,
-    block_price: i64,
-) -> Result<u64, postgres::Error> {
-    client.execute(
-        "
-                    UPDATE event_add_provider 
-                    SET update_block=$1::BIGINT, block_price_gwei=$2::BIGINT
-                    WHERE update_block<$1::BIGINT AND provider_id=$3::BIGINT AND chain_id=$4::BIGINT
-                ",
-        &[&update_block, &block_price, &provider_id, &chain_id],
-    )
-}
-
-pub fn update_provider_api_url(
-    client: &mut postgres::Client,
-    chain_id: i64,
-    update_block: i64,
-    provider_id: i64,
-    api_url: String,
+    event: &ProviderEvent,
 ) -> Result<u64, postgres::Error> {
     client.execute(
-        "
-                    UPDATE event_add_provider 
-                    SET update_block=$1::BIGINT, api_url=$2::TEXT
-                    WHERE update_block<$1::BIGINT AND provider_id=$3::BIGINT AND chain_id=$4::BIGINT
-                ",
-        &[&update_block, &api_url, &provider_id, &chain_id],
+        "INSERT INTO provider_events (chain_id, provider_id, event, update_block, tx_hash, log_index,
+                        owner, block_price_gwei, api_url, name)
+                    VALUES ($1::BIGINT, $2::BIGINT, $3::TEXT, $4::BIGINT, $5::TEXT, $6::BIGINT,
+                        LOWER($7::TEXT), $8::BIGINT, $9::TEXT, $10::TEXT)
+                    ON CONFLICT (chain_id, tx_hash, log_index) DO NOTHING",
+        &[
+            &event.chain_id,
+            &event.provider_id,
+            &event.event,
+            &event.update_block,
+            &event.tx_hash,
+            &event.log_index,
+            &event.owner,
+            &event.block_price_gwei,
+            &event.api_url,
+            &event.name,
+        ],
     )
 }
 
-pub fn update_provider_owner(
+pub fn cid_exists(
     client: &mut postgres::Client,
     chain_id: i64,
-    update_block: i64,
     provider_id: i64,
-    owner: String,
-) -> Result<u64, postgres::Error> {
-    client.execute(
+    cid: &str,
+) -> Result<bool, postgres::Error> {
+    let row = client.query_one(
         "
-                    UPDATE event_add_provider 
-                    SET update_block=$1::BIGINT, owner=$2::TEXT
-                    WHERE update_block<$1::BIGINT AND provider_id=$3::BIGINT AND chain_id=$4::BIGINT
+                    SELECT count(cid) FROM event_update_valid_block
+                    WHERE cid=$1::TEXT AND chain_id=$2::BIGINT AND provider_id=$3::BIGINT;
                 ",
-        &[&update_block, &owner, &provider_id, &chain_id],
-    )
-}
+        &[&cid, &chain_id, &provider_id],
+    )?;
 
-pub fn update_provider_name(
-    client: &mut postgres::Client,
-    chain_id: i64,
-    update_block: i64,
-    provider_id: i64,
-    name: String,
-) -> Result<u64, postgres::Error> {
-    client.execute(
-        "
-                    UPDATE event_add_provider 
-                    SET update_block=$1::BIGINT, name=$2::TEXT
-                    WHERE update_block<$1::BIGINT AND provider_id=$3::BIGINT AND chain_id=$4::BIGINT
-                ",
-        &[&update_block, &name, &provider_id, &chain_id],
-    )
+    let count: i64 = row.try_get(0)?;
+    if count > 0 {
+        return Ok(true);
+    }
+    Ok(false)
 }
 
-// pub fn cid_exists(client: &mut postgres::Client, cid: &str) -> Result<bool, postgres::Error> {
-//     let row = client.query_one(
-//         "
-//                     SELECT count(cid) FROM event_update_valid_block WHERE cid=$1::TEXT;
-//                 ",
-//         &[&cid],
-//     )?;
-
-//     let count: i64 = row.try_get(0)?;
-//     if count > 0 {
-//         return Ok(true);
-//     }
-//     Ok(false)
-// }
-
 pub fn get_max_update_block(
     client: &mut postgres::Client,
     table: String,
@@ -131,73 +95,123 @@ pub fn get_max_update_block(
 pub fn delete_cid(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     node: String,
     cid: String,
     end_block: i64,
 ) -> Result<u64, postgres::Error> {
     client.execute("DELETE FROM pinned_cids
-                            WHERE chain_id=$1::BIGINT AND node=$2::TEXT AND cid=$3::TEXT AND end_block=$4::BIGINT;", 
-                    &[&chain_id, &node, &cid, &end_block])
+                            WHERE chain_id=$1::BIGINT AND node=$2::TEXT AND cid=$3::TEXT AND end_block=$4::BIGINT
+                            AND provider_id=$5::BIGINT;", 
+                    &[&chain_id, &node, &cid, &end_block, &provider_id])
 }
 
 pub fn add_cid(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     node: String,
     cid: String,
     end_block: i64,
 ) -> Result<u64, postgres::Error> {
     client.execute(
-        "INSERT INTO pinned_cids (chain_id, node, cid, end_block)
-                                          VALUES ($1::BIGINT, $2::TEXT, $3::TEXT, $4::BIGINT)",
-        &[&chain_id, &node, &cid, &end_block],
+        "INSERT INTO pinned_cids (chain_id, provider_id, node, cid, end_block)
+                                          VALUES ($1::BIGINT, $5::BIGINT, $2::TEXT, $3::TEXT, $4::BIGINT)",
+        &[&chain_id, &node, &cid, &end_block, &provider_id],
     )
 }
 
 pub fn add_failed_pin(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     node: &str,
     cid: &str,
     end_block: i64,
 ) -> Result<u64, postgres::Error> {
     client.execute(
-        "INSERT INTO failed_pins (chain_id, node, cid, end_block)
-                                    VALUES ($1::BIGINT, $2::TEXT, $3::TEXT, $4::BIGINT)
-                                    ON CONFLICT (chain_id, node, cid, end_block) DO NOTHING",
-        &[&chain_id, &node, &cid, &end_block],
+        "INSERT INTO failed_pins (chain_id, provider_id, node, cid, end_block)
+                                    VALUES ($1::BIGINT, $5::BIGINT, $2::TEXT, $3::TEXT, $4::BIGINT)
+                                    ON CONFLICT (chain_id, provider_id, node, cid, end_block) DO NOTHING",
+        &[&chain_id, &node, &cid, &end_block, &provider_id],
     )
 }
 
+/// Marks the expired pins of a listing `pending_unpin`, returns the CIDs newly marked
+pub fn mark_pending_unpin(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+    end_block: i64,
+) -> Result<Vec<CIDInfo>, postgres::Error> {
+    let r = client.query(
+        "WITH m AS (
+                        UPDATE pinned_cids SET pending_unpin=true
+                        WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT AND NOT pending_unpin
+                        AND end_block<=$3::BIGINT AND end_block <> -1::BIGINT
+                        AND NOT EXISTS (SELECT 1 FROM event_update_valid_block e
+                            WHERE e.chain_id=pinned_cids.chain_id AND e.provider_id=pinned_cids.provider_id
+                            AND e.cid=pinned_cids.cid AND (e.end_block>$3::BIGINT OR e.end_block=-1::BIGINT))
+                        RETURNING cid, end_block
+                    )
+                    SELECT cid, MAX(end_block) FROM m GROUP BY cid",
+        &[&chain_id, &provider_id, &end_block],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| CIDInfo {
+            chain_id: Option::Some(chain_id),
+            provider_id: Option::Some(provider_id),
+            cid: row.get(0),
+            end_block: row.get(1),
+            node: Option::None,
+            node_login: Option::None,
+            node_pass: Option::None,
+        })
+        .collect())
+}
+
+/// Pending unpins of a listing whose CID is still pinned on the node for another chain or listing,
+/// the row goes but the CID stays pinned. Pins renewed by an indexed event are kept even before
+/// `update_existing_cids_end_block` extended them
 pub fn delete_multichain_expired_cids(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     end_block: i64,
 ) -> Result<u64, postgres::Error> {
     client.execute("DELETE FROM pinned_cids AS p1
                         USING pinned_cids AS p2
-                        WHERE p1.chain_id=$1::BIGINT AND p1.end_block<=$2::BIGINT AND p1.end_block <> -1::BIGINT
-                        AND p1.chain_id!=p2.chain_id 
-                        AND p1.cid=p2.cid;",
-            &[&chain_id, &end_block])
+                        WHERE p1.chain_id=$1::BIGINT AND p1.provider_id=$3::BIGINT AND p1.pending_unpin
+                        AND p1.end_block<=$2::BIGINT AND p1.end_block <> -1::BIGINT
+                        AND (p1.chain_id!=p2.chain_id OR p1.provider_id!=p2.provider_id)
+                        AND p1.cid=p2.cid AND p1.node=p2.node
+                        AND NOT EXISTS (SELECT 1 FROM event_update_valid_block e
+                            WHERE e.chain_id=p1.chain_id AND e.provider_id=p1.provider_id AND e.cid=p1.cid
+                            AND (e.end_block>$2::BIGINT OR e.end_block=-1::BIGINT));",
+            &[&chain_id, &end_block, &provider_id])
 }
 
 pub fn get_single_chain_expired_cids(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     end_block: i64,
 ) -> Result<Vec<CIDInfo>, postgres::Error> {
     let res = client.query("SELECT p1.cid, p1.end_block, p1.node 
                                           FROM pinned_cids AS p1
-                                          LEFT JOIN pinned_cids p2 ON p1.chain_id!=p2.chain_id AND p1.cid!=p2.cid
                                           WHERE p1.chain_id=$1::BIGINT AND p1.end_block<=$2::BIGINT AND p1.end_block <> -1::BIGINT
+                                          AND p1.provider_id=$3::BIGINT AND p1.pending_unpin
+                                          AND NOT EXISTS (SELECT 1 FROM event_update_valid_block e
+                                              WHERE e.chain_id=p1.chain_id AND e.provider_id=p1.provider_id AND e.cid=p1.cid
+                                              AND (e.end_block>$2::BIGINT OR e.end_block=-1::BIGINT))
                                           GROUP BY p1.chain_id, p1.node, p1.cid, p1.end_block", 
-                                &[&chain_id, &end_block])?;
+                                &[&chain_id, &end_block, &provider_id])?;
 
     let mut v = vec![];
     for r in res {
         v.push(CIDInfo {
-            chain_id: Option::Some(chain_id.clone()),
+            chain_id: Option::Some(chain_id),
+            provider_id: Option::Some(provider_id),
             cid: r.get(0),
             end_block: r.get(1),
             node: r.get(2),
@@ -208,36 +222,42 @@ pub fn get_single_chain_expired_cids(
     Ok(v)
 }
 
+/// Extends pins renewed by a later event, cancelling their pending unpin
 pub fn update_existing_cids_end_block(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     end_block: i64,
 ) -> Result<u64, postgres::Error> {
     client.execute("UPDATE pinned_cids as pc
-                        SET end_block=euvb.end_block
+                        SET end_block=euvb.end_block, pending_unpin=false
                         FROM event_update_valid_block as euvb
                         WHERE euvb.chain_id=pc.chain_id AND pc.cid=euvb.cid AND pc.end_block<euvb.end_block 
+                                AND euvb.provider_id=pc.provider_id AND euvb.provider_id=$3::BIGINT
                                 AND euvb.end_block>$1::BIGINT AND euvb.chain_id=$2::BIGINT;", 
-            &[&end_block, &chain_id])
+            &[&end_block, &chain_id, &provider_id])
 }
 
 pub fn get_new_cids(
     client: &mut postgres::Client,
     chain_id: i64,
+    provider_id: i64,
     end_block: i64,
 ) -> Result<Vec<CIDInfo>, postgres::Error> {
     let r = client.query("SELECT euvb.cid, MAX(euvb.end_block)
                                                 FROM event_update_valid_block as euvb
                                                 LEFT JOIN pinned_cids pc ON euvb.chain_id=pc.chain_id AND euvb.cid=pc.cid
+                                                    AND euvb.provider_id=pc.provider_id
                                                 WHERE (euvb.end_block>$1::BIGINT OR euvb.end_block= -1::BIGINT) 
-                                                AND euvb.chain_id=$2::BIGINT 
+                                                AND euvb.chain_id=$2::BIGINT AND euvb.provider_id=$3::BIGINT
                                                 AND pc.cid IS NULL
                                                 GROUP BY euvb.cid;", 
-                                                        &[&end_block, &chain_id])?;
+                                                        &[&end_block, &chain_id, &provider_id])?;
     let mut rows = vec![];
     for row in r {
         rows.push(CIDInfo {
-            chain_id: Option::Some(chain_id.clone()),
+            chain_id: Option::Some(chain_id),
+            provider_id: Option::Some(provider_id),
             cid: row.get(0),
             end_block: row.get(1),
             node: Option::None,
@@ -265,7 +285,7 @@ pub fn get_failed_cids(
     end_block: i64,
 ) -> Result<Vec<CIDInfo>, postgres::Error> {
     let r = client.query(
-        "SELECT node, cid, end_block
+        "SELECT node, cid, end_block, provider_id
                                                 FROM failed_pins
                                                 WHERE end_block>$1::BIGINT AND chain_id=$2::BIGINT",
         &[&end_block, &chain_id],
@@ -273,7 +293,8 @@ pub fn get_failed_cids(
     let mut rows = vec![];
     for row in r {
         rows.push(CIDInfo {
-            chain_id: Option::Some(chain_id.clone()),
+            chain_id: Option::Some(chain_id),
+            provider_id: row.get(3),
             node: row.get(0),
             cid: row.get(1),
             end_block: row.get(2),
@@ -283,3 +304,741 @@ pub fn get_failed_cids(
     }
     Ok(rows)
 }
+
+pub fn add_drained_node(client: &mut postgres::Client, node: &str) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO drained_nodes (node) VALUES ($1::TEXT) ON CONFLICT (node) DO NOTHING",
+        &[&node],
+    )
+}
+
+pub fn delete_drained_node(client: &mut postgres::Client, node: &str) -> Result<u64, postgres::Error> {
+    client.execute("DELETE FROM drained_nodes WHERE node=$1::TEXT", &[&node])
+}
+
+pub fn get_drained_nodes(client: &mut postgres::Client) -> Result<Vec<String>, postgres::Error> {
+    let r = client.query("SELECT node FROM drained_nodes", &[])?;
+    Ok(r.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// Deletes the expired nonces and adds `nonce`, returns `false` if `max_pending` are still valid.
+pub fn add_auth_nonce(
+    client: &mut postgres::Client,
+    nonce: &str,
+    ttl_sec: i64,
+    max_pending: i64,
+) -> Result<bool, postgres::Error> {
+    client.execute(
+        "DELETE FROM auth_nonces WHERE created_at <= now() - ($1::BIGINT * interval '1 second')",
+        &[&ttl_sec],
+    )?;
+    let r = client.execute(
+        "INSERT INTO auth_nonces (nonce)
+                    SELECT $1::TEXT WHERE (SELECT count(*) FROM auth_nonces) < $2::BIGINT",
+        &[&nonce, &max_pending],
+    )?;
+    Ok(r > 0)
+}
+
+/// Deletes the nonce, returns `true` if it existed and was issued less than `ttl_sec` seconds ago.
+pub fn use_auth_nonce(
+    client: &mut postgres::Client,
+    nonce: &str,
+    ttl_sec: i64,
+) -> Result<bool, postgres::Error> {
+    let r = client.execute(
+        "DELETE FROM auth_nonces
+                    WHERE nonce=$1::TEXT AND created_at > now() - ($2::BIGINT * interval '1 second')",
+        &[&nonce, &ttl_sec],
+    )?;
+    client.execute(
+        "DELETE FROM auth_nonces WHERE created_at <= now() - ($1::BIGINT * interval '1 second')",
+        &[&ttl_sec],
+    )?;
+    Ok(r > 0)
+}
+
+pub fn add_session(
+    client: &mut postgres::Client,
+    token: &str,
+    address: &str,
+    chain_id: i64,
+    scopes: &[String],
+    ttl_sec: i64,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO sessions (token, address, chain_id, scopes, expires_at)
+                    VALUES ($1::TEXT, LOWER($2::TEXT), $3::BIGINT, $4::TEXT[], now() + ($5::BIGINT * interval '1 second'))",
+        &[&token, &address, &chain_id, &scopes, &ttl_sec],
+    )
+}
+
+/// Returns `(address, chain_id, scopes)` of a non expired session.
+pub fn get_session(
+    client: &mut postgres::Client,
+    token: &str,
+) -> Result<Option<(String, i64, Vec<String>)>, postgres::Error> {
+    let r = client.query_opt(
+        "SELECT address, chain_id, scopes FROM sessions WHERE token=$1::TEXT AND expires_at > now()",
+        &[&token],
+    )?;
+    Ok(r.map(|row| (row.get(0), row.get(1), row.get(2))))
+}
+
+pub fn delete_expired_sessions(client: &mut postgres::Client) -> Result<u64, postgres::Error> {
+    client.execute("DELETE FROM sessions WHERE expires_at <= now()", &[])
+}
+
+pub fn delete_failed_pin(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+    node: &str,
+    cid: &str,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "DELETE FROM failed_pins
+                    WHERE chain_id=$1::BIGINT AND node=$2::TEXT AND cid=$3::TEXT AND provider_id=$4::BIGINT",
+        &[&chain_id, &node, &cid, &provider_id],
+    )
+}
+
+/// Pinned and failed pins of a CID on all nodes and chains
+pub fn get_cid_pins(client: &mut postgres::Client, cid: &str) -> Result<Vec<NodePin>, postgres::Error> {
+    let r = client.query(
+        "SELECT chain_id, node, cid, end_block, true, provider_id FROM pinned_cids WHERE cid=$1::TEXT
+                    UNION ALL
+                    SELECT chain_id, node, cid, end_block, false, provider_id FROM failed_pins WHERE cid=$1::TEXT
+                    ORDER BY 1, 6, 2",
+        &[&cid],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| NodePin {
+            chain_id: row.get(0),
+            node: row.get(1),
+            cid: row.get(2),
+            end_block: row.get(3),
+            pinned: row.get(4),
+            provider_id: row.get(5),
+        })
+        .collect())
+}
+
+/// CIDs pinned on `node`, optionally narrowed down to a chain and/or a CID
+pub fn get_node_cids(
+    client: &mut postgres::Client,
+    node: &str,
+    chain_id: Option<i64>,
+    cid: Option<String>,
+) -> Result<Vec<CIDInfo>, postgres::Error> {
+    let r = client.query(
+        "SELECT chain_id, cid, end_block, provider_id
+                    FROM pinned_cids
+                    WHERE node=$1::TEXT AND ($2::BIGINT IS NULL OR chain_id=$2::BIGINT)
+                    AND ($3::TEXT IS NULL OR cid=$3::TEXT)",
+        &[&node, &chain_id, &cid],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| CIDInfo {
+            chain_id: row.get(0),
+            provider_id: row.get(3),
+            cid: row.get(1),
+            end_block: row.get(2),
+            node: Option::Some(node.to_owned()),
+            node_login: Option::None,
+            node_pass: Option::None,
+        })
+        .collect())
+}
+
+pub fn add_history_event(
+    client: &mut postgres::Client,
+    event: &HistoryEvent,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO monitoring_history (chain_id, kind, event, ts, update_block, duration_ms, count)
+                    VALUES ($1::BIGINT, $2::TEXT, $3::TEXT, $4::BIGINT, $5::BIGINT, $6::BIGINT, $7::BIGINT)",
+        &[
+            &event.chain_id,
+            &event.kind,
+            &event.event,
+            &event.ts,
+            &event.update_block,
+            &event.duration_ms,
+            &event.count,
+        ],
+    )
+}
+
+pub fn delete_history_before(client: &mut postgres::Client, ts: i64) -> Result<u64, postgres::Error> {
+    client.execute("DELETE FROM monitoring_history WHERE ts<$1::BIGINT", &[&ts])
+}
+
+pub fn get_cid_donors(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    cid: &str,
+) -> Result<Vec<String>, postgres::Error> {
+    let r = client.query(
+        "SELECT DISTINCT donor FROM event_update_valid_block WHERE chain_id=$1::BIGINT AND cid=$2::TEXT",
+        &[&chain_id, &cid],
+    )?;
+    Ok(r.into_iter().map(|row| row.get(0)).collect())
+}
+
+/// Pinned CIDs with `from_block < end_block <= to_block`, only the ones paid by `donor` if set
+pub fn get_expiring_cids(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    from_block: i64,
+    to_block: i64,
+    donor: Option<String>,
+) -> Result<Vec<CIDInfo>, postgres::Error> {
+    let r = client.query(
+        "SELECT p.cid, MAX(p.end_block) FROM pinned_cids p
+                    WHERE p.chain_id=$1::BIGINT AND p.end_block <> -1::BIGINT
+                    AND ($4::TEXT IS NULL OR EXISTS (SELECT 1 FROM event_update_valid_block e
+                        WHERE e.chain_id=p.chain_id AND e.cid=p.cid AND e.donor=LOWER($4::TEXT)))
+                    GROUP BY p.cid
+                    HAVING MAX(p.end_block)>$2::BIGINT AND MAX(p.end_block)<=$3::BIGINT
+                    ORDER BY 2, 1",
+        &[&chain_id, &from_block, &to_block, &donor],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| CIDInfo {
+            chain_id: Option::Some(chain_id),
+            provider_id: Option::None,
+            cid: row.get(0),
+            end_block: row.get(1),
+            node: Option::None,
+            node_login: Option::None,
+            node_pass: Option::None,
+        })
+        .collect())
+}
+
+pub fn add_webhook_delivery(
+    client: &mut postgres::Client,
+    url: &str,
+    subscription: &str,
+    event: &str,
+    payload: &str,
+    now: i64,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO webhook_outbox (url, subscription, event, payload, next_attempt_at)
+                    VALUES ($1::TEXT, $2::TEXT, $3::TEXT, $4::TEXT, $5::BIGINT)",
+        &[&url, &subscription, &event, &payload, &now],
+    )
+}
+
+/// Takes up to `limit` due deliveries and hides them from other workers until `lease_until`
+pub fn claim_webhook_deliveries(
+    client: &mut postgres::Client,
+    now: i64,
+    lease_until: i64,
+    max_attempts: i32,
+    limit: i64,
+) -> Result<Vec<WebhookDelivery>, postgres::Error> {
+    let r = client.query(
+        "UPDATE webhook_outbox SET next_attempt_at=$2::BIGINT
+                    WHERE id IN (
+                        SELECT id FROM webhook_outbox
+                        WHERE delivered_at IS NULL AND next_attempt_at<=$1::BIGINT AND attempts<$3::INT
+                        ORDER BY id LIMIT $4::BIGINT
+                        FOR UPDATE SKIP LOCKED
+                    )
+                    RETURNING id, url, subscription, event, payload, attempts",
+        &[&now, &lease_until, &max_attempts, &limit],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| WebhookDelivery {
+            id: row.get(0),
+            url: row.get(1),
+            subscription: row.get(2),
+            event: row.get(3),
+            payload: row.get(4),
+            attempts: row.get(5),
+        })
+        .collect())
+}
+
+pub fn set_webhook_delivered(client: &mut postgres::Client, id: i64, now: i64) -> Result<u64, postgres::Error> {
+    client.execute(
+        "UPDATE webhook_outbox SET delivered_at=$2::BIGINT, attempts=attempts+1, last_error=NULL WHERE id=$1::BIGINT",
+        &[&id, &now],
+    )
+}
+
+pub fn set_webhook_failed(
+    client: &mut postgres::Client,
+    id: i64,
+    next_attempt_at: i64,
+    error: &str,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "UPDATE webhook_outbox SET attempts=attempts+1, next_attempt_at=$2::BIGINT, last_error=$3::TEXT
+                    WHERE id=$1::BIGINT",
+        &[&id, &next_attempt_at, &error],
+    )
+}
+
+/// Gives up on a delivery, it is not claimed again
+pub fn set_webhook_abandoned(
+    client: &mut postgres::Client,
+    id: i64,
+    max_attempts: i32,
+    error: &str,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "UPDATE webhook_outbox SET attempts=$2::INT, last_error=$3::TEXT WHERE id=$1::BIGINT",
+        &[&id, &max_attempts, &error],
+    )
+}
+
+pub fn delete_delivered_webhooks(client: &mut postgres::Client, before: i64) -> Result<u64, postgres::Error> {
+    client.execute(
+        "DELETE FROM webhook_outbox WHERE delivered_at<$1::BIGINT",
+        &[&before],
+    )
+}
+
+pub fn add_rejected_event(
+    client: &mut postgres::Client,
+    event: &RejectedEvent,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO rejected_events (chain_id, event, update_block, tx_hash, data, reason, ts)
+                    VALUES ($1::BIGINT, $2::TEXT, $3::BIGINT, $4::TEXT, $5::TEXT, $6::TEXT, $7::BIGINT)",
+        &[
+            &event.chain_id,
+            &event.event,
+            &event.update_block,
+            &event.tx_hash,
+            &event.data,
+            &event.reason,
+            &event.ts,
+        ],
+    )
+}
+
+pub fn add_quarantined_log(
+    client: &mut postgres::Client,
+    q: &QuarantinedLog,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO quarantined_logs (chain_id, event, block_number, tx_hash, topics, data, log, error, ts)
+                    VALUES ($1::BIGINT, $2::TEXT, $3::BIGINT, $4::TEXT, $5::TEXT[], $6::TEXT, $7::JSONB, $8::TEXT, $9::BIGINT)",
+        &[
+            &q.chain_id,
+            &q.event,
+            &q.block_number,
+            &q.tx_hash,
+            &q.topics,
+            &q.data,
+            &q.log,
+            &q.error,
+            &q.ts,
+        ],
+    )
+}
+
+/// Quarantined logs of a chain, only the ones not reprocessed yet unless `all`
+pub fn get_quarantined_logs(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    id: Option<i64>,
+    all: bool,
+) -> Result<Vec<QuarantinedLog>, postgres::Error> {
+    let res = client.query(
+        "SELECT id, chain_id, event, block_number, tx_hash, topics, data, log, error, ts, reprocessed_at
+                    FROM quarantined_logs
+                    WHERE chain_id=$1::BIGINT
+                        AND ($2::BIGINT IS NULL OR id=$2::BIGINT)
+                        AND ($3::BOOLEAN OR reprocessed_at IS NULL)
+                    ORDER BY id",
+        &[&chain_id, &id, &all],
+    )?;
+    Ok(res
+        .iter()
+        .map(|r| QuarantinedLog {
+            id: r.get(0),
+            chain_id: r.get(1),
+            event: r.get(2),
+            block_number: r.get(3),
+            tx_hash: r.get(4),
+            topics: r.get(5),
+            data: r.get(6),
+            log: r.get(7),
+            error: r.get(8),
+            ts: r.get(9),
+            reprocessed_at: r.get(10),
+        })
+        .collect())
+}
+
+pub fn set_quarantined_reprocessed(
+    client: &mut postgres::Client,
+    id: i64,
+    now: i64,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "UPDATE quarantined_logs SET reprocessed_at=$2::BIGINT WHERE id=$1::BIGINT",
+        &[&id, &now],
+    )
+}
+
+pub fn set_quarantined_error(
+    client: &mut postgres::Client,
+    id: i64,
+    error: &str,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "UPDATE quarantined_logs SET error=$2::TEXT WHERE id=$1::BIGINT",
+        &[&id, &error],
+    )
+}
+
+pub fn has_cid_size(client: &mut postgres::Client, cid: &str) -> Result<bool, postgres::Error> {
+    let r = client.query_opt("SELECT 1 FROM cid_sizes WHERE cid=$1::TEXT", &[&cid])?;
+    Ok(r.is_some())
+}
+
+pub fn set_cid_size(
+    client: &mut postgres::Client,
+    cid: &str,
+    size: i64,
+    num_blocks: i64,
+    now: i64,
+) -> Result<u64, postgres::Error> {
+    client.execute(
+        "INSERT INTO cid_sizes (cid, size, num_blocks, updated_at)
+                    VALUES ($1::TEXT, $2::BIGINT, $3::BIGINT, $4::BIGINT)
+                    ON CONFLICT (cid) DO UPDATE
+                    SET size=EXCLUDED.size, num_blocks=EXCLUDED.num_blocks, updated_at=EXCLUDED.updated_at",
+        &[&cid, &size, &num_blocks, &now],
+    )
+}
+
+/// Pinned CIDs without a size, with one of the nodes they are pinned on
+pub fn get_unsized_cids(client: &mut postgres::Client, limit: i64) -> Result<Vec<CIDInfo>, postgres::Error> {
+    let r = client.query(
+        "SELECT DISTINCT ON (p.cid) p.cid, p.node
+                    FROM pinned_cids p LEFT JOIN cid_sizes s ON s.cid=p.cid
+                    WHERE s.cid IS NULL
+                    ORDER BY p.cid LIMIT $1::BIGINT",
+        &[&limit],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| CIDInfo {
+            chain_id: Option::None,
+            provider_id: Option::None,
+            cid: row.get(0),
+            end_block: Option::None,
+            node: row.get(1),
+            node_login: Option::None,
+            node_pass: Option::None,
+        })
+        .collect())
+}
+
+pub fn get_chain_storage(client: &mut postgres::Client) -> Result<Vec<ChainStorage>, postgres::Error> {
+    let r = client.query(
+        "SELECT p.chain_id, COUNT(*), COALESCE(SUM(s.size), 0)::BIGINT,
+                    COALESCE(SUM(s.num_blocks), 0)::BIGINT, COUNT(*) FILTER (WHERE s.cid IS NULL)
+                    FROM (SELECT DISTINCT chain_id, cid FROM pinned_cids) p
+                    LEFT JOIN cid_sizes s ON s.cid=p.cid
+                    GROUP BY p.chain_id ORDER BY 3 DESC, 1",
+        &[],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| ChainStorage {
+            chain_id: row.get(0),
+            cids: row.get(1),
+            size: row.get(2),
+            num_blocks: row.get(3),
+            unsized_cids: row.get(4),
+        })
+        .collect())
+}
+
+pub fn get_node_storage(client: &mut postgres::Client) -> Result<Vec<NodeStorage>, postgres::Error> {
+    let r = client.query(
+        "SELECT p.node, COUNT(*), COALESCE(SUM(s.size), 0)::BIGINT,
+                    COALESCE(SUM(s.num_blocks), 0)::BIGINT, COUNT(*) FILTER (WHERE s.cid IS NULL)
+                    FROM (SELECT DISTINCT node, cid FROM pinned_cids) p
+                    LEFT JOIN cid_sizes s ON s.cid=p.cid
+                    GROUP BY p.node ORDER BY 3 DESC, 1",
+        &[],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| NodeStorage {
+            node: row.get(0),
+            cids: row.get(1),
+            size: row.get(2),
+            num_blocks: row.get(3),
+            unsized_cids: row.get(4),
+        })
+        .collect())
+}
+
+/// Pinned CIDs per donor, a CID paid for by several donors counts for each of them
+pub fn get_donor_storage(
+    client: &mut postgres::Client,
+    chain_id: Option<i64>,
+    donor: Option<String>,
+) -> Result<Vec<DonorStorage>, postgres::Error> {
+    let r = client.query(
+        "SELECT d.chain_id, d.donor, COUNT(*), COALESCE(SUM(s.size), 0)::BIGINT,
+                    COALESCE(SUM(s.num_blocks), 0)::BIGINT, COUNT(*) FILTER (WHERE s.cid IS NULL)
+                    FROM (SELECT DISTINCT e.chain_id, e.donor, e.cid FROM event_update_valid_block e
+                        WHERE ($1::BIGINT IS NULL OR e.chain_id=$1::BIGINT)
+                        AND ($2::TEXT IS NULL OR e.donor=$2::TEXT)
+                        AND EXISTS (SELECT 1 FROM pinned_cids p WHERE p.chain_id=e.chain_id AND p.cid=e.cid)) d
+                    LEFT JOIN cid_sizes s ON s.cid=d.cid
+                    GROUP BY d.chain_id, d.donor ORDER BY 4 DESC, 1, 2 LIMIT 1000",
+        &[&chain_id, &donor],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| DonorStorage {
+            chain_id: row.get(0),
+            donor: row.get(1),
+            cids: row.get(2),
+            size: row.get(3),
+            num_blocks: row.get(4),
+            unsized_cids: row.get(5),
+        })
+        .collect())
+}
+
+/// Latest `block_price_gwei` of a provider, `None` if it was never added
+pub fn get_provider_block_price(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+) -> Result<Option<i64>, postgres::Error> {
+    let r = client.query_opt(
+        "SELECT block_price_gwei FROM event_add_provider
+                    WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT
+                    ORDER BY update_block DESC LIMIT 1",
+        &[&chain_id, &provider_id],
+    )?;
+    Ok(r.map(|row| row.get(0)))
+}
+
+/// `block_price_gwei` of a provider in effect at `block`, from the last price setting event up to it
+pub fn get_provider_block_price_at(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+    block: i64,
+) -> Result<Option<i64>, postgres::Error> {
+    let r = client.query_opt(
+        "SELECT block_price_gwei FROM provider_events
+                    WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT AND block_price_gwei IS NOT NULL
+                    AND update_block <= $3::BIGINT
+                    ORDER BY update_block DESC, log_index DESC LIMIT 1",
+        &[&chain_id, &provider_id, &block],
+    )?;
+    Ok(r.map(|row| row.get(0)))
+}
+
+/// Furthest expiring `end_block` a CID had before `update_block`, ignoring the ones never expiring
+pub fn get_cid_end_block_before(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+    cid: &str,
+    update_block: i64,
+) -> Result<Option<i64>, postgres::Error> {
+    let r = client.query_one(
+        "SELECT MAX(end_block) FROM event_update_valid_block
+                    WHERE chain_id=$1::BIGINT AND cid=$2::TEXT AND update_block<$3::BIGINT
+                    AND provider_id=$4::BIGINT AND end_block <> -1::BIGINT",
+        &[&chain_id, &cid, &update_block, &provider_id],
+    )?;
+    Ok(r.get(0))
+}
+
+/// `unverified` events of a chain whose provider has a block price indexed by their `update_block`
+pub fn get_unverified_payments(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    limit: i64,
+) -> Result<Vec<EventUpdateValidBlock>, postgres::Error> {
+    let r = client.query(
+        "SELECT e.chain_id, e.provider_id, e.cid, e.cid_original, e.donor, e.update_block, e.end_block,
+                    e.manual_add, e.tx_hash, e.log_index
+                    FROM event_update_valid_block e
+                    WHERE e.chain_id=$1::BIGINT AND e.payment='unverified' AND e.tx_hash IS NOT NULL
+                    AND EXISTS (SELECT 1 FROM provider_events p
+                        WHERE p.chain_id=e.chain_id AND p.provider_id=e.provider_id
+                        AND p.block_price_gwei IS NOT NULL AND p.update_block<=e.update_block)
+                    ORDER BY e.update_block, e.log_index LIMIT $2::BIGINT",
+        &[&chain_id, &limit],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| EventUpdateValidBlock {
+            chain_id: row.get(0),
+            provider_id: row.get(1),
+            cid: row.get(2),
+            cid_original: row.get(3),
+            donor: row.get(4),
+            update_block: row.get(5),
+            end_block: row.get(6),
+            manual_add: row.get(7),
+            claimed_end_block: Option::None,
+            paid_wei: Option::None,
+            paid_end_block: Option::None,
+            payment: Option::None,
+            tx_hash: row.get(8),
+            log_index: row.get(9),
+        })
+        .collect())
+}
+
+/// Stores the payment of an indexed log verified after the fact. Pins of its CID kept past the
+/// furthest `end_block` of the listing's events, once capped, are lowered to it
+pub fn set_payment(
+    client: &mut postgres::Client,
+    event: &EventUpdateValidBlock,
+) -> Result<u64, postgres::Error> {
+    let mut tx = client.transaction()?;
+    let n = tx.execute(
+        "UPDATE event_update_valid_block
+                    SET end_block=$3::BIGINT, claimed_end_block=$4::BIGINT, paid_wei=$5::TEXT::NUMERIC,
+                        paid_end_block=$6::BIGINT, payment=$7::TEXT
+                    WHERE chain_id=$1::BIGINT AND tx_hash=$2::TEXT AND log_index=$8::BIGINT",
+        &[
+            &event.chain_id,
+            &event.tx_hash,
+            &event.end_block,
+            &event.claimed_end_block,
+            &event.paid_wei,
+            &event.paid_end_block,
+            &event.payment,
+            &event.log_index,
+        ],
+    )?;
+    let furthest: Option<i64> = tx
+        .query_one(
+            "SELECT CASE WHEN BOOL_OR(end_block=-1::BIGINT) THEN -1::BIGINT ELSE MAX(end_block) END
+                    FROM event_update_valid_block
+                    WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT AND cid=$3::TEXT",
+            &[&event.chain_id, &event.provider_id, &event.cid],
+        )?
+        .get(0);
+    if let Some(end_block) = furthest.filter(|b| *b != -1) {
+        let nodes = tx.query(
+            "DELETE FROM pinned_cids
+                        WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT AND cid=$3::TEXT
+                        AND (end_block>$4::BIGINT OR end_block=-1::BIGINT)
+                        RETURNING node",
+            &[&event.chain_id, &event.provider_id, &event.cid, &end_block],
+        )?;
+        for row in nodes {
+            let node: String = row.get(0);
+            tx.execute(
+                "INSERT INTO pinned_cids (chain_id, provider_id, node, cid, end_block)
+                        VALUES ($1::BIGINT, $2::BIGINT, $3::TEXT, $4::TEXT, $5::BIGINT)
+                        ON CONFLICT DO NOTHING",
+                &[&event.chain_id, &event.provider_id, &node, &event.cid, &end_block],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(n)
+}
+
+pub fn get_payment_events(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    payment: &str,
+) -> Result<Vec<PaymentEvent>, postgres::Error> {
+    let r = client.query(
+        "SELECT chain_id, cid, donor, update_block, end_block, claimed_end_block, paid_wei::TEXT,
+                    paid_end_block, payment
+                    FROM event_update_valid_block
+                    WHERE chain_id=$1::BIGINT AND payment=$2::TEXT
+                    ORDER BY update_block DESC LIMIT 1000",
+        &[&chain_id, &payment],
+    )?;
+    Ok(r.into_iter()
+        .map(|row| PaymentEvent {
+            chain_id: row.get(0),
+            cid: row.get(1),
+            donor: row.get(2),
+            update_block: row.get(3),
+            end_block: row.get(4),
+            claimed_end_block: row.get(5),
+            paid_wei: row.get(6),
+            paid_end_block: row.get(7),
+            payment: row.get(8),
+        })
+        .collect())
+}
+
+/// Pinned CIDs, their donors and bytes on a chain
+pub fn get_active_stats(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+) -> Result<(i64, i64, i64), postgres::Error> {
+    let r = client.query_one(
+        "SELECT (SELECT COUNT(DISTINCT cid) FROM pinned_cids WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT),
+                    (SELECT COUNT(DISTINCT e.donor) FROM event_update_valid_block e
+                        WHERE e.chain_id=$1::BIGINT AND e.provider_id=$2::BIGINT
+                        AND EXISTS (SELECT 1 FROM pinned_cids p
+                            WHERE p.chain_id=e.chain_id AND p.provider_id=e.provider_id AND p.cid=e.cid)),
+                    (SELECT COALESCE(SUM(s.size), 0)::BIGINT
+                        FROM (SELECT DISTINCT cid FROM pinned_cids
+                            WHERE chain_id=$1::BIGINT AND provider_id=$2::BIGINT) p
+                        JOIN cid_sizes s ON s.cid=p.cid)",
+        &[&chain_id, &provider_id],
+    )?;
+    Ok((r.get(0), r.get(1), r.get(2)))
+}
+
+/// See `StatsWindow`, manual adds and CIDs never expiring are not sold
+pub fn get_window_stats(
+    client: &mut postgres::Client,
+    chain_id: i64,
+    provider_id: i64,
+    window_sec: u64,
+    from_block: i64,
+) -> Result<StatsWindow, postgres::Error> {
+    let r = client.query_one(
+        "WITH e AS (
+                        SELECT cid, donor, update_block, end_block, paid_wei,
+                        MAX(end_block) OVER (PARTITION BY cid ORDER BY update_block
+                            ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING) AS prev_end_block
+                        FROM event_update_valid_block
+                        WHERE chain_id=$1::BIGINT AND provider_id=$3::BIGINT AND NOT COALESCE(manual_add, false)
+                        AND end_block <> -1::BIGINT
+                    ), w AS (
+                        SELECT cid, donor, paid_wei,
+                        GREATEST(end_block - GREATEST(update_block, COALESCE(prev_end_block, update_block)), 0) AS blocks,
+                        COALESCE((SELECT pe.block_price_gwei FROM provider_events pe
+                            WHERE pe.chain_id=$1::BIGINT AND pe.provider_id=$3::BIGINT
+                            AND pe.block_price_gwei IS NOT NULL AND pe.update_block <= e.update_block
+                            ORDER BY pe.update_block DESC, pe.log_index DESC LIMIT 1), 0) AS price
+                        FROM e WHERE update_block>=$2::BIGINT
+                    )
+                    SELECT COUNT(*), COUNT(DISTINCT cid), COUNT(DISTINCT donor),
+                    COALESCE(SUM(blocks), 0)::BIGINT,
+                    (COALESCE(SUM(blocks::NUMERIC * price), 0) * 1000000000)::TEXT,
+                    COALESCE(SUM(paid_wei), 0)::TEXT
+                    FROM w",
+        &[&chain_id, &from_block, &provider_id],
+    )?;
+    Ok(StatsWindow {
+        window_sec,
+        from_block,
+        events: r.get(0),
+        cids: r.get(1),
+        donors: r.get(2),
+        blocks_sold: r.get(3),
+        revenue_wei: r.get(4),
+        paid_wei: r.get(5),
+    })
+}
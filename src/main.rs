@@ -4,7 +4,7 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::sync::{Arc, Mutex};
 use types::State;
@@ -19,50 +19,132 @@ mod yaml_parser;
 async fn main() {
     pretty_env_logger::init();
     let args: Vec<String> = env::args().collect();
+    if args.len() == 3 && args[1].eq("check-config") {
+        match yaml_parser::load_conf(&args[2]) {
+            Ok(_) => println!("'{}' is valid", &args[2]),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     if args.len().ne(&2) {
-        error!("Please specify the path to <config.yml> file");
+        error!("Usage: hosq <config.yml> | hosq check-config <config.yml>");
         return;
     }
 
-    let conf = yaml_parser::get_conf(&args[1]);
+    let conf = match yaml_parser::load_conf(&args[1]) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
     //   let pre_release = conf.pre_release;
-    let nodes = Arc::new(conf.ipfs_nodes.unwrap());
+    let only_api = !matches!(conf.only_api, Some(false));
+    let config_service = services::config_watcher::ConfigService {
+        path: args[1].clone(),
+        conf: conf.clone(),
+        only_api,
+    };
+    let nodes = Arc::new(Mutex::new(Arc::new(conf.ipfs_nodes.unwrap())));
 
     let providers_service = services::providers::Providers {};
     let providers_manage = providers_service
         .get_providers(conf.providers.unwrap())
         .await
         .unwrap();
-    let providers_manage = Arc::new(providers_manage);
+    let providers_manage = Arc::new(Mutex::new(Arc::new(providers_manage)));
 
     let ipfs_watcher = services::ipfs_watcher::IPFSService {
         retry_failed_cids_sec: conf.retry_failed_cids_sec,
         update_nodes_sec: conf.update_nodes_sec,
+        node_capacity_sec: conf.node_capacity_sec.unwrap_or(60),
     };
 
+    services::webhooks::set_subscriptions(conf.webhooks.clone().unwrap_or_default());
+
     let r = rocket::build()
         .mount(
             "/v0",
             routes![
                 routes::proxy::upload,
                 routes::cors::all_options,
+                routes::auth::nonce,
+                routes::auth::login,
+                routes::auth::admin_token,
                 routes::handlers::get_cids,
                 routes::handlers::get_providers,
                 routes::handlers::get_provider,
+                routes::handlers::provider_stats,
+                routes::handlers::provider_history,
                 routes::handlers::is_pinned,
                 routes::handlers::cid_info,
-                // routes::handlers::pin_cid,
+                routes::handlers::expiring_cids,
+                routes::handlers::pin_cid,
                 routes::handlers::monitoring,
+                routes::handlers::monitoring_nodes,
+                routes::handlers::monitoring_history,
+                routes::events::stream,
+                routes::admin::pins,
+                routes::admin::force_pin,
+                routes::admin::force_unpin,
+                routes::admin::requeue_failed,
+                routes::admin::pause_chain,
+                routes::admin::resume_chain,
+                routes::admin::resync_chain,
+                routes::admin::quarantined,
+                routes::admin::reprocess_quarantined,
+                routes::admin::drain_node,
+                routes::admin::undrain_node,
+                routes::admin::payments,
+                routes::admin::storage_chains,
+                routes::admin::storage_nodes,
+                routes::admin::storage_donors,
+                routes::docs::openapi,
+                routes::docs::docs,
+            ],
+        )
+        .mount(
+            "/",
+            routes![
+                routes::proxy::ipfs,
+                routes::handlers::metrics,
+                routes::health::healthz,
+                routes::health::readyz,
+            ],
+        )
+        .register(
+            "/",
+            catchers![
+                routes::errors::not_found,
+                routes::errors::unprocessable_entity,
+                routes::errors::internal_error,
+                routes::errors::default,
             ],
         )
-        .mount("/", routes![routes::proxy::ipfs])
         .attach(types::DbConn::fairing())
         .attach(routes::cors::CORS)
+        .attach(config_service)
+        .attach(services::webhooks::WebhookService {
+            expiring_sec: conf.webhook_expiring_sec.unwrap_or(86400),
+        })
+        .attach(services::history::HistoryService {
+            retention_days: conf.monitoring_retention_days.unwrap_or(30),
+        })
         .manage(State {
             nodes,
             providers: providers_manage,
             admin_secret: conf.admin_secret,
+            session_ttl_sec: conf.session_ttl_sec.unwrap_or(86400),
+            admin_token_max_ttl_sec: conf.admin_token_max_ttl_sec.unwrap_or(3600),
+            siwe_domain: conf.siwe_domain,
             monitoring: Arc::new(Mutex::new(HashMap::new())),
+            drained_nodes: Arc::new(Mutex::new(HashSet::new())),
+            node_capacity: Arc::new(Mutex::new(HashMap::new())),
+            readiness: conf.readiness.unwrap_or(types::config::Readiness::default()),
+            only_api,
         });
 
     match conf.only_api {
@@ -0,0 +1,721 @@
+use std::{collections::HashSet, sync::Arc};
+
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    response::status::Custom,
+    serde::json::Json,
+    tokio, Request, State,
+};
+use web3::types::Log;
+
+use crate::db;
+use crate::routes::{
+    auth::AuthError,
+    errors::{ApiError, ApiResult},
+    handlers::canonical_cid,
+};
+use crate::services::{contract_watcher, ipfs_watcher};
+use crate::types::{
+    self,
+    api::{
+        DrainResponse, ForcePinResponse, PauseResponse, ReprocessResponse, RequeueResponse,
+        ResyncResponse, UndrainResponse, UnpinResponse,
+    },
+    auth::{AdminToken, SCOPE_ADMIN},
+    config::IPFSNode,
+    db::{ChainStorage, DonorStorage, NodePin, NodeStorage, PaymentEvent, QuarantinedLog},
+    CIDInfo, DbConn, Web3Node,
+};
+
+/// Admin token carrying the `admin` scope
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = AuthError;
+
+    async fn from_request(r: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match r.guard::<AdminToken>().await {
+            Outcome::Success(t) if t.has_scope(SCOPE_ADMIN) => Outcome::Success(Admin),
+            Outcome::Success(_) => Outcome::Error((Status::Forbidden, AuthError::MissingScope)),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+fn get_provider(state: &types::State, chain_id: i64) -> Option<Web3Node> {
+    state.providers().iter().find(|p| p.chain_id == chain_id).cloned()
+}
+
+fn find_node(state: &types::State, node: &str) -> Option<IPFSNode> {
+    state.nodes().iter().find(|n| n.api_url.eq(node)).cloned()
+}
+
+/// List pinned and failed pins of a CID on every node.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Pins of the CID", body = [NodePin]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/admin/pins?<cid>")]
+pub async fn pins(_admin: Admin, cid: String, psql: DbConn) -> ApiResult<Vec<NodePin>> {
+    let cid = canonical_cid(&cid)?;
+    match psql.run(move |client| db::get_cid_pins(client, &cid)).await {
+        Ok::<Vec<NodePin>, postgres::Error>(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting CID pins > {}", e);
+            Err(ApiError::internal("failed to collect CID pins"))
+        }
+    }
+}
+
+/// Pin a CID to a node regardless of the indexed events.
+///
+/// `end_block` defaults to `-1` (never expires), `provider_id` to the chain's first listing
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Pin attempted", body = ForcePinResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/pin?<cid>&<node>&<chain_id>&<end_block>&<provider_id>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn force_pin(
+    _admin: Admin,
+    cid: String,
+    node: String,
+    chain_id: i64,
+    end_block: Option<i64>,
+    provider_id: Option<i64>,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<ForcePinResponse> {
+    let cid = canonical_cid(&cid)?;
+    let target = match find_node(state, &node) {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request(format!("unknown node '{}'", node))),
+    };
+    let provider_id = match get_provider(state, chain_id) {
+        Some(p) => match provider_id {
+            Some(id) => p.listing(id).map(|l| l.provider_id),
+            None => p.listings.first().map(|l| l.provider_id),
+        },
+        None => return Err(ApiError::bad_request(format!("unknown chain '{}'", chain_id))),
+    };
+    let provider_id = match provider_id {
+        Some(v) => v,
+        None => {
+            return Err(ApiError::bad_request(format!(
+                "the provider is not served on chain '{}'",
+                chain_id
+            )))
+        }
+    };
+    warn!(
+        "CHAIN '{}' > ADMIN force pin '{}' to NODE '{}' for provider '{}'",
+        chain_id, cid, node, provider_id
+    );
+    let pinned = ipfs_watcher::pin_cid_to_node_now(
+        Arc::new(psql),
+        CIDInfo {
+            chain_id: Option::Some(chain_id),
+            provider_id: Option::Some(provider_id),
+            cid: Option::Some(cid),
+            end_block: Option::Some(end_block.unwrap_or(-1)),
+            node: Option::Some(node),
+            node_login: target.login,
+            node_pass: target.password,
+        },
+        false,
+    )
+    .await;
+    Ok(Json(ForcePinResponse { pinned }))
+}
+
+/// Unpin a CID from a node.
+///
+/// The CID will be pinned again by the watcher if it is still paid for and no other node has it
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Pins removed", body = UnpinResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/unpin?<cid>&<node>&<chain_id>")]
+pub async fn force_unpin(
+    _admin: Admin,
+    cid: String,
+    node: String,
+    chain_id: i64,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<UnpinResponse> {
+    let cid = canonical_cid(&cid)?;
+    warn!("CHAIN '{}' > ADMIN force unpin '{}' from NODE '{}'", chain_id, cid, node);
+    let psql = Arc::new(psql);
+    let n = node.clone();
+    let pins = match psql
+        .run(move |client| db::get_node_cids(client, &n, Option::Some(chain_id), Option::Some(cid)))
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Error collecting node CIDs > {}", e);
+            return Err(ApiError::internal("failed to collect node CIDs"));
+        }
+    };
+    // nodes removed from the config can still be unpinned from, without credentials
+    let target = find_node(state, &node);
+    let mut unpinned = 0;
+    for mut c in pins {
+        if let Some(t) = &target {
+            c.node_login = t.login.clone();
+            c.node_pass = t.password.clone();
+        }
+        if ipfs_watcher::unpin_cid_from_node_now(psql.clone(), c).await {
+            unpinned += 1;
+        }
+    }
+    Ok(Json(UnpinResponse { unpinned }))
+}
+
+/// Retry failed pins of a chain now, optionally only the ones of `node`.
+///
+/// Pins of drained, full or no longer configured nodes are skipped
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Failed pins queued", body = RequeueResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+        (status = 503, description = "Not ready to serve the request", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/failed/requeue?<chain_id>&<node>")]
+pub async fn requeue_failed(
+    _admin: Admin,
+    chain_id: i64,
+    node: Option<String>,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<RequeueResponse> {
+    let bn = match get_provider(state, chain_id) {
+        Some(p) => {
+            let bn = { *p.latest_block.lock().unwrap() };
+            match bn {
+                Some(v) => v,
+                None => return Err(ApiError::unavailable("latest block is not known yet")),
+            }
+        }
+        None => return Err(ApiError::bad_request(format!("unknown chain '{}'", chain_id))),
+    };
+    let psql = Arc::new(psql);
+    let failed = match psql.run(move |client| db::get_failed_cids(client, chain_id, bn)).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Error collecting failed CIDs > {}", e);
+            return Err(ApiError::internal("failed to collect failed CIDs"));
+        }
+    };
+    // same nodes as the retry service, credentials from the live config
+    let nodes = ipfs_watcher::active_nodes(&state.nodes(), &state.drained_nodes, &state.node_capacity);
+    let mut requeued = 0;
+    for mut c in failed {
+        if node.is_some() && !c.node.eq(&node) {
+            continue;
+        }
+        let target = match nodes.iter().find(|n| c.node.as_ref() == Some(&n.api_url)) {
+            Some(t) => t,
+            None => continue,
+        };
+        c.node_login = target.login.clone();
+        c.node_pass = target.password.clone();
+        ipfs_watcher::pin_cid_to_node(psql.clone(), c, false).await;
+        requeued += 1;
+    }
+    info!("CHAIN '{}' > ADMIN requeued '{}' failed pins", chain_id, requeued);
+    Ok(Json(RequeueResponse { requeued }))
+}
+
+/// Stop indexing and pinning for a chain, the latest block is still tracked.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Chain paused", body = PauseResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 404, description = "Unknown resource", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/chain/<chain_id>/pause")]
+pub async fn pause_chain(
+    _admin: Admin,
+    chain_id: i64,
+    state: &State<types::State>,
+) -> ApiResult<PauseResponse> {
+    set_paused(state, chain_id, true)
+}
+
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Chain resumed", body = PauseResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 404, description = "Unknown resource", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/chain/<chain_id>/resume")]
+pub async fn resume_chain(
+    _admin: Admin,
+    chain_id: i64,
+    state: &State<types::State>,
+) -> ApiResult<PauseResponse> {
+    set_paused(state, chain_id, false)
+}
+
+fn set_paused(state: &types::State, chain_id: i64, paused: bool) -> ApiResult<PauseResponse> {
+    let provider = match get_provider(state, chain_id) {
+        Some(v) => v,
+        None => return Err(ApiError::not_found(format!("unknown chain '{}'", chain_id))),
+    };
+    {
+        *provider.paused.lock().unwrap() = paused;
+    }
+    warn!(
+        "CHAIN '{}' - '{}' > ADMIN set paused to '{}'",
+        provider.chain_name, chain_id, paused
+    );
+    Ok(Json(PauseResponse { paused }))
+}
+
+/// Re-index all contract events of a chain starting at `from_block`.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Resync scheduled", body = ResyncResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 404, description = "Unknown resource", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/chain/<chain_id>/resync?<from_block>")]
+pub async fn resync_chain(
+    _admin: Admin,
+    chain_id: i64,
+    from_block: i64,
+    state: &State<types::State>,
+) -> ApiResult<ResyncResponse> {
+    let provider = match get_provider(state, chain_id) {
+        Some(v) => v,
+        None => return Err(ApiError::not_found(format!("unknown chain '{}'", chain_id))),
+    };
+    {
+        let mut resync = provider.resync_from.lock().unwrap();
+        for event in contract_watcher::EVENTS {
+            resync.insert(event.to_owned(), from_block);
+        }
+    }
+    warn!(
+        "CHAIN '{}' - '{}' > ADMIN resync from block '{}'",
+        provider.chain_name, chain_id, from_block
+    );
+    Ok(Json(ResyncResponse { from_block }))
+}
+
+/// List quarantined logs of a chain, with `all=true` also the reprocessed ones.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Quarantined logs", body = [QuarantinedLog]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/admin/quarantine?<chain_id>&<all>")]
+pub async fn quarantined(
+    _admin: Admin,
+    chain_id: i64,
+    all: Option<bool>,
+    psql: DbConn,
+) -> ApiResult<Vec<QuarantinedLog>> {
+    let all = all.unwrap_or(false);
+    match psql
+        .run(move |client| db::get_quarantined_logs(client, chain_id, Option::None, all))
+        .await
+    {
+        Ok(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting quarantined logs > {}", e);
+            Err(ApiError::internal("failed to collect quarantined logs"))
+        }
+    }
+}
+
+/// Run quarantined logs of a chain through their event handler again, optionally only `id`.
+///
+/// Logs failing again stay quarantined with the new error
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Logs reprocessed", body = ReprocessResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 404, description = "Unknown resource", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/quarantine/reprocess?<chain_id>&<id>")]
+pub async fn reprocess_quarantined(
+    _admin: Admin,
+    chain_id: i64,
+    id: Option<i64>,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<ReprocessResponse> {
+    let provider = match get_provider(state, chain_id) {
+        Some(v) => v,
+        None => return Err(ApiError::not_found(format!("unknown chain '{}'", chain_id))),
+    };
+    let psql = Arc::new(psql);
+    let logs = match psql
+        .run(move |client| db::get_quarantined_logs(client, chain_id, id, false))
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Error collecting quarantined logs > {}", e);
+            return Err(ApiError::internal("failed to collect quarantined logs"));
+        }
+    };
+
+    let (mut reprocessed, mut failed) = (0, 0);
+    for q in logs {
+        let res = match serde_json::from_value::<Log>(q.log) {
+            Ok(l) => contract_watcher::process_log(psql.clone(), &q.event, l, &provider)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(format!("invalid stored log > {}", e)),
+        };
+        let (q_id, now) = (q.id, chrono::Utc::now().timestamp_millis());
+        let stored = match res {
+            Ok(_) => {
+                reprocessed += 1;
+                psql.run(move |client| db::set_quarantined_reprocessed(client, q_id, now))
+                    .await
+            }
+            Err(e) => {
+                failed += 1;
+                warn!(
+                    "CHAIN '{}' > ADMIN reprocessing quarantined log '{}' failed > {}",
+                    chain_id, q_id, e
+                );
+                psql.run(move |client| db::set_quarantined_error(client, q_id, &e))
+                    .await
+            }
+        };
+        if let Err(e) = stored {
+            error!("Error updating quarantined log '{}' > {}", q_id, e);
+        }
+    }
+    warn!(
+        "CHAIN '{}' - '{}' > ADMIN reprocessed '{}' quarantined logs, '{}' failed",
+        provider.chain_name, chain_id, reprocessed, failed
+    );
+    Ok(Json(ReprocessResponse {
+        reprocessed,
+        failed,
+    }))
+}
+
+/// Stop pinning to `node` and move its pins to the other nodes before removing them from it.
+///
+/// Runs in the background, returns the number of pins to move. The node stays drained across
+/// restarts until `/admin/node/undrain`, pins not moved yet are moved by draining it again
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 202, description = "Drain started", body = DrainResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/node/drain?<node>")]
+pub async fn drain_node(
+    _admin: Admin,
+    node: String,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> Result<Custom<Json<DrainResponse>>, ApiError> {
+    let drained = match find_node(state, &node) {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request(format!("unknown node '{}'", node))),
+    };
+    let n = node.clone();
+    if let Err(e) = psql.run(move |client| db::add_drained_node(client, &n)).await {
+        error!("Error storing drained node > {}", e);
+        return Err(ApiError::internal("failed to drain the node"));
+    }
+    {
+        state.drained_nodes.lock().unwrap().insert(node.clone());
+    }
+    let psql = Arc::new(psql);
+    let n = node.clone();
+    let pins = match psql
+        .run(move |client| db::get_node_cids(client, &n, Option::None, Option::None))
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Error collecting node CIDs > {}", e);
+            return Err(ApiError::internal("failed to collect node CIDs"));
+        }
+    };
+    let total = pins.len();
+    warn!("ADMIN draining NODE '{}', pins to move: '{}'", node, total);
+
+    let targets = ipfs_watcher::active_nodes(&state.nodes(), &state.drained_nodes, &state.node_capacity);
+    let providers = state.providers();
+    tokio::spawn(async move {
+        for c in pins {
+            let cid = c.cid.clone().unwrap();
+            let (chain_id, provider_id) = (c.chain_id.unwrap(), c.provider_id.unwrap());
+            // pins of a listing move to its own nodes, all of them if it is not served anymore
+            let targets = match providers
+                .iter()
+                .find(|p| p.chain_id == chain_id)
+                .and_then(|p| p.listing(provider_id))
+            {
+                Some(l) => l.nodes_of(&targets),
+                None => targets.to_vec(),
+            };
+            let cc = cid.clone();
+            let pinned_on: HashSet<String> = match psql
+                .run(move |client| db::get_cid_pins(client, &cc))
+                .await
+            {
+                Ok(v) => v
+                    .into_iter()
+                    .filter(|p| p.pinned && p.chain_id == chain_id && p.provider_id == provider_id)
+                    .map(|p| p.node)
+                    .collect(),
+                Err(e) => {
+                    error!("DRAIN '{}' > ERROR collecting pins of '{}': {}", node, cid, e);
+                    continue;
+                }
+            };
+
+            let mut moved = pinned_on.iter().any(|n| !n.eq(&node));
+            for target in targets.iter().filter(|t| !pinned_on.contains(&t.api_url)) {
+                let mut t = c.clone();
+                t.node = Option::Some(target.api_url.clone());
+                t.node_login = target.login.clone();
+                t.node_pass = target.password.clone();
+                moved |= ipfs_watcher::pin_cid_to_node_now(psql.clone(), t, true).await;
+            }
+
+            if moved {
+                let mut c = c;
+                c.node_login = drained.login.clone();
+                c.node_pass = drained.password.clone();
+                ipfs_watcher::unpin_cid_from_node_now(psql.clone(), c).await;
+            } else {
+                error!("DRAIN '{}' > could not move '{}', keeping it", node, cid);
+            }
+        }
+        warn!("ADMIN drained NODE '{}'", node);
+    });
+
+    Ok(Custom(Status::Accepted, Json(DrainResponse { pins: total })))
+}
+
+/// Allow a drained node to receive new pins again.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Node accepts pins again", body = UndrainResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/admin/node/undrain?<node>")]
+pub async fn undrain_node(
+    _admin: Admin,
+    node: String,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<UndrainResponse> {
+    let n = node.clone();
+    if let Err(e) = psql.run(move |client| db::delete_drained_node(client, &n)).await {
+        error!("Error removing drained node > {}", e);
+        return Err(ApiError::internal("failed to undrain the node"));
+    }
+    let removed = { state.drained_nodes.lock().unwrap().remove(&node) };
+    Ok(Json(UndrainResponse { undrained: removed }))
+}
+
+/// List `UpdateValidBlock` events of a chain by payment verification outcome, `underpaid` by default.
+///
+/// `payment` is one of `paid`, `underpaid`, `capped`, `unverified` or `unchecked`
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Events, latest first, at most 1000", body = [PaymentEvent]),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/admin/payments?<chain_id>&<payment>")]
+pub async fn payments(
+    _admin: Admin,
+    chain_id: i64,
+    payment: Option<String>,
+    psql: DbConn,
+) -> ApiResult<Vec<PaymentEvent>> {
+    let payment = payment.unwrap_or_else(|| contract_watcher::PAYMENT_UNDERPAID.to_owned());
+    if ![
+        contract_watcher::PAYMENT_PAID,
+        contract_watcher::PAYMENT_UNDERPAID,
+        contract_watcher::PAYMENT_CAPPED,
+        contract_watcher::PAYMENT_UNVERIFIED,
+        contract_watcher::PAYMENT_UNCHECKED,
+    ]
+    .contains(&payment.as_str())
+    {
+        return Err(ApiError::bad_request(format!("unknown payment '{}'", payment)));
+    }
+    match psql
+        .run(move |client| db::get_payment_events(client, chain_id, &payment))
+        .await
+    {
+        Ok(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting payment events > {}", e);
+            Err(ApiError::internal("failed to collect payment events"))
+        }
+    }
+}
+
+/// Bytes pinned per chain, by size descending.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Storage used per chain", body = [ChainStorage]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/admin/storage/chains")]
+pub async fn storage_chains(_admin: Admin, psql: DbConn) -> ApiResult<Vec<ChainStorage>> {
+    match psql.run(db::get_chain_storage).await {
+        Ok(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting chain storage > {}", e);
+            Err(ApiError::internal("failed to collect chain storage"))
+        }
+    }
+}
+
+/// Bytes pinned per node, by size descending.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Storage used per node", body = [NodeStorage]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/admin/storage/nodes")]
+pub async fn storage_nodes(_admin: Admin, psql: DbConn) -> ApiResult<Vec<NodeStorage>> {
+    match psql.run(db::get_node_storage).await {
+        Ok(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting node storage > {}", e);
+            Err(ApiError::internal("failed to collect node storage"))
+        }
+    }
+}
+
+/// Bytes pinned per donor, by size descending, optionally for a chain and/or a donor.
+///
+/// A CID paid for by several donors counts for each of them
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Storage used per donor, at most 1000", body = [DonorStorage]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the required scope", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[get("/admin/storage/donors?<chain_id>&<donor>")]
+pub async fn storage_donors(
+    _admin: Admin,
+    chain_id: Option<i64>,
+    donor: Option<String>,
+    psql: DbConn,
+) -> ApiResult<Vec<DonorStorage>> {
+    let donor = donor.map(|d| d.trim().to_lowercase());
+    match psql
+        .run(move |client| db::get_donor_storage(client, chain_id, donor))
+        .await
+    {
+        Ok(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting donor storage > {}", e);
+            Err(ApiError::internal("failed to collect donor storage"))
+        }
+    }
+}
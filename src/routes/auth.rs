@@ -0,0 +1,309 @@
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome},
+    serde::json::Json,
+    Request, State,
+};
+use subtle::ConstantTimeEq;
+
+use crate::db;
+use crate::routes::errors::{ApiError, ApiResult};
+use crate::types::{
+    self,
+    api::{AdminTokenResponse, NonceResponse},
+    auth::{AdminToken, AuthUser, LoginRequest, LoginResponse, SCOPES},
+    DbConn,
+};
+use crate::utils::siwe::{recover_signer, SiweMessage};
+
+/// Seconds a nonce returned by `/auth/nonce` can be used to sign in.
+const NONCE_TTL_SEC: i64 = 600;
+/// Unused nonces kept at most, `/auth/nonce` fails above it until older ones expire.
+const MAX_PENDING_NONCES: i64 = 10000;
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken,
+    MissingScope,
+    Db,
+}
+
+/// Request carrying the configured admin secret in the `X-Admin-Secret` header
+pub struct AdminSecret;
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+pub fn bearer_token(r: &Request<'_>) -> Option<String> {
+    r.headers()
+        .get_one("Authorization")
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_owned())
+}
+
+async fn session_from_request(
+    r: &Request<'_>,
+) -> Outcome<(String, i64, Vec<String>), AuthError> {
+    let token = match bearer_token(r) {
+        Some(v) => v,
+        None => return Outcome::Error((Status::Unauthorized, AuthError::MissingToken)),
+    };
+    let psql = match r.guard::<DbConn>().await {
+        Outcome::Success(v) => v,
+        _ => return Outcome::Error((Status::ServiceUnavailable, AuthError::Db)),
+    };
+    match psql.run(move |client| db::get_session(client, &token)).await {
+        Ok(Some(v)) => Outcome::Success(v),
+        Ok(None) => Outcome::Error((Status::Unauthorized, AuthError::InvalidToken)),
+        Err(e) => {
+            error!("Error getting session > {}", e);
+            Outcome::Error((Status::InternalServerError, AuthError::Db))
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthUser {
+    type Error = AuthError;
+
+    async fn from_request(r: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match session_from_request(r).await {
+            Outcome::Success((address, chain_id, scopes)) if scopes.is_empty() => {
+                Outcome::Success(AuthUser { address, chain_id })
+            }
+            Outcome::Success(_) => Outcome::Error((Status::Unauthorized, AuthError::InvalidToken)),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = AuthError;
+
+    async fn from_request(r: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match session_from_request(r).await {
+            Outcome::Success((_, _, scopes)) if !scopes.is_empty() => {
+                Outcome::Success(AdminToken { scopes })
+            }
+            Outcome::Success(_) => Outcome::Error((Status::Forbidden, AuthError::MissingScope)),
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(f) => Outcome::Forward(f),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminSecret {
+    type Error = AuthError;
+
+    async fn from_request(r: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let secret = match r.rocket().state::<types::State>() {
+            Some(v) => &v.admin_secret,
+            None => return Outcome::Error((Status::InternalServerError, AuthError::Db)),
+        };
+        match r.headers().get_one("X-Admin-Secret") {
+            Some(v) if bool::from(v.as_bytes().ct_eq(secret.as_bytes())) => {
+                Outcome::Success(AdminSecret)
+            }
+            Some(_) => Outcome::Error((Status::Unauthorized, AuthError::InvalidToken)),
+            None => Outcome::Error((Status::Unauthorized, AuthError::MissingToken)),
+        }
+    }
+}
+
+/// Issue a nonce to be used in the `Nonce` field of a SIWE message.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Fresh nonce", body = NonceResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+        (status = 503, description = "Too many pending nonces", body = ErrorResponse),
+    ),
+)]
+#[get("/auth/nonce")]
+pub async fn nonce(psql: DbConn) -> ApiResult<NonceResponse> {
+    let nonce = random_string(17);
+    let n = nonce.clone();
+    match psql
+        .run(move |client| db::add_auth_nonce(client, &n, NONCE_TTL_SEC, MAX_PENDING_NONCES))
+        .await
+    {
+        Ok(true) => Ok(Json(NonceResponse { nonce })),
+        Ok(false) => {
+            warn!("Too many pending auth nonces, refusing to issue a new one");
+            Err(ApiError::unavailable("too many pending nonces, retry later"))
+        }
+        Err(e) => {
+            error!("Error adding auth nonce > {}", e);
+            Err(ApiError::internal("failed to issue a nonce"))
+        }
+    }
+}
+
+/// Sign in with a signed EIP-4361 message.
+///
+/// Returns a session token to be sent as `Authorization: Bearer <token>`
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Session created", body = LoginResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+        (status = 503, description = "Sign in is not configured", body = ErrorResponse),
+    ),
+)]
+#[post("/auth/login", data = "<login>")]
+pub async fn login(
+    login: Json<LoginRequest>,
+    psql: DbConn,
+    state: &State<types::State>,
+) -> ApiResult<LoginResponse> {
+    let login = login.into_inner();
+    let msg = match SiweMessage::parse(&login.message) {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("Invalid SIWE message > {}", e);
+            return Err(ApiError::bad_request(format!("invalid SIWE message: {}", e)));
+        }
+    };
+
+    let domain = match &state.siwe_domain {
+        Some(v) => v,
+        None => {
+            error!("Rejecting login, 'siwe_domain' is not configured");
+            return Err(ApiError::unavailable("sign in is not configured"));
+        }
+    };
+    if let Err(e) = msg.verify_origin(domain) {
+        warn!("Invalid SIWE origin > {}", e);
+        return Err(ApiError::unauthorized(format!("SIWE message rejected: {}", e)));
+    }
+    if !state.providers().iter().any(|p| p.chain_id == msg.chain_id) {
+        return Err(ApiError::bad_request(format!("unknown chain '{}'", msg.chain_id)));
+    }
+    if !msg.is_valid_now() {
+        return Err(ApiError::unauthorized("SIWE message is expired or not valid yet"));
+    }
+
+    match recover_signer(&login.message, &login.signature) {
+        Ok(v) if v.eq(&msg.address) => {}
+        Ok(v) => {
+            warn!("SIWE signer '{}' does not match '{}'", v, msg.address);
+            return Err(ApiError::unauthorized("signature does not match the address"));
+        }
+        Err(e) => {
+            warn!("Invalid SIWE signature > {}", e);
+            return Err(ApiError::bad_request("invalid signature"));
+        }
+    };
+
+    let token = random_string(48);
+    let ttl = state.session_ttl_sec;
+    let (t, address, chain_id, nonce) = (token.clone(), msg.address.clone(), msg.chain_id, msg.nonce);
+    match psql
+        .run(move |client| {
+            if !db::use_auth_nonce(client, &nonce, NONCE_TTL_SEC)? {
+                return Ok(false);
+            }
+            db::delete_expired_sessions(client)?;
+            db::add_session(client, &t, &address, chain_id, &[], ttl as i64)?;
+            Ok(true)
+        })
+        .await
+    {
+        Ok::<bool, postgres::Error>(true) => Ok(Json(LoginResponse {
+            token,
+            address: msg.address,
+            chain_id: msg.chain_id,
+            expires_in_sec: ttl,
+        })),
+        Ok(false) => Err(ApiError::unauthorized("nonce is unknown, used or expired")),
+        Err(e) => {
+            error!("Error creating session > {}", e);
+            Err(ApiError::internal("failed to create a session"))
+        }
+    }
+}
+
+/// Mint a short lived admin token, requires the `X-Admin-Secret` header.
+///
+/// `scopes` is a comma separated list, e.g. `?scopes=pin`
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "auth",
+    params(
+        ("scopes", Query, description = "Comma separated list of `pin` and `admin`"),
+        ("ttl_sec", Query, description = "Token lifetime, the session TTL by default, at most `admin_token_max_ttl_sec`"),
+    ),
+    responses(
+        (status = 200, description = "Admin token minted", body = AdminTokenResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security(("admin_secret" = [])),
+)]
+#[post("/auth/admin?<scopes>&<ttl_sec>")]
+pub async fn admin_token(
+    _secret: AdminSecret,
+    scopes: String,
+    ttl_sec: Option<u64>,
+    psql: DbConn,
+    state: &State<types::State>,
+) -> ApiResult<AdminTokenResponse> {
+    let scopes: Vec<String> = scopes
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if scopes.is_empty() || scopes.iter().any(|s| !SCOPES.contains(&s.as_str())) {
+        return Err(ApiError::bad_request(format!(
+            "'scopes' must be a comma separated list of {:?}",
+            SCOPES
+        )));
+    }
+
+    let max_ttl = state.admin_token_max_ttl_sec;
+    let ttl = match ttl_sec {
+        Some(v) if v == 0 || v > max_ttl => {
+            return Err(ApiError::bad_request(format!(
+                "'ttl_sec' must be between 1 and {}",
+                max_ttl
+            )))
+        }
+        Some(v) => v,
+        None => std::cmp::min(state.session_ttl_sec, max_ttl),
+    };
+
+    let token = random_string(48);
+    let (t, s) = (token.clone(), scopes.clone());
+    match psql
+        .run(move |client| db::add_session(client, &t, "admin", 0, &s, ttl as i64))
+        .await
+    {
+        Ok(_) => {
+            warn!("Minted admin token with scopes {:?}", scopes);
+            Ok(Json(AdminTokenResponse {
+                token,
+                scopes,
+                expires_in_sec: ttl,
+            }))
+        }
+        Err(e) => {
+            error!("Error creating admin token > {}", e);
+            Err(ApiError::internal("failed to create an admin token"))
+        }
+    }
+}
@@ -3,6 +3,7 @@ use rocket::http::Header;
 use rocket::response::Response;
 use rocket::Request;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CORS;
 
 #[rocket::async_trait]
@@ -0,0 +1,145 @@
+use rocket::{response::content::RawHtml, serde::json::Json};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+use crate::routes::{admin, auth, events, handlers, health, proxy};
+use crate::types::{api, auth as auth_types, db, events::PinEvent, monitoring};
+use crate::utils::pagination::{HistoryPage, PinnedCIDsPage, ProviderHistoryPage, ProvidersPage};
+
+/// Multipart body of `/v0/file/upload`, only used to describe it in the OpenAPI document
+#[allow(dead_code)]
+#[derive(ToSchema)]
+pub struct UploadForm {
+    /// One part per file, all added under a directory with `?dir=true`
+    #[schema(value_type = Vec<String>, format = Binary)]
+    file: Vec<Vec<u8>>,
+}
+
+/// Adds the `bearer` (session or admin token) and `admin_secret` schemes used by the paths
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+        components.add_security_scheme(
+            "admin_secret",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Admin-Secret"))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "hosq", description = "Pins IPFS CIDs paid for on chain"),
+    paths(
+        proxy::upload,
+        auth::nonce,
+        auth::login,
+        auth::admin_token,
+        handlers::get_cids,
+        handlers::get_providers,
+        handlers::get_provider,
+        handlers::provider_stats,
+        handlers::provider_history,
+        handlers::is_pinned,
+        handlers::cid_info,
+        handlers::expiring_cids,
+        handlers::pin_cid,
+        handlers::monitoring,
+        handlers::monitoring_nodes,
+        handlers::monitoring_history,
+        handlers::metrics,
+        events::stream,
+        admin::pins,
+        admin::force_pin,
+        admin::force_unpin,
+        admin::requeue_failed,
+        admin::pause_chain,
+        admin::resume_chain,
+        admin::resync_chain,
+        admin::quarantined,
+        admin::reprocess_quarantined,
+        admin::drain_node,
+        admin::undrain_node,
+        admin::payments,
+        admin::storage_chains,
+        admin::storage_nodes,
+        admin::storage_donors,
+        health::healthz,
+        health::readyz,
+    ),
+    components(schemas(
+        UploadForm,
+        PinnedCIDsPage,
+        ProvidersPage,
+        ProviderHistoryPage,
+        HistoryPage,
+        PinEvent,
+        api::ErrorResponse,
+        api::ErrorBody,
+        api::NonceResponse,
+        api::AdminTokenResponse,
+        api::PinResponse,
+        api::NodeCount,
+        api::ForcePinResponse,
+        api::UnpinResponse,
+        api::RequeueResponse,
+        api::PauseResponse,
+        api::ResyncResponse,
+        api::ReprocessResponse,
+        api::DrainResponse,
+        api::UndrainResponse,
+        api::ProviderStats,
+        auth_types::LoginRequest,
+        auth_types::LoginResponse,
+        db::PinnedCIDs,
+        db::EventAddProviderResponse,
+        db::ProviderEvent,
+        db::CIDInfo,
+        db::ExpiringCID,
+        db::NodePin,
+        db::QuarantinedLog,
+        db::PaymentEvent,
+        db::StatsWindow,
+        db::ChainStorage,
+        db::NodeStorage,
+        db::DonorStorage,
+        monitoring::Monitoring,
+        monitoring::Event,
+        monitoring::HistoryEvent,
+        monitoring::NodeCapacity,
+    )),
+    modifiers(&SecurityAddon)
+)]
+pub struct ApiDoc;
+
+/// OpenAPI 3 document of the API
+#[get("/openapi.json")]
+pub async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Redoc UI over `/v0/openapi.json`
+#[get("/docs")]
+pub async fn docs() -> RawHtml<&'static str> {
+    RawHtml(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>hosq API</title>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+  </head>
+  <body>
+    <redoc spec-url="/v0/openapi.json"></redoc>
+    <script src="https://cdn.redoc.ly/redoc/latest/bundles/redoc.standalone.js"></script>
+  </body>
+</html>"#,
+    )
+}
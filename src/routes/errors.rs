@@ -0,0 +1,127 @@
+use rocket::{
+    http::Status,
+    response::{self, status::Custom, Responder},
+    serde::json::Json,
+    Request,
+};
+
+use crate::types::api::{ErrorBody, ErrorResponse};
+
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// Failed request, rendered as `ErrorResponse`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(Status::BadRequest, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(Status::Unauthorized, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Status::NotFound, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(Status::InternalServerError, message)
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        Self::new(Status::ServiceUnavailable, message)
+    }
+}
+
+/// `snake_case` reason of the status, e.g. `not_found`
+fn code(status: Status) -> String {
+    status
+        .reason()
+        .unwrap_or("error")
+        .to_lowercase()
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+}
+
+fn body(status: Status, message: String) -> Json<ErrorResponse> {
+    Json(ErrorResponse {
+        error: ErrorBody {
+            code: code(status),
+            message,
+        },
+    })
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        Custom(self.status, body(self.status, self.message)).respond_to(req)
+    }
+}
+
+#[catch(404)]
+pub fn not_found(req: &Request) -> Json<ErrorResponse> {
+    body(Status::NotFound, format!("'{}' not found", req.uri().path()))
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(_req: &Request) -> Json<ErrorResponse> {
+    body(
+        Status::UnprocessableEntity,
+        "the request body could not be parsed".to_owned(),
+    )
+}
+
+#[catch(500)]
+pub fn internal_error(_req: &Request) -> Json<ErrorResponse> {
+    body(Status::InternalServerError, "internal server error".to_owned())
+}
+
+/// Guard failures such as a missing or invalid session token
+#[catch(default)]
+pub fn default(status: Status, _req: &Request) -> Custom<Json<ErrorResponse>> {
+    let message = status.reason().unwrap_or("error").to_owned();
+    Custom(status, body(status, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        assert_eq!(code(Status::BadRequest), "bad_request");
+        assert_eq!(code(Status::Unauthorized), "unauthorized");
+        assert_eq!(code(Status::Forbidden), "forbidden");
+        assert_eq!(code(Status::NotFound), "not_found");
+        assert_eq!(code(Status::PayloadTooLarge), "payload_too_large");
+        assert_eq!(code(Status::UnprocessableEntity), "unprocessable_entity");
+        assert_eq!(code(Status::InternalServerError), "internal_server_error");
+        assert_eq!(code(Status::ServiceUnavailable), "service_unavailable");
+        assert_eq!(code(Status::ImATeapot), "i_m_a_teapot");
+        assert_eq!(code(Status::new(599)), "error");
+    }
+
+    #[test]
+    fn error_body() {
+        let e = ApiError::unavailable("latest block is not known yet");
+        assert_eq!(e.status, Status::ServiceUnavailable);
+        let b = body(e.status, e.message).into_inner();
+        assert_eq!(
+            serde_json::to_value(b).unwrap(),
+            serde_json::json!({
+                "error": { "code": "service_unavailable", "message": "latest block is not known yet" }
+            })
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use rocket::{
+    response::stream::{Event, EventStream},
+    tokio::{select, sync::broadcast::error::RecvError},
+    Shutdown,
+};
+
+use crate::services::events;
+
+fn same_address(a: &str, b: &str) -> bool {
+    a.trim_start_matches("0x")
+        .eq_ignore_ascii_case(b.trim_start_matches("0x"))
+}
+
+/// Server-sent events for the CIDs of `address` on `chain_id`.
+///
+/// Sends `cid_indexed`, `pinned`, `pin_failed` and `unpinned` as they happen, each with the
+/// same JSON body as the webhooks. Events missed by a slow client are skipped
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "events",
+    params(
+        ("address", Query, description = "Donor address"),
+    ),
+    responses(
+        (status = 200, description = "One `PinEvent` per message, named after its `event`", body = PinEvent, content_type = "text/event-stream"),
+    ),
+)]
+#[get("/events/stream?<address>&<chain_id>")]
+pub async fn stream(address: String, chain_id: i64, mut end: Shutdown) -> EventStream![] {
+    let mut rx = events::subscribe();
+    EventStream! {
+        loop {
+            let e = select! {
+                e = rx.recv() => match e {
+                    Ok(v) => v,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("EVENTS '{}' > stream lagged, skipped '{}' events", &address, n);
+                        continue;
+                    }
+                },
+                _ = &mut end => break,
+            };
+            if e.chain_id != chain_id || !e.donors.iter().any(|d| same_address(d, &address)) {
+                continue;
+            }
+            yield Event::json(&e).event(e.event.clone());
+        }
+    }
+}
@@ -1,23 +1,31 @@
-// use crate::db;
+use crate::db;
+use crate::routes::errors::{ApiError, ApiResult};
+use crate::services::ipfs_watcher;
+use crate::utils::{
+    self,
+    pagination::{self, KeyType, Page, PagedQuery, SortKey},
+};
 use crate::types::{
     self,
-    db::{CIDInfo, EventAddProviderResponse, PinnedCIDs},
+    api::{NodeCount, PinResponse, ProviderStats},
+    auth::{AdminToken, AuthUser, SCOPE_PIN},
+    db::{CIDInfo, EventAddProviderResponse, ExpiringCID, PinnedCIDs, ProviderEvent},
+    monitoring::{HistoryEvent, Monitoring, NodeCapacity},
     DbConn, Web3Node,
 };
 use postgres::Client;
+use rocket::State;
 use rocket::{
-    response::status::Custom,
-    State,
+    http::{ContentType, Status},
+    serde::json::Json,
 };
-use rocket::{http::Status, serde::json::Json};
-use serde_json::json;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 async fn get_block_number(chain_id: i64, providers: Arc<Vec<Web3Node>>) -> Option<(u64, u64)> {
     for provider in &**providers {
         if provider.chain_id == chain_id {
             // return Option::Some(provider.to_owned());
-            let bn = { provider.latest_block.clone().lock().unwrap().clone() };
+            let bn = { *provider.latest_block.lock().unwrap() };
             match bn {
                 Some(v) => return Option::Some((v as u64, provider.block_time_sec)),
                 None => {
@@ -31,234 +39,651 @@ async fn get_block_number(chain_id: i64, providers: Arc<Vec<Web3Node>>) -> Optio
     Option::None
 }
 
-// #[post("/cid/pin?<cid>&<chain_id>&<address>&<secret>")]
-// pub async fn pin_cid(
-//     cid: String,
-//     address: String,
-//     secret: Option<String>,
-//     state: &State<types::State>,
-//     chain_id: i64,
-//     psql: DbConn,
-// ) -> Custom<Option<Json<String>>> {
-//     let (update_block, b_time) = match get_block_number(chain_id, state.providers.clone()).await {
-//         Some(v) => v,
-//         None => return Custom(Status::BadRequest, Option::None),
-//     };
-
-//     let admin_pin = if let Some(sec) = secret {
-//         sec.eq(&state.admin_secret)
-//     } else {
-//         false
-//     };
-
-//     let end_block = if admin_pin {
-//         warn!("Request contains Admin secret");
-//         -1
-//     } else {
-//         let req = match reqwest::Client::new()
-//             .post(format!(
-//                 "{}/api/v0/dag/stat?arg={}&progress=false",
-//                 state.nodes[0].api_url, cid
-//             ))
-//             .send()
-//             .await
-//         {
-//             Ok(v) => {
-//                 println!("{:?}", v);
-//                 v.json::<IpfsDagStat>().await
-//             }
-//             Err(e) => {
-//                 error!("Error fetching CID info {}", e);
-//                 return Custom(Status::InternalServerError, Option::None);
-//             }
-//         };
-
-//         let cid_size = match req {
-//             Ok(v) => v.size,
-//             Err(e) => {
-//                 error!("Error parsing json for CID info {}", e);
-//                 return Custom(Status::InternalServerError, Option::None);
-//             }
-//         };
-
-//         if cid_size <= 10_485_760 {
-//             //10Mib
-//             -1
-//         } else {
-//             (update_block + (604800 / b_time)) as i64
-//         } // 7 Days
-//     };
-
-//     match psql
-//         .run(move |client| {
-//             if !db::cid_exists(client, &cid)? || admin_pin {
-//                 db::add_valid_block(
-//                     client,
-//                     types::db::EventUpdateValidBlock {
-//                         chain_id,
-//                         cid,
-//                         donor: address,
-//                         update_block: update_block as i64,
-//                         end_block: end_block,
-//                         manual_add: Option::Some(true),
-//                     },
-//                 )?;
-//             }
-//             Ok::<(), postgres::Error>(())
-//         })
-//         .await
-//     {
-//         Ok(_) => Custom(Status::Ok, Option::Some(Json("true".to_owned()))),
-//         Err(e) => {
-//             error!("Error Adding cid {}", e);
-//             return Custom(Status::InternalServerError, Option::None);
-//         }
-//     }
-// }
-
-#[get("/cid/pinned?<address>&<chain_id>")]
+/// Canonical form of a CID passed to the API, either form can be used for lookups
+pub fn canonical_cid(cid: &str) -> Result<String, ApiError> {
+    utils::cid::canonical(cid)
+        .map_err(|e| ApiError::bad_request(format!("invalid CID '{}': {}", cid, e)))
+}
+
+/// Pin a CID manually.
+///
+/// With an admin token carrying the `pin` scope the CID is pinned without expiration for `address`.
+/// Signed in donors get a trial pin on their session's chain, its duration depends on the CID size
+/// according to the `pin_policy` of the listing, the chain's first one unless `provider_id` is set.
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "cid",
+    params(
+        ("cid", Query, description = "CID to pin"),
+        ("address", Query, description = "Donor to pin for, admin tokens only"),
+        ("provider_id", Query, description = "Listing to pin for, the chain's first one by default"),
+    ),
+    responses(
+        (status = 200, description = "CID is indexed for pinning", body = PinResponse),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 413, description = "CID is too big for a trial pin", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+        (status = 503, description = "Not ready to serve the request", body = ErrorResponse),
+    ),
+    security(("bearer" = [])),
+)]
+#[post("/cid/pin?<cid>&<chain_id>&<address>&<provider_id>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn pin_cid(
+    cid: String,
+    chain_id: i64,
+    address: Option<String>,
+    provider_id: Option<i64>,
+    admin: Option<AdminToken>,
+    user: Option<AuthUser>,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<PinResponse> {
+    let cid_original = cid;
+    let cid = canonical_cid(&cid_original)?;
+    let listing = match state.providers().iter().find(|p| p.chain_id == chain_id) {
+        Some(p) => match provider_id {
+            Some(id) => p.listing(id).cloned(),
+            None => p.listings.first().cloned(),
+        },
+        None => return Err(ApiError::bad_request(format!("unknown chain '{}'", chain_id))),
+    };
+    let listing = match listing {
+        Some(v) => v,
+        None => {
+            return Err(ApiError::bad_request(format!(
+                "the provider is not served on chain '{}'",
+                chain_id
+            )))
+        }
+    };
+    let (provider_id, pin_policy) = (listing.provider_id, listing.pin_policy());
+    let (update_block, b_time) = match get_block_number(chain_id, state.providers()).await {
+        Some(v) => v,
+        None => return Err(ApiError::unavailable("latest block is not known yet")),
+    };
+
+    let admin_pin = admin.is_some_and(|a| a.has_scope(SCOPE_PIN));
+    let donor = match (admin_pin, address, user) {
+        (true, Some(a), _) => a,
+        (true, None, Some(u)) => u.address,
+        (true, None, None) => "admin".to_owned(),
+        (false, _, Some(u)) if u.chain_id == chain_id => u.address,
+        (false, _, Some(_)) => {
+            return Err(ApiError::bad_request("the session is for another chain"))
+        }
+        (false, _, None) => return Err(ApiError::unauthorized("a session or admin token is required")),
+    };
+
+    let end_block = if admin_pin {
+        warn!("CHAIN '{}' > Admin pin of '{}' for '{}'", chain_id, cid, donor);
+        -1
+    } else {
+        let node = match listing.nodes_of(&state.nodes()).first() {
+            Some(v) => v.api_url.clone(),
+            None => return Err(ApiError::unavailable("no IPFS node configured")),
+        };
+        let cid_size = match ipfs_watcher::get_dag_stat(&node, &cid).await {
+            Ok(v) => v.size,
+            Err(e) => {
+                error!("Error fetching CID info {}", e);
+                return Err(ApiError::internal("failed to get the CID size"));
+            }
+        };
+
+        match pin_policy.tier_for(cid_size) {
+            Some(t) => match t.duration_sec {
+                Some(d) => (update_block + d / b_time) as i64,
+                None => -1,
+            },
+            None => {
+                info!(
+                    "CHAIN '{}' > '{}' of size '{}' is too big for a trial pin",
+                    chain_id, cid, cid_size
+                );
+                return Err(ApiError::new(
+                    Status::PayloadTooLarge,
+                    format!("'{}' bytes is too big for a trial pin", cid_size),
+                ));
+            }
+        }
+    };
+
+    let c = cid.clone();
+    match psql
+        .run(move |client| {
+            if !admin_pin && db::cid_exists(client, chain_id, provider_id, &c)? {
+                return Ok(false);
+            }
+            db::add_valid_block(
+                client,
+                types::db::EventUpdateValidBlock {
+                    chain_id,
+                    provider_id,
+                    cid: c,
+                    cid_original,
+                    donor,
+                    update_block: update_block as i64,
+                    end_block,
+                    manual_add: Option::Some(true),
+                    claimed_end_block: Option::None,
+                    paid_wei: Option::None,
+                    paid_end_block: Option::None,
+                    payment: Option::None,
+                    tx_hash: Option::None,
+                    log_index: Option::None,
+                },
+            )?;
+            Ok(true)
+        })
+        .await
+    {
+        Ok::<bool, postgres::Error>(added) => Ok(Json(PinResponse {
+            cid,
+            end_block,
+            added,
+        })),
+        Err(e) => {
+            error!("Error Adding cid {}", e);
+            Err(ApiError::internal("failed to add the CID"))
+        }
+    }
+}
+
+/// Sort options of `get_cids`, `status` lists failed pins first then the least replicated ones
+fn cid_sort_keys(sort: &Option<String>) -> Option<Vec<SortKey>> {
+    match sort.as_deref() {
+        None | Some("status") => Some(vec![
+            ("-fc", KeyType::Int),
+            ("c", KeyType::Int),
+            ("eb", KeyType::Int),
+            ("cid", KeyType::Text),
+        ]),
+        Some("end_block") => Some(vec![("eb", KeyType::Int), ("cid", KeyType::Text)]),
+        Some("update_block") => Some(vec![("ub", KeyType::Int), ("cid", KeyType::Text)]),
+        Some("cid") => Some(vec![("cid", KeyType::Text)]),
+        _ => None,
+    }
+}
+
+/// Sort options of `get_providers` and `get_provider`
+fn provider_sort_keys(sort: &Option<String>, default: &str) -> Option<Vec<SortKey>> {
+    match sort.as_deref().unwrap_or(default) {
+        "price" => Some(vec![
+            ("block_price_gwei", KeyType::Int),
+            ("name", KeyType::Text),
+            ("provider_id", KeyType::Int),
+            ("update_block", KeyType::Int),
+        ]),
+        "name" => Some(vec![
+            ("name", KeyType::Text),
+            ("provider_id", KeyType::Int),
+            ("update_block", KeyType::Int),
+        ]),
+        "provider_id" => Some(vec![("provider_id", KeyType::Int), ("update_block", KeyType::Int)]),
+        _ => None,
+    }
+}
+
+/// List CIDs of a donor.
+///
+/// `address` can be omitted when the request carries a session token, the signed in address is used instead.
+/// `status` is one of `pinned`, `failed`, `expired` or `pending_unpin`, `sort` one of `status` (default), `end_block`,
+/// `update_block` or `cid`. Pass `next_cursor` of the response as `cursor` to get the next page
+#[allow(clippy::too_many_arguments)]
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "cid",
+    params(
+        ("address", Query, description = "Donor address, defaults to the signed in one"),
+        ("status", Query, description = "`pinned`, `failed`, `expired` or `pending_unpin`"),
+        ("sort", Query, description = "`status`, `end_block`, `update_block` or `cid`"),
+        ("order", Query, description = "`asc` or `desc`"),
+        ("limit", Query, description = "Page size, 100 by default and at most 500"),
+        ("cursor", Query, description = "`next_cursor` of the previous page"),
+    ),
+    responses(
+        (status = 200, description = "Page of CIDs", body = PinnedCIDsPage),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+    security((), ("bearer" = [])),
+)]
+#[get("/cid/pinned?<address>&<chain_id>&<status>&<cid_prefix>&<min_end_block>&<max_end_block>&<sort>&<order>&<limit>&<cursor>")]
 pub async fn get_cids(
-    address: String,
+    address: Option<String>,
     chain_id: i64,
+    status: Option<String>,
+    cid_prefix: Option<String>,
+    min_end_block: Option<i64>,
+    max_end_block: Option<i64>,
+    sort: Option<String>,
+    order: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    user: Option<AuthUser>,
     psql: DbConn,
     state: &State<types::State>,
-) -> Custom<Option<Json<String>>> {
-    let bn = match get_block_number(chain_id, state.providers.clone()).await {
-        Some(v) => v.0,
-        None => return Custom(Status::BadRequest, Option::None),
+) -> ApiResult<Page<PinnedCIDs>> {
+    let address = match address.or(user.map(|u| u.address)) {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request("'address' or a session token is required")),
+    };
+    let bn = match get_block_number(chain_id, state.providers()).await {
+        Some(v) => v.0 as i64,
+        None => return Err(ApiError::bad_request(format!("unknown chain '{}'", chain_id))),
     };
-    match psql.run( move |client: &mut Client|{
-        let res = client.query("
+    let (keys, desc) = match (cid_sort_keys(&sort), pagination::is_desc(&order)) {
+        (Some(k), Some(d)) => (k, d),
+        _ => return Err(ApiError::bad_request("invalid 'sort' or 'order'")),
+    };
+
+    let mut q = PagedQuery::default();
+    let base = format!("
         SELECT euvb.cid, euvb.donor, min(euvb.update_block) ub, 
                 COALESCE(
                         (SELECT MAX(end_block) 
                         FROM pinned_cids 
-                        WHERE chain_id=$1::BIGINT AND cid=euvb.cid),
+                        WHERE chain_id={chain}::BIGINT AND cid=euvb.cid),
                     MAX(euvb.end_block) ) as eb, 
-                (SELECT count(pc.node) 
+                (SELECT count(DISTINCT pc.node) 
                     FROM pinned_cids as pc 
-                    WHERE pc.chain_id=$1::BIGINT AND pc.cid=euvb.cid 
-                    AND (pc.end_block>=$3::BIGINT OR pc.end_block=-1::BIGINT)) as c,
-                (SELECT count(fc.node) 
+                    WHERE pc.chain_id={chain}::BIGINT AND pc.cid=euvb.cid 
+                    AND (pc.end_block>={bn}::BIGINT OR pc.end_block=-1::BIGINT)) as c,
+                (SELECT count(DISTINCT fc.node) 
                     FROM failed_pins as fc 
-                    WHERE fc.chain_id=$1::BIGINT AND fc.cid=euvb.cid AND fc.end_block>=$3::BIGINT) as fc
+                    WHERE fc.chain_id={chain}::BIGINT AND fc.cid=euvb.cid AND fc.end_block>={bn}::BIGINT) as fc,
+                min(euvb.cid_original) as cid_original,
+                EXISTS (SELECT 1
+                    FROM pinned_cids as pu
+                    WHERE pu.chain_id={chain}::BIGINT AND pu.cid=euvb.cid AND pu.pending_unpin) as pu
         FROM event_update_valid_block as euvb
-        WHERE euvb.chain_id=$1::BIGINT AND euvb.donor=LOWER($2::TEXT) 
+        WHERE euvb.chain_id={chain}::BIGINT AND euvb.donor=LOWER({address}::TEXT) 
         GROUP BY euvb.cid, euvb.donor
-        ORDER BY fc DESC, c ASC, eb ASC LIMIT 100;
-        ", &[&chain_id, &address, &(bn as i64)])?;
-
-        Ok(res.into_iter().map(|r| PinnedCIDs{
-            cid: r.get(0),
-            donor: r.get(1),
-            update_block: r.get(2),
-            end_block: r.get(3),
-            node_count: r.get(4),
-            failed_node_count: r.get(5),
-        }).collect())
-    })
-    .await{
-        Ok::<Vec<PinnedCIDs>, postgres::Error>(v)=>Custom(
-                    Status::Ok,
-                    Option::Some(Json(json!(v).to_string()))
-                ),
-        Err(e)=>{
-            error!("Error collecting pinned CIDs > {}", e);
-            return Custom(
-                Status::InternalServerError,
-                Option::None
-            )
+        ",
+        chain = q.bind(chain_id),
+        address = q.bind(address),
+        bn = q.bind(bn),
+    );
+    match status.as_deref() {
+        None => {}
+        Some("pinned") => q.filter("c>0".to_owned()),
+        Some("failed") => q.filter("fc>0".to_owned()),
+        Some("pending_unpin") => q.filter("pu".to_owned()),
+        Some("expired") => {
+            let p = q.bind(bn);
+            q.filter(format!("eb<>-1 AND eb<{}::BIGINT", p))
+        }
+        Some(v) => return Err(ApiError::bad_request(format!("invalid status '{}'", v))),
+    }
+    if let Some(v) = cid_prefix {
+        let p = q.bind(v);
+        q.filter(format!(
+            "(left(cid, length({p}::TEXT))={p}::TEXT OR left(cid_original, length({p}::TEXT))={p}::TEXT)",
+            p = p
+        ));
+    }
+    if let Some(v) = min_end_block {
+        let p = q.bind(v);
+        q.filter(format!("eb>={}::BIGINT", p));
+    }
+    if let Some(v) = max_end_block {
+        let p = q.bind(v);
+        q.filter(format!("eb<={}::BIGINT", p));
+    }
+    if let Some(c) = &cursor {
+        if !q.after(c, &keys, desc) {
+            return Err(ApiError::bad_request("invalid 'cursor'"));
         }
     }
-}
 
-#[get("/providers?<chain_id>")]
-pub async fn get_providers(chain_id: i64, psql: DbConn) -> Custom<Option<Json<String>>> {
+    let limit = pagination::limit(limit);
     match psql
         .run(move |client: &mut Client| {
-            let res = client.query(
-                "
-        SELECT provider_id, block_price_gwei, name, api_url
+            q.run(client, &base, &keys, desc, limit, |r| PinnedCIDs {
+                cid: r.get(0),
+                donor: r.get(1),
+                update_block: r.get(2),
+                end_block: r.get(3),
+                node_count: r.get(4),
+                failed_node_count: r.get(5),
+                cid_original: r.get(6),
+                pending_unpin: r.get(7),
+                expires_at: Option::None,
+            })
+        })
+        .await
+    {
+        Ok::<Page<PinnedCIDs>, postgres::Error>(mut v) => {
+            if let Some(p) = state.providers().iter().find(|p| p.chain_id == chain_id) {
+                for c in v.items.iter_mut() {
+                    c.expires_at = p.block_timestamp(c.end_block);
+                }
+            }
+            Ok(Json(v))
+        }
+        Err(e) => {
+            error!("Error collecting pinned CIDs > {}", e);
+            Err(ApiError::internal("failed to collect pinned CIDs"))
+        }
+    }
+}
+
+/// List providers of a chain, `sort` is one of `price` (default), `name` or `provider_id`
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "providers",
+    params(
+        ("sort", Query, description = "`price`, `name` or `provider_id`"),
+        ("order", Query, description = "`asc` or `desc`"),
+        ("cursor", Query, description = "`next_cursor` of the previous page"),
+    ),
+    responses(
+        (status = 200, description = "Page of providers", body = ProvidersPage),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+)]
+#[get("/providers?<chain_id>&<sort>&<order>&<limit>&<cursor>")]
+pub async fn get_providers(
+    chain_id: i64,
+    sort: Option<String>,
+    order: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    psql: DbConn,
+) -> ApiResult<Page<EventAddProviderResponse>> {
+    let (keys, desc) = match (provider_sort_keys(&sort, "price"), pagination::is_desc(&order)) {
+        (Some(k), Some(d)) => (k, d),
+        _ => return Err(ApiError::bad_request("invalid 'sort' or 'order'")),
+    };
+    let mut q = PagedQuery::default();
+    let base = format!(
+        "
+        SELECT provider_id, block_price_gwei, name, api_url, update_block
         FROM event_add_provider
-        WHERE chain_id=$1::BIGINT 
-        ORDER BY block_price_gwei ASC, name ASC 
-        LIMIT 100;
+        WHERE chain_id={}::BIGINT
         ",
-                &[&chain_id],
-            )?;
+        q.bind(chain_id)
+    );
+    if let Some(c) = &cursor {
+        if !q.after(c, &keys, desc) {
+            return Err(ApiError::bad_request("invalid 'cursor'"));
+        }
+    }
 
-            Ok(res
-                .into_iter()
-                .map(|r| EventAddProviderResponse {
-                    provider_id: r.get(0),
-                    block_price_gwei: r.get(1),
-                    name: r.get(2),
-                    api_url: r.get(3),
-                    update_block: None,
-                })
-                .collect())
+    let limit = pagination::limit(limit);
+    match psql
+        .run(move |client: &mut Client| {
+            q.run(client, &base, &keys, desc, limit, |r| EventAddProviderResponse {
+                provider_id: r.get(0),
+                block_price_gwei: r.get(1),
+                name: r.get(2),
+                api_url: r.get(3),
+                update_block: r.get(4),
+            })
         })
         .await
     {
-        Ok::<Vec<EventAddProviderResponse>, postgres::Error>(v) => {
-            Custom(Status::Ok, Option::Some(Json(json!(v).to_string())))
-        }
+        Ok::<Page<EventAddProviderResponse>, postgres::Error>(v) => Ok(Json(v)),
         Err(e) => {
-            error!("Error collecting pinned CIDs > {}", e);
-            return Custom(Status::InternalServerError, Option::None);
+            error!("Error collecting providers > {}", e);
+            Err(ApiError::internal("failed to collect providers"))
         }
     }
 }
 
-#[get("/provider?<chain_id>&<address>")]
+/// List providers owned by `address`, `sort` is one of `name` (default), `price` or `provider_id`
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "providers",
+    params(
+        ("address", Query, description = "Owner address"),
+        ("sort", Query, description = "`name`, `price` or `provider_id`"),
+        ("order", Query, description = "`asc` or `desc`"),
+        ("cursor", Query, description = "`next_cursor` of the previous page"),
+    ),
+    responses(
+        (status = 200, description = "Page of providers", body = ProvidersPage),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+)]
+#[get("/provider?<chain_id>&<address>&<sort>&<order>&<limit>&<cursor>")]
 pub async fn get_provider(
     chain_id: i64,
     address: String,
+    sort: Option<String>,
+    order: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
     psql: DbConn,
-) -> Custom<Option<Json<String>>> {
-    match psql
-        .run(move |client: &mut Client| {
-            let res = client.query(
-                "
+) -> ApiResult<Page<EventAddProviderResponse>> {
+    let (keys, desc) = match (provider_sort_keys(&sort, "name"), pagination::is_desc(&order)) {
+        (Some(k), Some(d)) => (k, d),
+        _ => return Err(ApiError::bad_request("invalid 'sort' or 'order'")),
+    };
+    let mut q = PagedQuery::default();
+    let base = format!(
+        "
         SELECT provider_id, block_price_gwei, name, api_url, update_block
         FROM event_add_provider
-        WHERE chain_id=$1::BIGINT AND owner=LOWER($2::TEXT) 
-        ORDER BY name ASC 
-        LIMIT 100;
+        WHERE chain_id={}::BIGINT AND owner=LOWER({}::TEXT)
         ",
-                &[&chain_id, &address],
-            )?;
+        q.bind(chain_id),
+        q.bind(address)
+    );
+    if let Some(c) = &cursor {
+        if !q.after(c, &keys, desc) {
+            return Err(ApiError::bad_request("invalid 'cursor'"));
+        }
+    }
 
-            Ok(res
-                .into_iter()
-                .map(|r| EventAddProviderResponse {
-                    provider_id: r.get(0),
-                    block_price_gwei: r.get(1),
-                    name: r.get(2),
-                    api_url: r.get(3),
-                    update_block: r.get(4),
-                })
-                .collect())
+    let limit = pagination::limit(limit);
+    match psql
+        .run(move |client: &mut Client| {
+            q.run(client, &base, &keys, desc, limit, |r| EventAddProviderResponse {
+                provider_id: r.get(0),
+                block_price_gwei: r.get(1),
+                name: r.get(2),
+                api_url: r.get(3),
+                update_block: r.get(4),
+            })
+        })
+        .await
+    {
+        Ok::<Page<EventAddProviderResponse>, postgres::Error>(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting providers > {}", e);
+            Err(ApiError::internal("failed to collect providers"))
+        }
+    }
+}
+
+/// Timeline of every event of a provider, oldest first by default.
+///
+/// Update events only carry the field they change, see `event_add_provider` for the current state
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "providers",
+    params(
+        ("order", Query, description = "`asc` or `desc`"),
+        ("cursor", Query, description = "`next_cursor` of the previous page"),
+    ),
+    responses(
+        (status = 200, description = "Page of provider events", body = ProviderHistoryPage),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+)]
+#[get("/provider/history?<chain_id>&<provider_id>&<order>&<limit>&<cursor>")]
+pub async fn provider_history(
+    chain_id: i64,
+    provider_id: i64,
+    order: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    psql: DbConn,
+) -> ApiResult<Page<ProviderEvent>> {
+    let desc = match pagination::is_desc(&order) {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request("invalid 'order'")),
+    };
+    let keys: Vec<SortKey> = vec![("update_block", KeyType::Int), ("log_index", KeyType::Int)];
+    let mut q = PagedQuery::default();
+    let base = format!(
+        "
+        SELECT chain_id, provider_id, event, update_block, tx_hash, log_index,
+        owner, block_price_gwei, api_url, name
+        FROM provider_events
+        WHERE chain_id={}::BIGINT AND provider_id={}::BIGINT
+        ",
+        q.bind(chain_id),
+        q.bind(provider_id)
+    );
+    if let Some(c) = &cursor {
+        if !q.after(c, &keys, desc) {
+            return Err(ApiError::bad_request("invalid 'cursor'"));
+        }
+    }
+
+    let limit = pagination::limit(limit);
+    match psql
+        .run(move |client: &mut Client| {
+            q.run(client, &base, &keys, desc, limit, |r| ProviderEvent {
+                chain_id: r.get(0),
+                provider_id: r.get(1),
+                event: r.get(2),
+                update_block: r.get(3),
+                tx_hash: r.get(4),
+                log_index: r.get(5),
+                owner: r.get(6),
+                block_price_gwei: r.get(7),
+                api_url: r.get(8),
+                name: r.get(9),
+            })
         })
         .await
     {
-        Ok::<Vec<EventAddProviderResponse>, postgres::Error>(v) => {
-            Custom(Status::Ok, Option::Some(Json(json!(v).to_string())))
+        Ok::<Page<ProviderEvent>, postgres::Error>(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting provider history > {}", e);
+            Err(ApiError::internal("failed to collect provider history"))
         }
+    }
+}
+
+/// Windows of `provider_stats` when none are requested: a day, a week, 30 days and all time
+const STATS_WINDOWS_SEC: [u64; 4] = [86_400, 604_800, 2_592_000, 0];
+
+/// Usage and earnings of the provider served on a chain.
+///
+/// `windows` is a comma separated list of window lengths in seconds, `0` for all time
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "providers",
+    params(
+        ("windows", Query, description = "Window lengths in seconds, e.g. `86400,604800,0`"),
+    ),
+    responses(
+        (status = 200, description = "Provider statistics", body = ProviderStats),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 404, description = "Provider is not served on the chain", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+        (status = 503, description = "Not ready to serve the request", body = ErrorResponse),
+    ),
+)]
+#[get("/provider/stats?<chain_id>&<provider_id>&<windows>")]
+pub async fn provider_stats(
+    chain_id: i64,
+    provider_id: i64,
+    windows: Option<String>,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<ProviderStats> {
+    let windows = match windows {
+        Some(w) => w
+            .split(',')
+            .map(|v| v.trim().parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|v| !v.is_empty() && v.len() <= 10),
+        None => Option::Some(STATS_WINDOWS_SEC.to_vec()),
+    };
+    let windows = match windows {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request("invalid 'windows', at most 10 allowed")),
+    };
+    let provider = match state
+        .providers()
+        .iter()
+        .find(|p| p.chain_id == chain_id && p.listing(provider_id).is_some())
+    {
+        Some(v) => v.clone(),
+        None => {
+            return Err(ApiError::not_found(format!(
+                "provider '{}' is not served on chain '{}'",
+                provider_id, chain_id
+            )))
+        }
+    };
+    let head = { *provider.latest_block.lock().unwrap() };
+    let head = match head {
+        Some(v) => v,
+        None => return Err(ApiError::unavailable("latest block is not known yet")),
+    };
+
+    match psql
+        .run(move |client| {
+            let price = db::get_provider_block_price(client, chain_id, provider_id)?;
+            let (active_cids, active_donors, bytes_pinned) =
+                db::get_active_stats(client, chain_id, provider_id)?;
+            let mut stats = vec![];
+            for w in windows {
+                let from_block = match w {
+                    0 => 0,
+                    _ => std::cmp::max(head - provider.blocks_within(w), 0),
+                };
+                stats.push(db::get_window_stats(client, chain_id, provider_id, w, from_block)?);
+            }
+            Ok(ProviderStats {
+                chain_id,
+                provider_id,
+                block: head,
+                block_price_gwei: price,
+                active_cids,
+                active_donors,
+                bytes_pinned,
+                windows: stats,
+            })
+        })
+        .await
+    {
+        Ok::<ProviderStats, postgres::Error>(v) => Ok(Json(v)),
         Err(e) => {
-            error!("Error collecting pinned CIDs > {}", e);
-            return Custom(Status::InternalServerError, Option::None);
+            error!("Error collecting provider stats > {}", e);
+            Err(ApiError::internal("failed to collect provider stats"))
         }
     }
 }
 
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "cid",
+    responses(
+        (status = 200, description = "Number of nodes the CID is pinned on", body = NodeCount),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+)]
 #[get("/cid/pinned/<cid>")]
-pub async fn is_pinned(cid: String, psql: DbConn) -> Custom<Option<Json<String>>> {
+pub async fn is_pinned(cid: String, psql: DbConn) -> ApiResult<NodeCount> {
+    let cid = canonical_cid(&cid)?;
     match psql
         .run(move |client: &mut Client| {
             let res = client.query_one(
                 "
-        SELECT count(node)
+        SELECT count(DISTINCT node)
         FROM pinned_cids
         WHERE cid=$1::TEXT;
         ",
@@ -269,25 +694,35 @@ pub async fn is_pinned(cid: String, psql: DbConn) -> Custom<Option<Json<String>>
         })
         .await
     {
-        Ok::<i64, postgres::Error>(v) => Custom(
-            Status::Ok,
-            Option::Some(Json(format!("{{\"nodes\":{}}}", v))),
-        ),
+        Ok::<i64, postgres::Error>(v) => Ok(Json(NodeCount { nodes: v })),
         Err(e) => {
             error!("Error collecting pinned CIDs > {}", e);
-            return Custom(Status::InternalServerError, Option::None);
+            Err(ApiError::internal("failed to count pinned nodes"))
         }
     }
 }
 
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "cid",
+    responses(
+        (status = 200, description = "Pins of the CID per chain", body = [CIDInfo]),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+)]
 #[get("/cid/info?<cid>")]
-pub async fn cid_info(cid: String, psql: DbConn) -> Custom<Option<Json<String>>> {
+pub async fn cid_info(
+    cid: String,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<Vec<CIDInfo>> {
+    let cid = canonical_cid(&cid)?;
     match psql
         .run(move |client: &mut Client| {
             let res = client.query(
                 "
-        SELECT pc.chain_id, count(pc.node), max(pc.end_block),
-                fp.chain_id, count(fp.node), max(fp.end_block)
+        SELECT pc.chain_id, count(DISTINCT pc.node), max(pc.end_block),
+                fp.chain_id, count(DISTINCT fp.node), max(fp.end_block)
         FROM pinned_cids pc
         FULL OUTER JOIN failed_pins fp ON pc.cid=fp.cid AND pc.chain_id=fp.chain_id
         WHERE pc.cid=$1::TEXT OR fp.cid=$1::TEXT
@@ -305,24 +740,143 @@ pub async fn cid_info(cid: String, psql: DbConn) -> Custom<Option<Json<String>>>
                         failed_chain_id: r.get(3),
                         failed_node_count: r.get(4),
                         failed_end_block: r.get(5),
+                        expires_at: Option::None,
                     })
                     .collect(),
             )
         })
         .await
     {
-        Ok(v) => Custom(Status::Ok, Option::Some(Json(json!(v).to_string()))),
+        Ok(mut v) => {
+            let providers = state.providers();
+            for c in v.iter_mut() {
+                let p = providers.iter().find(|p| Some(p.chain_id) == c.pinned_chain_id);
+                c.expires_at = match (p, c.pinned_end_block) {
+                    (Some(p), Some(b)) => p.block_timestamp(b),
+                    _ => Option::None,
+                };
+            }
+            Ok(Json(v))
+        }
         Err(e) => {
             error!("Error collecting pinned CIDs > {}", e);
-            return Custom(Status::InternalServerError, Option::None);
+            Err(ApiError::internal("failed to collect CID info"))
         }
     }
 }
 
+/// `within` of `expiring_cids` when none is given
+const EXPIRING_WITHIN: &str = "7d";
+
+/// Seconds of a duration like `90`, `30m`, `12h` or `7d`
+fn duration_sec(v: &str) -> Option<u64> {
+    let v = v.trim();
+    let (n, unit) = match v.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&v[..i], c),
+        _ => (v, 's'),
+    };
+    let mul = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        'w' => 604_800,
+        _ => return Option::None,
+    };
+    n.parse::<u64>().ok()?.checked_mul(mul)
+}
+
+/// List pinned CIDs of a chain whose pin ends within `within` (`7d` by default), soonest first.
+///
+/// `expires_at` is estimated from the block time observed on the chain
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "cid",
+    params(
+        ("within", Query, description = "Seconds or a duration like `30m`, `12h`, `7d`, `2w`"),
+        ("address", Query, description = "Only the CIDs paid by this donor"),
+    ),
+    responses(
+        (status = 200, description = "CIDs about to expire", body = [ExpiringCID]),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+        (status = 503, description = "Not ready to serve the request", body = ErrorResponse),
+    ),
+)]
+#[get("/cid/expiring?<chain_id>&<within>&<address>")]
+pub async fn expiring_cids(
+    chain_id: i64,
+    within: Option<String>,
+    address: Option<String>,
+    state: &State<types::State>,
+    psql: DbConn,
+) -> ApiResult<Vec<ExpiringCID>> {
+    let within = within.unwrap_or(EXPIRING_WITHIN.to_owned());
+    let within = match duration_sec(&within) {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request(format!("invalid 'within' '{}'", within))),
+    };
+    let provider = match state.providers().iter().find(|p| p.chain_id == chain_id) {
+        Some(v) => v.clone(),
+        None => return Err(ApiError::bad_request(format!("unknown chain '{}'", chain_id))),
+    };
+    let bn = { *provider.latest_block.lock().unwrap() };
+    let bn = match bn {
+        Some(v) => v,
+        None => return Err(ApiError::unavailable("latest block is not known yet")),
+    };
+    let to = bn.saturating_add(provider.blocks_within(within));
+
+    match psql
+        .run(move |client| db::get_expiring_cids(client, chain_id, bn, to, address))
+        .await
+    {
+        Ok(v) => Ok(Json(
+            v.into_iter()
+                .map(|c| {
+                    let end_block = c.end_block.unwrap();
+                    ExpiringCID {
+                        cid: c.cid.unwrap(),
+                        end_block,
+                        blocks_left: end_block - bn,
+                        expires_at: provider.block_timestamp(end_block),
+                    }
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            error!("Error collecting expiring CIDs > {}", e);
+            Err(ApiError::internal("failed to collect expiring CIDs"))
+        }
+    }
+}
+
+/// Prometheus metrics in the text exposition format
+#[utoipa::path(
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"),
+    ),
+)]
+#[get("/metrics")]
+pub async fn metrics() -> (ContentType, String) {
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        utils::metrics::metrics().render(),
+    )
+}
+
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "Watcher state per chain id", body = HashMap<u64, Monitoring>),
+    ),
+)]
 #[get("/monitoring")]
-pub async fn monitoring(state: &State<types::State>) -> Custom<Option<Json<String>>> {
+pub async fn monitoring(state: &State<types::State>) -> Json<HashMap<u64, Monitoring>> {
     let mon = { state.monitoring.clone().lock().unwrap().clone() };
-    Custom(Status::Ok, Option::Some(Json(json!(mon).to_string())))
+    Json(mon)
     // match psql
     //     .run(move |client: &mut Client| {
     //         let res = client.query_one(
@@ -348,3 +902,127 @@ pub async fn monitoring(state: &State<types::State>) -> Custom<Option<Json<Strin
     //     }
     // }
 }
+
+/// Repo usage of the IPFS nodes from their last `repo/stat`, `full` nodes get no new pins
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "monitoring",
+    responses(
+        (status = 200, description = "Capacity per node", body = [NodeCapacity]),
+    ),
+)]
+#[get("/monitoring/nodes")]
+pub async fn monitoring_nodes(state: &State<types::State>) -> Json<Vec<NodeCapacity>> {
+    let mut nodes: Vec<NodeCapacity> =
+        { state.node_capacity.lock().unwrap().values().cloned().collect() };
+    nodes.sort_by(|a, b| a.node.cmp(&b.node));
+    Json(nodes)
+}
+
+/// Monitoring events stored between `from` and `to` (unix ms), the last 24h by default, oldest
+/// first by default.
+///
+/// `kind` is one of `watcher`, `pin_batch`, `unpin_batch`, `retry_batch`, `socket_reconnect`
+/// or `nodes_full`
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "monitoring",
+    params(
+        ("from", Query, description = "Unix ms, 24h before `to` by default"),
+        ("to", Query, description = "Unix ms, now by default"),
+        ("order", Query, description = "`asc` or `desc`"),
+        ("cursor", Query, description = "`next_cursor` of the previous page"),
+    ),
+    responses(
+        (status = 200, description = "Page of stored monitoring events", body = HistoryPage),
+        (status = 400, description = "Invalid parameters", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse),
+    ),
+)]
+#[allow(clippy::too_many_arguments)]
+#[get("/monitoring/history?<chain_id>&<from>&<to>&<kind>&<order>&<limit>&<cursor>")]
+pub async fn monitoring_history(
+    chain_id: i64,
+    from: Option<i64>,
+    to: Option<i64>,
+    kind: Option<String>,
+    order: Option<String>,
+    limit: Option<i64>,
+    cursor: Option<String>,
+    psql: DbConn,
+) -> ApiResult<Page<HistoryEvent>> {
+    let to = to.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+    let from = from.unwrap_or(to - 86_400_000);
+    if from > to {
+        return Err(ApiError::bad_request("'from' is after 'to'"));
+    }
+    let desc = match pagination::is_desc(&order) {
+        Some(v) => v,
+        None => return Err(ApiError::bad_request("invalid 'order'")),
+    };
+    let keys: Vec<SortKey> = vec![("ts", KeyType::Int), ("id", KeyType::Int)];
+    let mut q = PagedQuery::default();
+    let base = format!(
+        "
+        SELECT chain_id, kind, event, ts, update_block, duration_ms, count, id
+        FROM monitoring_history
+        WHERE chain_id={}::BIGINT AND ts>={}::BIGINT AND ts<={}::BIGINT
+        ",
+        q.bind(chain_id),
+        q.bind(from),
+        q.bind(to)
+    );
+    if let Some(v) = kind {
+        let p = q.bind(v);
+        q.filter(format!("kind={}::TEXT", p));
+    }
+    if let Some(c) = &cursor {
+        if !q.after(c, &keys, desc) {
+            return Err(ApiError::bad_request("invalid 'cursor'"));
+        }
+    }
+
+    let limit = pagination::limit(limit);
+    match psql
+        .run(move |client: &mut Client| {
+            q.run(client, &base, &keys, desc, limit, |r| HistoryEvent {
+                chain_id: r.get(0),
+                kind: r.get(1),
+                event: r.get(2),
+                ts: r.get(3),
+                update_block: r.get(4),
+                duration_ms: r.get(5),
+                count: r.get(6),
+            })
+        })
+        .await
+    {
+        Ok::<Page<HistoryEvent>, postgres::Error>(v) => Ok(Json(v)),
+        Err(e) => {
+            error!("Error collecting monitoring history > {}", e);
+            Err(ApiError::internal("failed to collect monitoring history"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration_sec("90"), Some(90));
+        assert_eq!(duration_sec(" 90s "), Some(90));
+        assert_eq!(duration_sec("30m"), Some(1_800));
+        assert_eq!(duration_sec("12h"), Some(43_200));
+        assert_eq!(duration_sec(EXPIRING_WITHIN), Some(604_800));
+        assert_eq!(duration_sec("2w"), Some(1_209_600));
+        assert_eq!(duration_sec("0d"), Some(0));
+        assert_eq!(duration_sec(""), None);
+        assert_eq!(duration_sec("d"), None);
+        assert_eq!(duration_sec("7y"), None);
+        assert_eq!(duration_sec("-7d"), None);
+        assert_eq!(duration_sec("1.5h"), None);
+        assert_eq!(duration_sec(&format!("{}w", u64::MAX)), None);
+    }
+}
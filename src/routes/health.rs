@@ -0,0 +1,169 @@
+use futures_util::future::join_all;
+use rocket::{http::Status, response::status::Custom, serde::json::Json, State};
+use serde_json::{json, Value};
+
+use crate::services::contract_watcher;
+use crate::types::{self, config::IPFSNode, DbConn};
+
+/// Seconds to wait for the database and each IPFS node
+const CHECK_TIMEOUT_SEC: u64 = 5;
+
+/// The process is up and serving requests.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Process is up", body = Object),
+    ),
+)]
+#[get("/healthz")]
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn check_db(psql: Option<DbConn>) -> Value {
+    let psql = match psql {
+        Some(v) => v,
+        None => return json!({ "ok": false, "error": "no connection available" }),
+    };
+    let res = rocket::tokio::time::timeout(
+        rocket::tokio::time::Duration::from_secs(CHECK_TIMEOUT_SEC),
+        psql.run(|client| client.query_one("SELECT 1", &[])),
+    )
+    .await;
+    match res {
+        Ok(Ok(_)) => json!({ "ok": true }),
+        Ok(Err(e)) => json!({ "ok": false, "error": e.to_string() }),
+        Err(_) => json!({ "ok": false, "error": "timeout" }),
+    }
+}
+
+async fn check_node(node: IPFSNode, drained: bool) -> Value {
+    let client = reqwest::Client::new();
+    let mut req = client
+        .post(format!("{}/api/v0/id", &node.api_url))
+        .timeout(std::time::Duration::from_secs(CHECK_TIMEOUT_SEC));
+    if let Some(login) = &node.login {
+        req = req.basic_auth(login, node.password.clone());
+    }
+    match req.send().await {
+        Ok(v) if v.status().is_success() => {
+            json!({ "node": node.api_url, "ok": true, "drained": drained })
+        }
+        Ok(v) => json!({
+            "node": node.api_url,
+            "ok": false,
+            "drained": drained,
+            "error": format!("status {}", v.status()),
+        }),
+        Err(e) => json!({
+            "node": node.api_url,
+            "ok": false,
+            "drained": drained,
+            "error": e.to_string(),
+        }),
+    }
+}
+
+/// Chain head is moving and every event watcher is close to it
+fn check_chains(state: &types::State) -> Vec<Value> {
+    let mon = { state.monitoring.lock().unwrap().clone() };
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut chains = vec![];
+    for p in state.providers().iter() {
+        let mut problems = vec![];
+        let m = mon.get(&(p.chain_id as u64));
+
+        let max_age_sec = std::cmp::max(
+            state.readiness.head_stale_blocks * p.block_time_sec,
+            2 * p.block_update_sec,
+        );
+        let head_age_sec = m.map(|m| (now - m.head_change_time) / 1000);
+        match head_age_sec {
+            Some(age) if age <= max_age_sec as i64 => {}
+            Some(age) => problems.push(format!("head did not move for '{}' sec.", age)),
+            None => problems.push("head not seen yet".to_owned()),
+        }
+
+        let head = m.map_or(0, |m| m.current_block as i64);
+        let mut lag = serde_json::Map::new();
+        for event in contract_watcher::EVENTS {
+            match m.and_then(|m| m.indexed_blocks.get(event)) {
+                Some(b) => {
+                    let l = head - b;
+                    if l > state.readiness.max_indexer_lag_blocks && !p.is_paused() {
+                        problems.push(format!("'{}' is '{}' blocks behind", event, l));
+                    }
+                    lag.insert(event.to_owned(), json!(l));
+                }
+                None => {
+                    if !p.is_paused() {
+                        problems.push(format!("'{}' not indexed yet", event));
+                    }
+                    lag.insert(event.to_owned(), Value::Null);
+                }
+            }
+        }
+
+        chains.push(json!({
+            "chain_id": p.chain_id,
+            "chain_name": p.chain_name,
+            "ok": problems.is_empty(),
+            "paused": p.is_paused(),
+            "head_block": head,
+            "head_age_sec": head_age_sec,
+            "lag_blocks": lag,
+            "problems": problems,
+        }));
+    }
+    chains
+}
+
+/// Ready when the database answers, at least one IPFS node is reachable and,
+/// unless running `only_api`, every chain head is fresh and its indexer not lagging.
+///
+/// Returns 503 with the same breakdown when not ready
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready, with a per component breakdown", body = Object),
+        (status = 503, description = "Not ready, same breakdown", body = Object),
+    ),
+)]
+#[get("/readyz")]
+pub async fn readyz(
+    state: &State<types::State>,
+    psql: Option<DbConn>,
+) -> Custom<Json<Value>> {
+    let db = check_db(psql).await;
+
+    let drained = { state.drained_nodes.lock().unwrap().clone() };
+    let nodes = join_all(state.nodes().iter().map(|n| {
+        let is_drained = drained.contains(&n.api_url);
+        check_node(n.clone(), is_drained)
+    }))
+    .await;
+
+    let chains = if state.only_api {
+        vec![]
+    } else {
+        check_chains(state)
+    };
+
+    let ready = db["ok"] == true
+        && nodes.iter().any(|n| n["ok"] == true)
+        && chains.iter().all(|c| c["ok"] == true);
+    let status = if ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    Custom(
+        status,
+        Json(json!({
+            "ready": ready,
+            "db": db,
+            "ipfs_nodes": nodes,
+            "chains": chains,
+        })),
+    )
+}
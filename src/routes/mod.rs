@@ -1,3 +1,9 @@
+pub mod admin;
+pub mod auth;
 pub mod cors;
+pub mod docs;
+pub mod errors;
+pub mod events;
 pub mod handlers;
+pub mod health;
 pub mod proxy;
@@ -12,11 +12,16 @@ use rocket::{
 };
 use serde_json::Value;
 
+use std::time::Instant;
+
+use crate::types::auth::AuthUser;
+use crate::utils::metrics::{self, metrics};
 use crate::utils::proxy::{upload_to_ipfs, get_from_ipfs};
 
 pub struct ProxyUploadData {
     pub data: Value,
 }
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum ProxyError {
     ProxyFailed(String),
@@ -27,11 +32,17 @@ impl<'r> FromData<'r> for ProxyUploadData {
     type Error = ProxyError;
 
     async fn from_data(r: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
-        match upload_to_ipfs(r, data).await {
+        let start = Instant::now();
+        let res = upload_to_ipfs(r, data).await;
+        metrics()
+            .proxy_request_duration
+            .with_label_values(&["upload", metrics::result(res.is_ok())])
+            .observe(start.elapsed().as_secs_f64());
+        match res {
             Ok(v) => Outcome::Success(Self { data: v }),
             Err(e) =>{
                 error!("Uploading to IPFS: {e}");
-                Outcome::Failure((Status::InternalServerError, ProxyError::ProxyFailed("Failed to upload to IPFS".to_owned())))
+                Outcome::Error((Status::InternalServerError, ProxyError::ProxyFailed("Failed to upload to IPFS".to_owned())))
             }
         }
     }
@@ -42,8 +53,26 @@ impl<'r> FromData<'r> for ProxyUploadData {
 /// Use multipart form for payload where key=file and value=blob
 ///
 /// Wrap the files in dir with query `?dir=true`
+///
+/// Requests with a session token are logged with the uploader's address
+#[utoipa::path(
+    context_path = "/v0",
+    tag = "ipfs",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    params(
+        ("dir", Query, description = "Wrap the files in a directory"),
+    ),
+    responses(
+        (status = 200, description = "IPFS `add` response with the `Name`, `Hash` and `Size` of the added entry", body = Object),
+        (status = 500, description = "Upload to IPFS failed"),
+    ),
+)]
 #[post("/file/upload?<dir>", data = "<data>")]
-pub async fn upload(dir: Option<bool>, data: ProxyUploadData) -> status::Custom<Json<Value>> {
+#[allow(unused_variables)] // `dir` is read by the `ProxyUploadData` guard
+pub async fn upload(dir: Option<bool>, user: Option<AuthUser>, data: ProxyUploadData) -> status::Custom<Json<Value>> {
+    if let Some(u) = user {
+        info!("CHAIN '{}' > '{}' uploaded {}", u.chain_id, u.address, data.data);
+    }
     status::Custom(Status::Ok, Json(data.data))
 }
 
@@ -58,11 +87,17 @@ impl<'r> FromRequest<'r> for ProxyIpfsData {
     type Error = ProxyError;
 
     async fn from_request(r: &'r Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
-        match get_from_ipfs(r).await{
+        let start = Instant::now();
+        let res = get_from_ipfs(r).await;
+        metrics()
+            .proxy_request_duration
+            .with_label_values(&["ipfs", metrics::result(res.is_ok())])
+            .observe(start.elapsed().as_secs_f64());
+        match res{
             Ok(v)=>rocket::request::Outcome::Success(Self { data: v }),
             Err(e)=>{
                 error!("Error get ipfs: {e}");
-                rocket::request::Outcome::Failure((Status::InternalServerError, ProxyError::ProxyFailed("Failed to get data from IPFS".to_owned())))
+                rocket::request::Outcome::Error((Status::InternalServerError, ProxyError::ProxyFailed("Failed to get data from IPFS".to_owned())))
             }
         }
     }
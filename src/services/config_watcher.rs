@@ -0,0 +1,202 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{
+        self,
+        signal::unix::{signal, SignalKind},
+    },
+    Orbit, Rocket, Shutdown,
+};
+
+use crate::services::{contract_watcher, ipfs_watcher::IPFSService, providers::Providers, webhooks};
+use crate::types::{
+    config::{Config, IPFSNode},
+    monitoring::{Monitoring, NodeCapacity},
+    DbConn, State, Web3Node,
+};
+use crate::yaml_parser;
+
+/// Reloads the config on `SIGHUP` or, when `watch_config_sec` is set, when the file changes.
+///
+/// A config failing `yaml_parser::load_conf` is rejected and the running one is kept.
+/// IPFS nodes are swapped in place, chains that were added, removed or changed get their
+/// watchers stopped and started again. Changing `update_nodes_sec` or `retry_failed_cids_sec`
+/// restarts every chain. Webhook subscriptions are replaced, pending deliveries of a removed one or of
+/// one whose secret changed are dropped. Other fields need a restart of hosq.
+///
+/// Pins of a removed IPFS node are not moved to the remaining nodes. Once expired they are unpinned
+/// at the node URL stored with them, without credentials since the config does not have them
+/// anymore, failed unpins stay in `pinned_cids` and are tried again on the next pass.
+#[derive(Debug, Clone)]
+pub struct ConfigService {
+    pub path: String,
+    pub conf: Config,
+    pub only_api: bool,
+}
+
+struct Watched {
+    db: Arc<DbConn>,
+    shutdown: Shutdown,
+    nodes: Arc<Mutex<Arc<Vec<IPFSNode>>>>,
+    providers: Arc<Mutex<Arc<Vec<Web3Node>>>>,
+    drained: Arc<Mutex<HashSet<String>>>,
+    capacity: Arc<Mutex<HashMap<String, NodeCapacity>>>,
+    mon: Arc<Mutex<HashMap<u64, Monitoring>>>,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl ConfigService {
+    async fn reload(&self, w: &Watched, current: &Config) -> Result<Config, anyhow::Error> {
+        let conf = yaml_parser::load_conf(&self.path)?;
+
+        let old_entries = current.providers.clone().unwrap_or_default();
+        let new_entries = conf.providers.clone().unwrap_or_default();
+        let restart_all = conf.update_nodes_sec != current.update_nodes_sec
+            || conf.retry_failed_cids_sec != current.retry_failed_cids_sec;
+        let removed: Vec<_> = old_entries
+            .iter()
+            .filter(|p| restart_all || !new_entries.contains(p))
+            .cloned()
+            .collect();
+        let added: Vec<_> = new_entries
+            .iter()
+            .filter(|p| restart_all || !old_entries.contains(p))
+            .cloned()
+            .collect();
+
+        // connect to the new chains before touching the running ones
+        let providers_service = Providers {};
+        let started = providers_service.get_providers(added).await?;
+
+        if conf.webhooks != current.webhooks {
+            info!("CONFIG > Webhooks updated");
+            webhooks::set_subscriptions(conf.webhooks.clone().unwrap_or_default());
+        }
+
+        let new_nodes = conf.ipfs_nodes.clone().unwrap_or_default();
+        if current.ipfs_nodes.clone().unwrap_or_default() != new_nodes {
+            info!("CONFIG > IPFS nodes updated, total: '{}'", new_nodes.len());
+            *w.nodes.lock().unwrap() = Arc::new(new_nodes);
+        }
+
+        let running = { w.providers.lock().unwrap().clone() };
+        let mut kept = vec![];
+        for p in running.iter() {
+            let is_removed = removed.iter().any(|r| {
+                r.provider.eq(&p.url)
+                    && r.contracts().eq(&p.contracts)
+                    && r.listings().eq(&p.listings)
+            });
+            if is_removed {
+                warn!("CHAIN '{}' - '{}' > Stopping watchers", p.chain_name, p.chain_id);
+                p.stop();
+                w.mon.lock().unwrap().remove(&(p.chain_id as u64));
+            } else {
+                kept.push(p.clone());
+            }
+        }
+        kept.extend(started.iter().cloned());
+        {
+            *w.providers.lock().unwrap() = Arc::new(kept);
+        }
+
+        if !self.only_api {
+            let ipfs_service = IPFSService {
+                retry_failed_cids_sec: conf.retry_failed_cids_sec,
+                update_nodes_sec: conf.update_nodes_sec,
+                node_capacity_sec: conf.node_capacity_sec.unwrap_or(60),
+            };
+            for p in &started {
+                info!("CHAIN '{}' - '{}' > Starting watchers", p.chain_name, p.chain_id);
+                providers_service.watch_block(p.clone(), w.db.clone(), w.mon.clone());
+                contract_watcher::watch_contract(p, w.db.clone(), w.shutdown.clone(), w.mon.clone());
+                ipfs_service.watch_chain(
+                    p,
+                    w.db.clone(),
+                    w.nodes.clone(),
+                    w.drained.clone(),
+                    w.capacity.clone(),
+                );
+            }
+        }
+
+        Ok(conf)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for ConfigService {
+    fn info(&self) -> Info {
+        Info {
+            name: "Run config reload service",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let db = Arc::new(DbConn::get_one(rocket).await.expect("database mounted."));
+        let state = rocket.state::<State>().unwrap();
+        let w = Watched {
+            db,
+            shutdown: rocket.shutdown(),
+            nodes: state.nodes.clone(),
+            providers: state.providers.clone(),
+            drained: state.drained_nodes.clone(),
+            capacity: state.node_capacity.clone(),
+            mon: state.monitoring.clone(),
+        };
+        let this = self.clone();
+
+        tokio::spawn(async move {
+            let mut hup = match signal(SignalKind::hangup()) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("CONFIG > Failed to listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            let mut current = this.conf.clone();
+            let mut last_modified = modified(&this.path);
+            loop {
+                match current.watch_config_sec {
+                    Some(sec) if sec > 0 => {
+                        tokio::select! {
+                            _ = hup.recv() => {}
+                            _ = tokio::time::sleep(tokio::time::Duration::from_secs(sec)) => {
+                                if modified(&this.path) == last_modified {
+                                    continue;
+                                }
+                            }
+                        }
+                    }
+                    _ => {
+                        hup.recv().await;
+                    }
+                };
+                last_modified = modified(&this.path);
+
+                info!("CONFIG > Reloading '{}'", &this.path);
+                match this.reload(&w, &current).await {
+                    Ok(v) => {
+                        current = v;
+                        info!("CONFIG > Reloaded '{}'", &this.path);
+                    }
+                    Err(e) => {
+                        error!(
+                            "CONFIG > Rejected '{}', keeping the running config: {}",
+                            &this.path, e
+                        )
+                    }
+                }
+            }
+        });
+    }
+}
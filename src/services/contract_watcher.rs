@@ -1,26 +1,83 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::ops::Div;
 use std::str::FromStr;
+use std::sync::Mutex;
 use std::{sync::Arc, time::Instant};
 
-use crate::types::db::{EventAddProvider, EventUpdateValidBlock};
+use crate::types::db::{EventUpdateValidBlock, ProviderEvent, QuarantinedLog, RejectedEvent};
 use crate::types::errors::CustomError;
 use crate::types::{
-    monitoring::{Event, Monitoring},
-    DbConn, State,
+    config::PaymentCheck,
+    monitoring::{Event, Monitoring, KIND_WATCHER},
+    DbConn, State, Web3Node,
 };
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::{tokio, Orbit, Rocket};
+use rocket::{tokio, Orbit, Rocket, Shutdown};
+use ethabi::ethereum_types::U256;
 use web3::signing::keccak256;
-use web3::types::{BlockNumber, FilterBuilder, Log, H160, H256, U64};
+use web3::types::{BlockNumber, FilterBuilder, Log, TransactionId, H160, H256, U64};
 
 use crate::db;
+use crate::services::{events, history, webhooks};
+use crate::utils::{metrics::metrics, sanitize};
+
+pub const UPDATE_VALID_BLOCK: &str = "UpdateValidBlock(address,uint256,uint256,string)";
+pub const ADD_PROVIDER: &str = "AddProvider(address,uint256,uint256,string,string)";
+pub const UPDATE_PROVIDER_BLOCK_PRICE: &str = "UpdateProviderBlockPrice(uint256,uint256)";
+pub const UPDATE_PROVIDER_API_URL: &str = "UpdateProviderApiUrl(uint256,string)";
+pub const UPDATE_PROVIDER_ADDRESS: &str = "UpdateProviderAddress(uint256,address)";
+pub const UPDATE_PROVIDER_NAME: &str = "UpdateProviderName(uint256,string)";
+pub const EVENTS: [&str; 6] = [
+    UPDATE_VALID_BLOCK,
+    ADD_PROVIDER,
+    UPDATE_PROVIDER_BLOCK_PRICE,
+    UPDATE_PROVIDER_API_URL,
+    UPDATE_PROVIDER_ADDRESS,
+    UPDATE_PROVIDER_NAME,
+];
+
+/// ABI versions of the registry contract the handlers can decode, see `config::Contract`
+pub const ABI_VERSIONS: [u32; 1] = [1];
+
+/// Outcomes of the payment verification of `UpdateValidBlock` events, see `verify_payment`
+pub const PAYMENT_PAID: &str = "paid";
+pub const PAYMENT_UNDERPAID: &str = "underpaid";
+pub const PAYMENT_CAPPED: &str = "capped";
+pub const PAYMENT_UNVERIFIED: &str = "unverified";
+pub const PAYMENT_UNCHECKED: &str = "unchecked";
+
+/// Transactions whose payment is kept in `Web3Node::paid_wei` before it is emptied
+const PAID_WEI_CACHE_SIZE: usize = 1024;
+/// `unverified` events verified again per pass of the `UpdateValidBlock` watcher
+const REVERIFY_BATCH: i64 = 100;
 
 macro_rules! get_logs {
     ($name:expr => $provider:expr, $psql:expr, $filter:expr, $start_block:expr, $mon:expr => $f:expr) => {
         let mut start_block = $start_block;
+        // log a transient failure stopped at, the batch is retried from its block
+        let mut retry_from: Option<(U64, web3::types::U256)> = Option::None;
         loop{
             let start = Instant::now();
+            if $provider.is_stopped() {
+                info!("CHAIN '{}' - '{}' > Stopped watching '{}'",
+                &$provider.chain_name, &$provider.chain_id, $name);
+                return;
+            }
+            if $provider.is_paused() {
+                info!("CHAIN '{}' - '{}' > Paused, will sleep for '{}' sec. > '{}'",
+                &$provider.chain_name, &$provider.chain_id, &$provider.log_update_sec, $name);
+                tokio::time::sleep(tokio::time::Duration::from_secs(
+                    $provider.log_update_sec,
+                ))
+                .await;
+                continue;
+            }
+            if let Some(b) = $provider.take_resync($name) {
+                warn!("CHAIN '{}' - '{}' > Resyncing from block '{}' > '{}'",
+                &$provider.chain_name, &$provider.chain_id, b, $name);
+                start_block = b;
+            }
             let bn = {
                 $provider.latest_block.clone().lock().unwrap().clone()
             };
@@ -38,14 +95,9 @@ macro_rules! get_logs {
                 }
             };
 
-            match $provider.skip_old{
-                Some(v)=>{
-                    if v && bn-start_block > $provider.batch_size{
-                        start_block = bn-$provider.batch_size;
-                    }
-                }
-                None=>{}
-            };
+            if $provider.skip_old == Some(true) && bn-start_block > $provider.batch_size {
+                start_block = bn-$provider.batch_size;
+            }
 
             if start_block >= bn {
                 info!("CHAIN '{}' - '{}' > Synced at '{}' waiting for latest block, will sleep for '{} sec.' > '{}'",
@@ -62,6 +114,7 @@ macro_rules! get_logs {
             };
 
             info!("CHAIN '{}' - '{}' > Looking '{}' logs from block '{}'", &$provider.chain_name, $provider.chain_id, $name, $start_block);
+            let batch_from = start_block;
             let filter = if bn-start_block>$provider.batch_size{
                 let tf = $filter.clone().from_block(BlockNumber::Number(
                     U64::from_dec_str(start_block.to_string().as_str()).unwrap(),
@@ -71,7 +124,7 @@ macro_rules! get_logs {
                     )
                 )
                 .build();
-                start_block = start_block+$provider.batch_size;
+                start_block += $provider.batch_size;
                 tf
             } else {
                 let tf = $filter.clone().from_block(BlockNumber::Number(
@@ -98,17 +151,60 @@ macro_rules! get_logs {
                 }
             };
             let log_size = logs.len();
+            let mut failed = false;
             for l in logs {
-                // failed inserts will be retried in the next loop
-                match $f($psql.clone(), l, $provider.chain_id, $provider.provider_id).await{
-                    Err(e)=>{
-                        error!("CHAIN '{}' - '{}' > Failed to insert log '{}' > '{}'",
-                        &$provider.chain_name, &$provider.chain_id, e, $name);
+                if !$provider.is_registry_log(&l) {
+                    continue;
+                }
+                // handled before the failure being retried
+                if let (Some(r), Some(b), Some(i)) = (retry_from, l.block_number, l.log_index) {
+                    if (b, i) < r {
                         continue;
                     }
+                }
+                // malformed logs are quarantined, the watcher moves past them
+                let res = $f($psql.clone(), l.clone(), &$provider)
+                    .await
+                    .map_err(classify);
+                match res {
+                    Err((true, e))=>{
+                        quarantine($psql.clone(), $provider.chain_id, $name, l, e).await;
+                        {
+                            let mut data = $mon.lock().unwrap();
+                            data.entry($provider.chain_id as u64).or_insert(Monitoring::default()).quarantined_logs += 1;
+                        }
+                    }
+                    Err((false, e))=>{
+                        // the cursor must not move past it, the batch is retried from its block
+                        start_block = l.block_number.map_or(batch_from, |b| b.as_u64() as i64);
+                        retry_from = l.block_number.zip(l.log_index);
+                        error!("CHAIN '{}' - '{}' > Failed to insert log '{}', will retry from block '{}' > '{}'",
+                        &$provider.chain_name, &$provider.chain_id, e, start_block, $name);
+                        failed = true;
+                        break;
+                    }
                     _=>{}
                 };
             }
+            if failed {
+                tokio::time::sleep(tokio::time::Duration::from_secs(
+                    $provider.log_update_sec,
+                ))
+                .await;
+                continue;
+            }
+            retry_from = Option::None;
+            if $name == UPDATE_VALID_BLOCK {
+                reverify_payments($psql.clone(), &$provider).await;
+            }
+            {
+                let labels = [$provider.chain_id.to_string(), $name.to_owned()];
+                let labels = [labels[0].as_str(), labels[1].as_str()];
+                metrics().indexed_block.with_label_values(&labels).set(start_block);
+                metrics().log_batch_duration.with_label_values(&labels).observe(start.elapsed().as_secs_f64());
+            }
+            history::record($psql.clone(), $provider.chain_id, KIND_WATCHER, $name, bn,
+                start.elapsed().as_millis() as i64, log_size as i64).await;
             tokio::time::sleep(tokio::time::Duration::from_secs(
                 $provider.log_update_sec,
                 ))
@@ -117,6 +213,7 @@ macro_rules! get_logs {
             {
                 let mut data = $mon.lock().unwrap();
                 let obj = data.entry($provider.chain_id as u64).or_insert(Monitoring::default());
+                obj.indexed_blocks.insert($name.to_owned(), start_block);
                 if obj.events.len()>=30{
                     obj.events.remove(0);
                 }
@@ -152,16 +249,20 @@ macro_rules! watch_event {
                 }
             };
 
-            let contract_address = match H160::from_str(&provider.contract_address){
-                Ok(v)=>v,
-                Err(e)=>{error!(
-                    "CHAIN '{}' - '{}' > '{}', Invalid contract address '{}' > {}",
-                    provider.chain_name, provider.chain_id, $topic, &provider.contract_address, e
-                ); r_off.notify(); return}
-            };
+            let mut addresses = vec![];
+            for c in &provider.contracts {
+                match H160::from_str(c.address.trim_start_matches("0x")){
+                    Ok(v)=>addresses.push(v),
+                    Err(e)=>{error!(
+                        "CHAIN '{}' - '{}' > '{}', Invalid contract address '{}' > {}",
+                        provider.chain_name, provider.chain_id, $topic, &c.address, e
+                    ); r_off.notify(); return}
+                };
+            }
             let event = H256::from_slice(&keccak256($topic.as_bytes()));
+            // one filter over every deployment, logs outside a deployment's blocks are skipped
             let filter = FilterBuilder::default()
-                .address(vec![contract_address])
+                .address(addresses)
                 .topics(Some(vec![event]), None, None, None);
 
             info!(
@@ -174,12 +275,369 @@ macro_rules! watch_event {
     };
 }
 
+fn decode(types: &[ethabi::ParamType], l: &Log) -> Result<Vec<ethabi::Token>, CustomError> {
+    ethabi::decode(types, &l.data.0)
+        .map_err(|e| CustomError::Malformed(format!("decoding data > {}", e)))
+}
+
+fn block_number(l: &Log) -> Result<i64, CustomError> {
+    match l.block_number {
+        Some(v) => Ok(v.as_u64() as i64),
+        None => Err(CustomError::Malformed("log has no block number".to_owned())),
+    }
+}
+
+fn uint(t: &ethabi::Token) -> Result<U256, CustomError> {
+    t.clone()
+        .into_uint()
+        .ok_or_else(|| CustomError::Malformed(format!("'{}' is not a uint", t)))
+}
+
+fn to_i64(v: U256) -> Result<i64, CustomError> {
+    if v.bits() > 63 {
+        return Err(CustomError::Malformed(format!("'{}' does not fit in i64", v)));
+    }
+    Ok(v.low_u64() as i64)
+}
+
+/// `(true, reason)` when the log itself is at fault, see `quarantine`
+fn classify(e: Box<dyn Error>) -> (bool, String) {
+    (e.downcast_ref::<CustomError>().is_some(), e.to_string())
+}
+
+/// Stores a log its handler could not decode, it can be reprocessed through
+/// `/v0/admin/quarantine/reprocess` once the handler is fixed
+async fn quarantine(psql: Arc<DbConn>, chain_id: i64, event: &str, l: Log, reason: String) {
+    warn!(
+        "CHAIN '{}' > QUARANTINED '{}' log in tx '{:?}' > {}",
+        chain_id, event, l.transaction_hash, reason
+    );
+    metrics()
+        .quarantined_logs
+        .with_label_values(&[&chain_id.to_string(), event])
+        .inc();
+    let q = QuarantinedLog {
+        id: 0,
+        chain_id,
+        event: event.to_owned(),
+        block_number: l.block_number.map(|b| b.as_u64() as i64),
+        tx_hash: l.transaction_hash.map(|h| format!("{:?}", h)),
+        topics: l.topics.iter().map(|t| format!("{:?}", t)).collect(),
+        data: hex::encode(&l.data.0),
+        log: serde_json::to_value(&l).unwrap_or_default(),
+        error: reason,
+        ts: chrono::Utc::now().timestamp_millis(),
+        reprocessed_at: Option::None,
+    };
+    if let Err(e) = psql
+        .run(move |client| db::add_quarantined_log(client, &q))
+        .await
+    {
+        error!(
+            "CHAIN '{}' > ERROR storing quarantined '{}' log: {}",
+            chain_id, event, e
+        );
+    }
+}
+
+/// Runs the handler of `event`, one of `EVENTS`, on a log
+pub async fn process_log(
+    psql: Arc<DbConn>,
+    event: &str,
+    l: Log,
+    provider: &Web3Node,
+) -> Result<(), Box<dyn Error>> {
+    match event {
+        UPDATE_VALID_BLOCK => update_valid_block(psql, l, provider).await,
+        ADD_PROVIDER => update_add_provider(psql, l, provider).await,
+        UPDATE_PROVIDER_BLOCK_PRICE => update_provider_block_price(psql, l, provider).await,
+        UPDATE_PROVIDER_API_URL => update_provider_api_url(psql, l, provider).await,
+        UPDATE_PROVIDER_ADDRESS => update_provider_owner(psql, l, provider).await,
+        UPDATE_PROVIDER_NAME => update_provider_name(psql, l, provider).await,
+        _ => Err(Box::new(CustomError::Malformed(format!(
+            "unknown event '{}'",
+            event
+        )))),
+    }
+}
+
+/// Records an event dropped by `utils::sanitize`, it is not indexed
+async fn reject(psql: Arc<DbConn>, chain_id: i64, event: &str, l: &Log, reason: anyhow::Error) {
+    warn!(
+        "CHAIN '{}' > REJECTED '{}' event in tx '{:?}' > {}",
+        chain_id, event, l.transaction_hash, reason
+    );
+    metrics()
+        .rejected_events
+        .with_label_values(&[&chain_id.to_string(), event])
+        .inc();
+    let rejected = RejectedEvent {
+        chain_id,
+        event: event.to_owned(),
+        update_block: l.block_number.map(|b| b.as_u64() as i64),
+        tx_hash: l.transaction_hash.map(|h| format!("{:?}", h)),
+        data: hex::encode(&l.data.0),
+        reason: reason.to_string(),
+        ts: chrono::Utc::now().timestamp_millis(),
+    };
+    if let Err(e) = psql
+        .run(move |client| db::add_rejected_event(client, &rejected))
+        .await
+    {
+        error!(
+            "CHAIN '{}' > ERROR storing rejected '{}' event: {}",
+            chain_id, event, e
+        );
+    }
+}
+
+/// `end_block` to index an `UpdateValidBlock` event with and what its transaction paid
+struct Payment {
+    end_block: i64,
+    claimed_end_block: Option<i64>,
+    paid_wei: Option<String>,
+    paid_end_block: Option<i64>,
+    status: &'static str,
+}
+
+/// Wei paid by the transaction of `l` for it, a transaction emitting several
+/// `UpdateValidBlock` logs pays for each of them evenly. Cached by transaction so the
+/// other logs of a batch pinning several CIDs don't fetch it again
+async fn paid_wei(provider: &Web3Node, l: &Log) -> Result<web3::types::U256, String> {
+    let tx_hash = l.transaction_hash.ok_or("log has no transaction hash")?;
+    if let Some(v) = { provider.paid_wei.lock().unwrap().get(&tx_hash).cloned() } {
+        return Ok(v);
+    }
+
+    let web3 = { provider.web3.lock().unwrap().clone() };
+    let eth = web3.eth();
+    let (tx, receipt) = futures_util::future::try_join(
+        eth.transaction(TransactionId::Hash(tx_hash)),
+        eth.transaction_receipt(tx_hash),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    let tx = tx.ok_or("transaction not found")?;
+    let receipt = receipt.ok_or("transaction receipt not found")?;
+    let paid = if receipt.status == Some(U64::zero()) {
+        web3::types::U256::zero()
+    } else {
+        let logs = receipt
+            .logs
+            .iter()
+            .filter(|r| r.address == l.address && r.topics.first() == l.topics.first())
+            .count();
+        tx.value / web3::types::U256::from(std::cmp::max(logs, 1))
+    };
+
+    {
+        let mut cache = provider.paid_wei.lock().unwrap();
+        if cache.len() >= PAID_WEI_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(tx_hash, paid);
+    }
+    Ok(paid)
+}
+
+/// Checks the `end_block` of an `UpdateValidBlock` event against the value of its transaction.
+///
+/// The payment buys `paid_wei / block_price` blocks at the provider's price as of `update_block`,
+/// counted from the CID's previous `end_block` when it is a top up. Events that can not be
+/// verified, because the transaction or the provider can not be fetched, are indexed as is and
+/// marked `unverified` until `reverify_payments` settles them
+async fn verify_payment(
+    psql: &Arc<DbConn>,
+    provider: &Web3Node,
+    provider_id: i64,
+    l: &Log,
+    cid: &str,
+    update_block: i64,
+    end_block: i64,
+) -> Payment {
+    let mut p = Payment {
+        end_block,
+        claimed_end_block: Option::None,
+        paid_wei: Option::None,
+        paid_end_block: Option::None,
+        status: PAYMENT_UNCHECKED,
+    };
+    if provider.payment_check == PaymentCheck::Off {
+        return p;
+    }
+    p.status = PAYMENT_UNVERIFIED;
+
+    let paid = match paid_wei(provider, l).await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!(
+                "CHAIN '{}' > Could not verify payment of '{}' in tx '{:?}' > {}",
+                provider.chain_id, cid, l.transaction_hash, e
+            );
+            return p;
+        }
+    };
+    p.paid_wei = Option::Some(paid.to_string());
+
+    let (chain_id, c) = (provider.chain_id, cid.to_owned());
+    let res = psql
+        .run(move |client| {
+            let price = db::get_provider_block_price_at(client, chain_id, provider_id, update_block)?;
+            let base = db::get_cid_end_block_before(client, chain_id, provider_id, &c, update_block)?;
+            Ok::<_, postgres::Error>((price, base))
+        })
+        .await;
+    let (price, base) = match res {
+        Ok((Some(price), base)) => (price, base),
+        Ok((None, _)) => {
+            warn!(
+                "CHAIN '{}' > Could not verify payment of '{}', provider '{}' is not indexed yet",
+                chain_id, cid, provider_id
+            );
+            return p;
+        }
+        Err(e) => {
+            error!(
+                "CHAIN '{}' > ERROR collecting block price to verify payment of '{}' > {}",
+                chain_id, cid, e
+            );
+            return p;
+        }
+    };
+    let start_block = std::cmp::max(update_block, base.unwrap_or(update_block));
+    settle(p, provider.payment_check, paid, price, start_block)
+}
+
+/// Verifies again the `unverified` events whose provider price got indexed since, run after every
+/// pass of the `UpdateValidBlock` watcher. They stay `unverified` if it fails again
+async fn reverify_payments(psql: Arc<DbConn>, provider: &Web3Node) {
+    if provider.payment_check == PaymentCheck::Off {
+        return;
+    }
+    let chain_id = provider.chain_id;
+    let events = match psql
+        .run(move |client| db::get_unverified_payments(client, chain_id, REVERIFY_BATCH))
+        .await
+    {
+        Ok(v) => v,
+        Err(e) => {
+            error!(
+                "CHAIN '{}' > ERROR collecting unverified payments > {}",
+                chain_id, e
+            );
+            return;
+        }
+    };
+    for mut event in events {
+        let l = match indexed_log(provider, &event).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!(
+                    "CHAIN '{}' > Could not verify payment of '{}' in tx '{:?}' > {}",
+                    chain_id, event.cid, event.tx_hash, e
+                );
+                continue;
+            }
+        };
+        let payment = verify_payment(
+            &psql,
+            provider,
+            event.provider_id,
+            &l,
+            &event.cid,
+            event.update_block,
+            event.end_block,
+        )
+        .await;
+        if payment.status == PAYMENT_UNVERIFIED {
+            continue;
+        }
+        metrics()
+            .payments
+            .with_label_values(&[&chain_id.to_string(), payment.status])
+            .inc();
+        if payment.status == PAYMENT_UNDERPAID || payment.status == PAYMENT_CAPPED {
+            warn!(
+                "CHAIN '{}' > '{}' PAYMENT of '{}' by '{}' covers till block '{:?}', end block '{}'",
+                chain_id, payment.status, event.cid, event.donor, payment.paid_end_block, event.end_block
+            );
+        }
+        event.end_block = payment.end_block;
+        event.claimed_end_block = payment.claimed_end_block;
+        event.paid_wei = payment.paid_wei;
+        event.paid_end_block = payment.paid_end_block;
+        event.payment = Option::Some(payment.status.to_owned());
+        let cid = event.cid.clone();
+        if let Err(e) = psql.run(move |client| db::set_payment(client, &event)).await {
+            error!(
+                "CHAIN '{}' > ERROR storing verified payment of '{}' > {}",
+                chain_id, cid, e
+            );
+        }
+    }
+}
+
+/// Log of an indexed event, from the receipt of its transaction
+async fn indexed_log(provider: &Web3Node, event: &EventUpdateValidBlock) -> Result<Log, String> {
+    let tx_hash = event.tx_hash.as_deref().ok_or("event has no transaction hash")?;
+    let tx_hash = H256::from_str(tx_hash.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+    let log_index = event.log_index.ok_or("event has no log index")?;
+
+    let web3 = { provider.web3.lock().unwrap().clone() };
+    let receipt = web3
+        .eth()
+        .transaction_receipt(tx_hash)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("transaction receipt not found")?;
+    receipt
+        .logs
+        .into_iter()
+        .find(|l| l.log_index == Option::Some(web3::types::U256::from(log_index as u64)))
+        .ok_or_else(|| format!("log '{}' not in the transaction receipt", log_index))
+}
+
+/// Compares the `end_block` of `p` with the blocks `paid` wei buy at `price` gwei per block from
+/// `start_block` on, a free provider is always paid
+fn settle(
+    mut p: Payment,
+    check: PaymentCheck,
+    paid: web3::types::U256,
+    price: i64,
+    start_block: i64,
+) -> Payment {
+    if price <= 0 {
+        p.status = PAYMENT_PAID;
+        return p;
+    }
+
+    let blocks = paid / (web3::types::U256::from(price as u64) * web3::types::U256::exp10(9));
+    let blocks = if blocks.bits() > 62 {
+        i64::MAX / 2
+    } else {
+        blocks.low_u64() as i64
+    };
+    let paid_end_block = start_block + blocks;
+    p.paid_end_block = Option::Some(paid_end_block);
+
+    if p.end_block <= paid_end_block {
+        p.status = PAYMENT_PAID;
+    } else if check == PaymentCheck::Cap {
+        p.status = PAYMENT_CAPPED;
+        p.claimed_end_block = Option::Some(p.end_block);
+        p.end_block = paid_end_block;
+    } else {
+        p.status = PAYMENT_UNDERPAID;
+    }
+    p
+}
+
 pub async fn update_valid_block(
     psql: Arc<DbConn>,
     l: Log,
-    chain_id: i64,
-    cur_provider_id: i64,
+    provider: &Web3Node,
 ) -> Result<(), Box<dyn Error>> {
+    let chain_id = provider.chain_id;
     if l.data.0.len() < 96 {
         return Err(Box::new(CustomError::Inequality(format!(
             "update_valid_block: data len {:?} !>= 96",
@@ -187,27 +645,33 @@ pub async fn update_valid_block(
         ))));
     }
 
-    let dec_d = ethabi::decode(
+    let dec_d = decode(
         &[
             ethabi::ParamType::Address,
             ethabi::ParamType::Uint(256),
             ethabi::ParamType::Uint(256),
             ethabi::ParamType::String,
         ],
-        &l.data.0,
-    )
-    .unwrap();
-    // let block: i64 = l.block_number.unwrap().low_u64().try_into().unwrap();
-    let p_id = dec_d[2].clone().into_uint().unwrap().as_u64() as i64;
-    if p_id != cur_provider_id {
-        info!("CHAIN '{}' -> GOT 'update_valid_block' Event for provider '{}', I'am '{}', Not updating", chain_id, p_id, cur_provider_id);
+        &l,
+    )?;
+    let p_id = to_i64(uint(&dec_d[2])?)?;
+    if provider.listing(p_id).is_none() {
+        info!("CHAIN '{}' -> GOT 'update_valid_block' Event for provider '{}', not one of my listings, Not updating", chain_id, p_id);
         return Ok(());
     }
 
-    let donor = format!("0x{}", dec_d[0].to_string());
-    let update_block = (&l.block_number.unwrap()).as_u64() as i64;
-    let end_block = dec_d[1].clone().into_uint().unwrap().as_u64() as i64;
-    let cid = dec_d[3].to_string();
+    let donor = format!("0x{}", dec_d[0]);
+    let update_block = block_number(&l)?;
+    let (tx_hash, log_index) = log_key(&l)?;
+    let end_block = to_i64(uint(&dec_d[1])?)?;
+    let cid_original = dec_d[3].to_string();
+    let cid = match sanitize::cid(&cid_original) {
+        Ok(v) => v,
+        Err(e) => {
+            reject(psql, chain_id, UPDATE_VALID_BLOCK, &l, e).await;
+            return Ok(());
+        }
+    };
 
     info!(
         "CHAIN '{}' -> GOT 'update_valid_block' Event :: {:?}, {:?}, {:?}, {:?}, {:?} :: {:?}",
@@ -216,26 +680,114 @@ pub async fn update_valid_block(
         &update_block,
         &end_block,
         &p_id,
-        &cid,
+        &cid_original,
         &l.data.0.len()
     );
 
+    let payment = verify_payment(&psql, provider, p_id, &l, &cid, update_block, end_block).await;
+    metrics()
+        .payments
+        .with_label_values(&[&chain_id.to_string(), payment.status])
+        .inc();
+    if payment.status == PAYMENT_UNDERPAID || payment.status == PAYMENT_CAPPED {
+        warn!(
+            "CHAIN '{}' > '{}' PAYMENT of '{}' by '{}' covers till block '{:?}', end block '{}'",
+            chain_id, payment.status, cid, donor, payment.paid_end_block, end_block
+        );
+    }
+    let (end_block, status) = (payment.end_block, payment.status);
+
+    let (c, o, d) = (cid.clone(), cid_original.clone(), donor.clone());
     let res: Result<_, postgres::Error> = psql
         .run(move |client| {
             db::add_valid_block(
                 client,
                 EventUpdateValidBlock {
                     chain_id,
-                    cid,
-                    donor,
+                    provider_id: p_id,
+                    cid: c,
+                    cid_original: o,
+                    donor: d,
                     update_block,
                     end_block,
                     manual_add: Option::Some(false),
+                    claimed_end_block: payment.claimed_end_block,
+                    paid_wei: payment.paid_wei,
+                    paid_end_block: payment.paid_end_block,
+                    payment: Option::Some(payment.status.to_owned()),
+                    tx_hash: Option::Some(tx_hash),
+                    log_index: Option::Some(log_index),
                 },
             )
         })
         .await;
 
+    match res {
+        Ok(v) => {
+            // 0 when the log was already indexed
+            if v > 0 {
+                events::publish(
+                    psql.clone(),
+                    webhooks::EVENT_CID_INDEXED,
+                    chain_id,
+                    &cid,
+                    Option::Some(donor),
+                    serde_json::json!({
+                        "update_block": update_block,
+                        "end_block": end_block,
+                        "cid_original": cid_original,
+                        "provider_id": p_id,
+                        "payment": status,
+                    }),
+                )
+                .await;
+            }
+            Ok(())
+        }
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// `(tx_hash, log_index)` a log is keyed on once indexed
+fn log_key(l: &Log) -> Result<(String, i64), CustomError> {
+    let tx_hash = match l.transaction_hash {
+        Some(v) => format!("{:?}", v),
+        None => return Err(CustomError::Malformed("log has no transaction hash".to_owned())),
+    };
+    let log_index = match l.log_index {
+        Some(v) if v.bits() <= 63 => v.low_u64() as i64,
+        _ => return Err(CustomError::Malformed("log has no valid log index".to_owned())),
+    };
+    Ok((tx_hash, log_index))
+}
+
+/// `provider_events` row of a log, the handler sets the fields its event changes
+fn provider_event(
+    event: &str,
+    l: &Log,
+    chain_id: i64,
+    provider_id: i64,
+) -> Result<ProviderEvent, CustomError> {
+    let (tx_hash, log_index) = log_key(l)?;
+    Ok(ProviderEvent {
+        chain_id,
+        provider_id,
+        event: event.split('(').next().unwrap_or(event).to_owned(),
+        update_block: block_number(l)?,
+        tx_hash,
+        log_index,
+        owner: Option::None,
+        block_price_gwei: Option::None,
+        api_url: Option::None,
+        name: Option::None,
+    })
+}
+
+async fn add_provider_event(psql: Arc<DbConn>, event: ProviderEvent) -> Result<(), Box<dyn Error>> {
+    let res: Result<_, postgres::Error> = psql
+        .run(move |client| db::add_provider_event(client, &event))
+        .await;
+
     match res {
         Ok(_) => Ok(()),
         Err(e) => Err(Box::new(e)),
@@ -245,9 +797,9 @@ pub async fn update_valid_block(
 pub async fn update_add_provider(
     psql: Arc<DbConn>,
     l: Log,
-    chain_id: i64,
-    _cur_provider_id: i64,
+    provider: &Web3Node,
 ) -> Result<(), Box<dyn Error>> {
+    let chain_id = provider.chain_id;
     if l.data.0.len() < 96 {
         return Err(Box::new(CustomError::Inequality(format!(
             "update_add_provider: data len {:?} !>= 96",
@@ -255,7 +807,7 @@ pub async fn update_add_provider(
         ))));
     }
 
-    let dec_d = ethabi::decode(
+    let dec_d = decode(
         &[
             ethabi::ParamType::Address,
             ethabi::ParamType::Uint(256),
@@ -263,58 +815,44 @@ pub async fn update_add_provider(
             ethabi::ParamType::String,
             ethabi::ParamType::String,
         ],
-        &l.data.0,
-    )
-    .unwrap();
+        &l,
+    )?;
 
     let owner: String = format!("0x{}", dec_d[0]);
-    let update_block = (&l.block_number.unwrap()).as_u64() as i64;
-    let provider_id = dec_d[1].clone().into_uint().unwrap().as_u64() as i64;
-    let block_price_gwei = dec_d[2]
-        .clone()
-        .into_uint()
-        .unwrap()
-        .div(ethabi::ethereum_types::U256::from_dec_str("1000000000").unwrap())
-        .as_u64() as i64;
+    let update_block = block_number(&l)?;
+    let provider_id = to_i64(uint(&dec_d[1])?)?;
+    let block_price_gwei = to_i64(uint(&dec_d[2])?.div(U256::exp10(9)))?;
 
     // println!("bb {:?}, {}", dec_d, &l.data.0.len());
-    let api_url = dec_d[3].to_string();
-    let name = dec_d[4].to_string();
+    let (api_url, name) = match (
+        sanitize::api_url(&dec_d[3].to_string()),
+        sanitize::name(&dec_d[4].to_string()),
+    ) {
+        (Ok(u), Ok(n)) => (u, n),
+        (Err(e), _) | (_, Err(e)) => {
+            reject(psql, chain_id, ADD_PROVIDER, &l, e).await;
+            return Ok(());
+        }
+    };
 
     info!(
         "CHAIN '{}' -> GOT 'update_add_provider' Event :: {}, {}, {}, {}, {} : {}",
         &chain_id, &owner, &update_block, &provider_id, &block_price_gwei, &api_url, name
     );
-    // Ok(())
-    let res: Result<_, postgres::Error> = psql
-        .run(move |client| {
-            db::add_provider(
-                client,
-                EventAddProvider {
-                    chain_id,
-                    update_block,
-                    owner,
-                    provider_id,
-                    block_price_gwei,
-                    api_url,
-                    name,
-                },
-            )
-        })
-        .await;
-
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e)),
-    }
+    let mut event = provider_event(ADD_PROVIDER, &l, chain_id, provider_id)?;
+    event.owner = Option::Some(owner.to_lowercase());
+    event.block_price_gwei = Option::Some(block_price_gwei);
+    event.api_url = Option::Some(api_url);
+    event.name = Option::Some(name);
+    add_provider_event(psql, event).await
 }
 
 pub async fn update_provider_block_price(
     psql: Arc<DbConn>,
     l: Log,
-    chain_id: i64,
-    _cur_provider_id: i64,
+    provider: &Web3Node,
 ) -> Result<(), Box<dyn Error>> {
+    let chain_id = provider.chain_id;
     if l.data.0.len() < 64 {
         return Err(Box::new(CustomError::Inequality(format!(
             "update_provider_block_price: data len {:?} !>= 64",
@@ -322,20 +860,14 @@ pub async fn update_provider_block_price(
         ))));
     }
 
-    let dec_d = ethabi::decode(
+    let dec_d = decode(
         &[ethabi::ParamType::Uint(256), ethabi::ParamType::Uint(256)],
-        &l.data.0,
-    )
-    .unwrap();
+        &l,
+    )?;
 
-    let update_block = (&l.block_number.unwrap()).as_u64() as i64;
-    let provider_id = dec_d[0].clone().into_uint().unwrap().as_u64() as i64;
-    let block_price = dec_d[0]
-        .clone()
-        .into_uint()
-        .unwrap()
-        .div(ethabi::ethereum_types::U256::from_dec_str("1000000000").unwrap())
-        .as_u64() as i64; //gwei
+    let update_block = block_number(&l)?;
+    let provider_id = to_i64(uint(&dec_d[0])?)?;
+    let block_price = to_i64(uint(&dec_d[1])?.div(U256::exp10(9)))?; //gwei
 
     info!(
         "CHAIN '{}' -> GOT 'update_provider_block_price' Event :: {:?}, {:?}, {:?} :: {:?}",
@@ -346,30 +878,17 @@ pub async fn update_provider_block_price(
         &l.data.0.len()
     );
 
-    let res: Result<_, postgres::Error> = psql
-        .run(move |client| {
-            db::update_provider_block_price(
-                client,
-                chain_id,
-                update_block,
-                provider_id,
-                block_price,
-            )
-        })
-        .await;
-
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e)),
-    }
+    let mut event = provider_event(UPDATE_PROVIDER_BLOCK_PRICE, &l, chain_id, provider_id)?;
+    event.block_price_gwei = Option::Some(block_price);
+    add_provider_event(psql, event).await
 }
 
 pub async fn update_provider_api_url(
     psql: Arc<DbConn>,
     l: Log,
-    chain_id: i64,
-    _cur_provider_id: i64,
+    provider: &Web3Node,
 ) -> Result<(), Box<dyn Error>> {
+    let chain_id = provider.chain_id;
     if l.data.0.len() < 64 {
         return Err(Box::new(CustomError::Inequality(format!(
             "update_provider_api_url: data len {:?} !>= 64",
@@ -377,15 +896,20 @@ pub async fn update_provider_api_url(
         ))));
     }
 
-    let dec_d = ethabi::decode(
+    let dec_d = decode(
         &[ethabi::ParamType::Uint(256), ethabi::ParamType::String],
-        &l.data.0,
-    )
-    .unwrap();
+        &l,
+    )?;
 
-    let update_block = (&l.block_number.unwrap()).as_u64() as i64;
-    let provider_id = dec_d[0].clone().into_uint().unwrap().as_u64() as i64;
-    let api_url = dec_d[1].to_string();
+    let update_block = block_number(&l)?;
+    let provider_id = to_i64(uint(&dec_d[0])?)?;
+    let api_url = match sanitize::api_url(&dec_d[1].to_string()) {
+        Ok(v) => v,
+        Err(e) => {
+            reject(psql, chain_id, UPDATE_PROVIDER_API_URL, &l, e).await;
+            return Ok(());
+        }
+    };
 
     info!(
         "CHAIN '{}' -> GOT 'update_provider_api_url' Event :: {:?}, {:?}, {:?} :: {:?}",
@@ -396,24 +920,17 @@ pub async fn update_provider_api_url(
         &l.data.0.len()
     );
 
-    let res: Result<_, postgres::Error> = psql
-        .run(move |client| {
-            db::update_provider_api_url(client, chain_id, update_block, provider_id, api_url)
-        })
-        .await;
-
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e)),
-    }
+    let mut event = provider_event(UPDATE_PROVIDER_API_URL, &l, chain_id, provider_id)?;
+    event.api_url = Option::Some(api_url);
+    add_provider_event(psql, event).await
 }
 
 pub async fn update_provider_owner(
     psql: Arc<DbConn>,
     l: Log,
-    chain_id: i64,
-    _cur_provider_id: i64,
+    provider: &Web3Node,
 ) -> Result<(), Box<dyn Error>> {
+    let chain_id = provider.chain_id;
     if l.data.0.len() < 64 {
         return Err(Box::new(CustomError::Inequality(format!(
             "update_provider_owner: data len {:?} !>= 64",
@@ -421,15 +938,20 @@ pub async fn update_provider_owner(
         ))));
     }
 
-    let dec_d = ethabi::decode(
-        &[ethabi::ParamType::Uint(256), ethabi::ParamType::String],
-        &l.data.0,
-    )
-    .unwrap();
+    let dec_d = decode(
+        &[ethabi::ParamType::Uint(256), ethabi::ParamType::Address],
+        &l,
+    )?;
 
-    let update_block = (&l.block_number.unwrap()).as_u64() as i64;
-    let provider_id = dec_d[0].clone().into_uint().unwrap().as_u64() as i64;
-    let owner = format!("0x{}", dec_d[1].to_string());
+    let update_block = block_number(&l)?;
+    let provider_id = to_i64(uint(&dec_d[0])?)?;
+    let owner = match sanitize::address(&format!("0x{}", dec_d[1])) {
+        Ok(v) => v,
+        Err(e) => {
+            reject(psql, chain_id, UPDATE_PROVIDER_ADDRESS, &l, e).await;
+            return Ok(());
+        }
+    };
 
     info!(
         "CHAIN '{}' -> GOT 'update_provider_owner' Event :: {:?}, {:?}, {:?} :: {:?}",
@@ -440,24 +962,17 @@ pub async fn update_provider_owner(
         &l.data.0.len()
     );
 
-    let res: Result<_, postgres::Error> = psql
-        .run(move |client| {
-            db::update_provider_owner(client, chain_id, update_block, provider_id, owner)
-        })
-        .await;
-
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e)),
-    }
+    let mut event = provider_event(UPDATE_PROVIDER_ADDRESS, &l, chain_id, provider_id)?;
+    event.owner = Option::Some(owner);
+    add_provider_event(psql, event).await
 }
 
 pub async fn update_provider_name(
     psql: Arc<DbConn>,
     l: Log,
-    chain_id: i64,
-    _cur_provider_id: i64,
+    provider: &Web3Node,
 ) -> Result<(), Box<dyn Error>> {
+    let chain_id = provider.chain_id;
     if l.data.0.len() < 64 {
         return Err(Box::new(CustomError::Inequality(format!(
             "update_provider_name: data len {:?} !>= 64",
@@ -465,15 +980,20 @@ pub async fn update_provider_name(
         ))));
     }
 
-    let dec_d = ethabi::decode(
+    let dec_d = decode(
         &[ethabi::ParamType::Uint(256), ethabi::ParamType::String],
-        &l.data.0,
-    )
-    .unwrap();
+        &l,
+    )?;
 
-    let update_block = (&l.block_number.unwrap()).as_u64() as i64;
-    let provider_id = dec_d[0].clone().into_uint().unwrap().as_u64() as i64;
-    let name = dec_d[1].to_string();
+    let update_block = block_number(&l)?;
+    let provider_id = to_i64(uint(&dec_d[0])?)?;
+    let name = match sanitize::name(&dec_d[1].to_string()) {
+        Ok(v) => v,
+        Err(e) => {
+            reject(psql, chain_id, UPDATE_PROVIDER_NAME, &l, e).await;
+            return Ok(());
+        }
+    };
 
     info!(
         "CHAIN '{}' -> GOT 'update_provider_name' Event :: {:?}, {:?}, {:?} :: {:?}",
@@ -484,16 +1004,36 @@ pub async fn update_provider_name(
         &l.data.0.len()
     );
 
-    let res: Result<_, postgres::Error> = psql
-        .run(move |client| {
-            db::update_provider_name(client, chain_id, update_block, provider_id, name)
-        })
-        .await;
+    let mut event = provider_event(UPDATE_PROVIDER_NAME, &l, chain_id, provider_id)?;
+    event.name = Option::Some(name);
+    add_provider_event(psql, event).await
+}
 
-    match res {
-        Ok(_) => Ok(()),
-        Err(e) => Err(Box::new(e)),
-    }
+/// Spawns a watcher for every contract event of the chain
+pub fn watch_contract(
+    provider: &Web3Node,
+    db: Arc<DbConn>,
+    shutdown: Shutdown,
+    mon: Arc<Mutex<HashMap<u64, Monitoring>>>,
+) {
+    watch_event!("event_update_valid_block", UPDATE_VALID_BLOCK
+                    => provider, db, shutdown, mon
+                    => update_valid_block);
+    watch_event!("provider_events", ADD_PROVIDER
+                    => provider, db, shutdown, mon
+                    => update_add_provider);
+    watch_event!("provider_events", UPDATE_PROVIDER_BLOCK_PRICE
+                    => provider, db, shutdown, mon
+                    => update_provider_block_price);
+    watch_event!("provider_events", UPDATE_PROVIDER_API_URL
+                    => provider, db, shutdown, mon
+                    => update_provider_api_url);
+    watch_event!("provider_events", UPDATE_PROVIDER_ADDRESS
+                    => provider, db, shutdown, mon
+                    => update_provider_owner);
+    watch_event!("provider_events", UPDATE_PROVIDER_NAME
+                    => provider, db, shutdown, mon
+                    => update_provider_name);
 }
 
 #[derive(Debug, Clone)]
@@ -509,31 +1049,80 @@ impl Fairing for ContractService {
     }
 
     async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
-        let db = Arc::new(DbConn::get_one(&rocket).await.expect("database mounted."));
+        let db = Arc::new(DbConn::get_one(rocket).await.expect("database mounted."));
 
         let shutdown = rocket.shutdown();
-        let providers = rocket.state::<State>().unwrap().providers.clone();
-        let mon = rocket.state::<State>().unwrap().monitoring.clone();
-
-        for provider in &*providers {
-            watch_event!("event_update_valid_block", "UpdateValidBlock(address,uint256,uint256,string)"
-                            => provider, db, shutdown, mon
-                            => update_valid_block);
-            watch_event!("event_add_provider", "AddProvider(address,uint256,uint256,string,string)"
-                            => provider, db, shutdown, mon
-                            => update_add_provider);
-            watch_event!("event_add_provider", "UpdateProviderBlockPrice(uint256,uint256)"
-                            => provider, db, shutdown, mon
-                            => update_provider_block_price);
-            watch_event!("event_add_provider", "UpdateProviderApiUrl(uint256,string)"
-                            => provider, db, shutdown, mon
-                            => update_provider_api_url);
-            watch_event!("event_add_provider", "UpdateProviderAddress(uint256,address)"
-                            => provider, db, shutdown, mon
-                            => update_provider_owner);
-            watch_event!("event_add_provider", "UpdateProviderName(uint256,string)"
-                            => provider, db, shutdown, mon
-                            => update_provider_name);
+        let state = rocket.state::<State>().unwrap();
+
+        for provider in state.providers().iter() {
+            watch_contract(provider, db.clone(), shutdown.clone(), state.monitoring.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 gwei per block
+    const PRICE: i64 = 1;
+
+    fn payment(end_block: i64) -> Payment {
+        Payment {
+            end_block,
+            claimed_end_block: Option::None,
+            paid_wei: Option::Some("0".to_owned()),
+            paid_end_block: Option::None,
+            status: PAYMENT_UNVERIFIED,
         }
     }
+
+    fn gwei(v: u64) -> web3::types::U256 {
+        web3::types::U256::from(v) * web3::types::U256::exp10(9)
+    }
+
+    #[test]
+    fn paid_exactly_and_overpaid() {
+        let p = settle(payment(1100), PaymentCheck::Cap, gwei(100), PRICE, 1000);
+        assert_eq!((p.status, p.end_block, p.paid_end_block), (PAYMENT_PAID, 1100, Some(1100)));
+
+        // overpaying keeps the claimed end block, the rest is not credited
+        let p = settle(payment(1100), PaymentCheck::Cap, gwei(500), PRICE, 1000);
+        assert_eq!((p.status, p.end_block, p.paid_end_block), (PAYMENT_PAID, 1100, Some(1500)));
+        assert_eq!(p.claimed_end_block, None);
+    }
+
+    #[test]
+    fn underpaid() {
+        let p = settle(payment(1100), PaymentCheck::Flag, gwei(99), PRICE, 1000);
+        assert_eq!((p.status, p.end_block, p.paid_end_block), (PAYMENT_UNDERPAID, 1100, Some(1099)));
+
+        // a block is only bought whole
+        let p = settle(payment(1100), PaymentCheck::Flag, gwei(100) - 1, PRICE, 1000);
+        assert_eq!((p.status, p.paid_end_block), (PAYMENT_UNDERPAID, Some(1099)));
+
+        let p = settle(payment(1100), PaymentCheck::Cap, gwei(99), PRICE, 1000);
+        assert_eq!(
+            (p.status, p.end_block, p.claimed_end_block, p.paid_end_block),
+            (PAYMENT_CAPPED, 1099, Some(1100), Some(1099))
+        );
+    }
+
+    #[test]
+    fn top_ups_count_from_start_block() {
+        // a CID paid until 1200 topped up at 1000 for 100 blocks lasts until 1300
+        let p = settle(payment(1300), PaymentCheck::Flag, gwei(300), 3, 1200);
+        assert_eq!((p.status, p.paid_end_block), (PAYMENT_PAID, Some(1300)));
+        let p = settle(payment(1301), PaymentCheck::Flag, gwei(300), 3, 1200);
+        assert_eq!(p.status, PAYMENT_UNDERPAID);
+    }
+
+    #[test]
+    fn free_and_huge_payments() {
+        let p = settle(payment(i64::MAX), PaymentCheck::Cap, gwei(0), 0, 1000);
+        assert_eq!((p.status, p.paid_end_block), (PAYMENT_PAID, None));
+
+        let p = settle(payment(5000), PaymentCheck::Cap, web3::types::U256::MAX, PRICE, 1000);
+        assert_eq!((p.status, p.paid_end_block), (PAYMENT_PAID, Some(1000 + i64::MAX / 2)));
+    }
 }
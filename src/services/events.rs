@@ -0,0 +1,62 @@
+use std::sync::{Arc, OnceLock};
+
+use rocket::tokio::sync::broadcast::{self, Receiver, Sender};
+use serde_json::Value;
+
+use crate::db;
+use crate::services::webhooks;
+use crate::types::{events::PinEvent, DbConn};
+
+/// Events kept for slow stream subscribers before they start skipping
+const CHANNEL_CAPACITY: usize = 1024;
+
+static CHANNEL: OnceLock<Sender<PinEvent>> = OnceLock::new();
+
+fn channel() -> &'static Sender<PinEvent> {
+    CHANNEL.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+}
+
+pub fn subscribe() -> Receiver<PinEvent> {
+    channel().subscribe()
+}
+
+/// Pushes the event to the open streams and queues it for webhooks.
+///
+/// `donor` is looked up from the indexed events when not given and someone is listening
+pub async fn publish(
+    psql: Arc<DbConn>,
+    event: &str,
+    chain_id: i64,
+    cid: &str,
+    donor: Option<String>,
+    data: Value,
+) {
+    if channel().receiver_count() > 0 {
+        let donors = match &donor {
+            Some(v) => vec![v.to_lowercase()],
+            None => {
+                let c = cid.to_owned();
+                match psql
+                    .run(move |client| db::get_cid_donors(client, chain_id, &c))
+                    .await
+                {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("CHAIN '{}' > ERROR getting donors of '{}': {}", chain_id, cid, e);
+                        vec![]
+                    }
+                }
+            }
+        };
+        // no receivers left is not an error
+        let _ = channel().send(PinEvent {
+            event: event.to_owned(),
+            chain_id,
+            cid: cid.to_owned(),
+            donors,
+            ts: chrono::Utc::now().timestamp_millis(),
+            data: data.clone(),
+        });
+    }
+    webhooks::enqueue(psql, event, chain_id, cid, donor, data).await;
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio, Orbit, Rocket,
+};
+
+use crate::db;
+use crate::types::{monitoring::HistoryEvent, DbConn};
+
+/// Stores a monitoring event in `monitoring_history`, failures are only logged
+pub async fn record(
+    psql: Arc<DbConn>,
+    chain_id: i64,
+    kind: &str,
+    event: &str,
+    update_block: i64,
+    duration_ms: i64,
+    count: i64,
+) {
+    let e = HistoryEvent {
+        chain_id,
+        kind: kind.to_owned(),
+        event: event.to_owned(),
+        ts: chrono::Utc::now().timestamp_millis(),
+        update_block,
+        duration_ms,
+        count,
+    };
+    if let Err(e) = psql.run(move |client| db::add_history_event(client, &e)).await {
+        error!("CHAIN '{}' > ERROR storing monitoring history: {}", chain_id, e);
+    }
+}
+
+/// Deletes monitoring history older than `retention_days` every hour
+#[derive(Debug, Clone)]
+pub struct HistoryService {
+    pub retention_days: u64,
+}
+
+#[rocket::async_trait]
+impl Fairing for HistoryService {
+    fn info(&self) -> Info {
+        Info {
+            name: "Run monitoring history retention service",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let psql = Arc::new(DbConn::get_one(rocket).await.expect("database mounted."));
+        let retention_ms = (self.retention_days * 86_400_000) as i64;
+
+        tokio::spawn(async move {
+            loop {
+                let before = chrono::Utc::now().timestamp_millis() - retention_ms;
+                match psql
+                    .run(move |client| db::delete_history_before(client, before))
+                    .await
+                {
+                    Ok(v) => info!("DELETED '{}' monitoring history rows", v),
+                    Err(e) => error!("ERROR deleting monitoring history: {}", e),
+                };
+                tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
+            }
+        });
+    }
+}
@@ -2,22 +2,64 @@ use rocket::{
     fairing::{Fairing, Info, Kind},
     tokio, Orbit, Rocket,
 };
-use std::sync::Arc;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
-use crate::types::{CIDInfo, DbConn, Web3Node};
+use crate::services::{events, history, webhooks};
+use crate::types::{
+    monitoring::{
+        Monitoring, NodeCapacity, KIND_NODES_FULL, KIND_PIN_BATCH, KIND_RETRY_BATCH,
+        KIND_UNPIN_BATCH,
+    },
+    CIDInfo, DbConn, IpfsDagStat, IpfsRepoStat, Web3Node,
+};
+use crate::utils::metrics::{self, metrics};
 use crate::{
     db,
     types::{config::IPFSNode, State},
 };
 
+/// Nodes above their high-water mark, see `watch_node_capacity`
+pub fn full_nodes(capacity: &Arc<Mutex<HashMap<String, NodeCapacity>>>) -> HashSet<String> {
+    let capacity = capacity.lock().unwrap();
+    capacity.values().filter(|c| c.full).map(|c| c.node.clone()).collect()
+}
+
+/// Nodes that are not being drained nor above their high-water mark
+pub fn active_nodes(
+    nodes: &Arc<Vec<IPFSNode>>,
+    drained: &Arc<Mutex<HashSet<String>>>,
+    capacity: &Arc<Mutex<HashMap<String, NodeCapacity>>>,
+) -> Arc<Vec<IPFSNode>> {
+    let drained = { drained.lock().unwrap().clone() };
+    let full = full_nodes(capacity);
+    Arc::new(
+        nodes
+            .iter()
+            .filter(|n| !drained.contains(&n.api_url) && !full.contains(&n.api_url))
+            .cloned()
+            .collect(),
+    )
+}
+
 pub async fn pin_chain_cids(
     provider: Web3Node,
     psql: Arc<DbConn>,
-    nodes: Arc<Vec<IPFSNode>>,
+    nodes: Arc<Mutex<Arc<Vec<IPFSNode>>>>,
+    drained: Arc<Mutex<HashSet<String>>>,
+    capacity: Arc<Mutex<HashMap<String, NodeCapacity>>>,
     update_interval: u64,
 ) {
     loop {
-        let bn = { provider.latest_block.clone().lock().unwrap().clone() };
+        if provider.is_stopped() {
+            info!("CHAIN '{}' - '{}' > Stopped", &provider.chain_name, &provider.chain_id);
+            return;
+        }
+        let bn = { *provider.latest_block.lock().unwrap() };
 
         let bn = match bn {
             Some(v) => v,
@@ -29,33 +71,68 @@ pub async fn pin_chain_cids(
             }
         };
 
-        let (cn, c_id) = (provider.chain_name.clone(), provider.chain_id.clone());
+        if provider.is_paused() {
+            info!("CHAIN '{}' - '{}' > Paused, will sleep for '{}' sec.",
+            &provider.chain_name, &provider.chain_id, update_interval);
+            tokio::time::sleep(tokio::time::Duration::from_secs(update_interval)).await;
+            continue;
+        }
+
+        let (cn, c_id, listings) = (
+            provider.chain_name.clone(),
+            provider.chain_id,
+            provider.listings.clone(),
+        );
+        let start = Instant::now();
         match psql
             .run(move |client| {
-                //update pinned cids valid block number
-                let r = db::update_existing_cids_end_block(client, c_id, bn)?;
+                let mut v = vec![];
+                for listing in listings {
+                    //update pinned cids valid block number
+                    let r = db::update_existing_cids_end_block(client, c_id, listing.provider_id, bn)?;
 
-                info!(
-                    "CHAIN '{}' - '{}' > UPDATED 'end block' number for pinned CIDs, total: '{}'",
-                    &cn, c_id, r
-                );
+                    info!(
+                        "CHAIN '{}' - '{}' > UPDATED 'end block' number for pinned CIDs of provider '{}', total: '{}'",
+                        &cn, c_id, listing.provider_id, r
+                    );
 
-                //collect new cids to pin
-                db::get_new_cids(client, c_id, bn)
+                    //collect new cids to pin
+                    let cids = db::get_new_cids(client, c_id, listing.provider_id, bn)?;
+                    v.push((listing, cids));
+                }
+                Ok::<_, postgres::Error>(v)
             })
             .await
         {
             Ok(v) => {
-                info!(
-                    "CHAIN '{}' - '{}' > CIDs to pin, total: '{}'",
-                    &provider.chain_name,
-                    &provider.chain_id,
-                    v.len()
-                );
-                for cid in v {
-                    // let c = (Arc::new(cid)).clone();
-                    pin_unpin_cid(psql.clone(), nodes.clone(), cid, true).await;
+                let nodes = { nodes.lock().unwrap().clone() };
+                let nodes = active_nodes(&nodes, &drained, &capacity);
+                let mut count = 0;
+                for (listing, cids) in v {
+                    info!(
+                        "CHAIN '{}' - '{}' > CIDs to pin for provider '{}', total: '{}'",
+                        &provider.chain_name,
+                        &provider.chain_id,
+                        listing.provider_id,
+                        cids.len()
+                    );
+                    let nodes = Arc::new(listing.nodes_of(&nodes));
+                    if nodes.is_empty() && !cids.is_empty() {
+                        warn!(
+                            "CHAIN '{}' - '{}' > No node of provider '{}' accepts new pins, '{}' CIDs wait",
+                            &provider.chain_name,
+                            &provider.chain_id,
+                            listing.provider_id,
+                            cids.len()
+                        );
+                    }
+                    count += cids.len() as i64;
+                    for cid in cids {
+                        pin_unpin_cid(psql.clone(), nodes.clone(), cid, true).await;
+                    }
                 }
+                history::record(psql.clone(), provider.chain_id, KIND_PIN_BATCH, "pin", bn,
+                    start.elapsed().as_millis() as i64, count).await;
             }
             Err(e) => {
                 error!(
@@ -69,6 +146,10 @@ pub async fn pin_chain_cids(
     }
 }
 
+/// Pins the CID to every node, or unpins it from the node it is pinned on.
+///
+/// A node removed from the config is unpinned at its stored URL without credentials, see
+/// `config_watcher`
 pub async fn pin_unpin_cid(
     psql: Arc<DbConn>,
     nodes: Arc<Vec<IPFSNode>>,
@@ -90,11 +171,30 @@ pub async fn pin_unpin_cid(
             }
         }
     }
+    if !pin {
+        warn!(
+            "CHAIN '{}' > NODE '{}' is not configured anymore, unpinning '{}' without credentials",
+            cid_info.chain_id.unwrap(),
+            cid_info.node.as_ref().unwrap(),
+            cid_info.cid.as_ref().unwrap()
+        );
+        unpin_cid_from_node(psql, cid_info).await;
+    }
 }
 
-pub async fn retry_failed_cids(provider: Web3Node, psql: Arc<DbConn>, update_interval: u64) {
+pub async fn retry_failed_cids(
+    provider: Web3Node,
+    psql: Arc<DbConn>,
+    drained: Arc<Mutex<HashSet<String>>>,
+    capacity: Arc<Mutex<HashMap<String, NodeCapacity>>>,
+    update_interval: u64,
+) {
     loop {
-        let bn = { provider.latest_block.clone().lock().unwrap().clone() };
+        if provider.is_stopped() {
+            info!("CHAIN '{}' - '{}' > Stopped", &provider.chain_name, &provider.chain_id);
+            return;
+        }
+        let bn = { *provider.latest_block.lock().unwrap() };
 
         let bn = match bn {
             Some(v) => v,
@@ -105,7 +205,15 @@ pub async fn retry_failed_cids(provider: Web3Node, psql: Arc<DbConn>, update_int
                 continue;
             }
         };
-        let (cn, c_id) = (provider.chain_name.clone(), provider.chain_id.clone());
+
+        if provider.is_paused() {
+            info!("CHAIN '{}' - '{}' > Paused, will sleep for '{}' sec.",
+            &provider.chain_name, &provider.chain_id, update_interval);
+            tokio::time::sleep(tokio::time::Duration::from_secs(update_interval)).await;
+            continue;
+        }
+        let (cn, c_id) = (provider.chain_name.clone(), provider.chain_id);
+        let start = Instant::now();
         match psql
             .run(move |client| {
                 let r = db::delete_expired_failed_cids(client, c_id, bn)?;
@@ -125,11 +233,22 @@ pub async fn retry_failed_cids(provider: Web3Node, psql: Arc<DbConn>, update_int
                     &provider.chain_id,
                     v.len()
                 );
+                metrics()
+                    .failed_pins_queue
+                    .with_label_values(&[&provider.chain_id.to_string()])
+                    .set(v.len() as i64);
+                let drained = { drained.lock().unwrap().clone() };
+                let full = full_nodes(&capacity);
+                let count = v.len() as i64;
                 for cid in v {
-                    // let c = (Arc::new(cid)).clone();
-                    // self.pin_unpin_cid(c).await;
+                    let node = cid.node.as_ref().unwrap();
+                    if drained.contains(node) || full.contains(node) {
+                        continue;
+                    }
                     pin_cid_to_node(psql.clone(), cid, false).await;
                 }
+                history::record(psql.clone(), provider.chain_id, KIND_RETRY_BATCH, "retry", bn,
+                    start.elapsed().as_millis() as i64, count).await;
             }
             Err(e) => {
                 error!(
@@ -145,11 +264,15 @@ pub async fn retry_failed_cids(provider: Web3Node, psql: Arc<DbConn>, update_int
 pub async fn unpin_cids(
     provider: Web3Node,
     psql: Arc<DbConn>,
-    nodes: Arc<Vec<IPFSNode>>,
+    nodes: Arc<Mutex<Arc<Vec<IPFSNode>>>>,
     update_interval: u64,
 ) {
     loop {
-        let bn = { provider.latest_block.clone().lock().unwrap().clone() };
+        if provider.is_stopped() {
+            info!("CHAIN '{}' - '{}' > Stopped", &provider.chain_name, &provider.chain_id);
+            return;
+        }
+        let bn = { *provider.latest_block.lock().unwrap() };
 
         let bn = match bn {
             Some(v) => v,
@@ -160,29 +283,76 @@ pub async fn unpin_cids(
                 continue;
             }
         };
-        let (cn, c_id) = (provider.chain_name.clone(), provider.chain_id.clone());
+
+        if provider.is_paused() {
+            info!("CHAIN '{}' - '{}' > Paused, will sleep for '{}' sec.",
+            &provider.chain_name, &provider.chain_id, update_interval);
+            tokio::time::sleep(tokio::time::Duration::from_secs(update_interval)).await;
+            continue;
+        }
+        let (cn, c_id, listings) = (
+            provider.chain_name.clone(),
+            provider.chain_id,
+            provider.listings.clone(),
+        );
+        let start = Instant::now();
+        // expired pins stay pinned as `pending_unpin` until the grace period passed
+        let grace_blocks = provider.blocks_within(provider.unpin_grace_sec);
+        let unpin_before = bn.saturating_sub(grace_blocks);
         match psql
             .run(move |client| {
-                let res = db::delete_multichain_expired_cids(client, c_id, bn)?;
-                info!(
-                    "CHAIN '{}' - '{}' > DELETED '{}' multi-chain expired CIDs",
-                    cn, &c_id, res
-                );
+                let (mut marked, mut v) = (vec![], vec![]);
+                for listing in listings {
+                    let m = db::mark_pending_unpin(client, c_id, listing.provider_id, bn)?;
+                    info!(
+                        "CHAIN '{}' - '{}' > MARKED '{}' expired CIDs of provider '{}' pending unpin",
+                        cn, &c_id, m.len(), listing.provider_id
+                    );
+                    marked.extend(m);
+
+                    let res = db::delete_multichain_expired_cids(client, c_id, listing.provider_id, unpin_before)?;
+                    info!(
+                        "CHAIN '{}' - '{}' > DELETED '{}' multi-chain expired CIDs of provider '{}'",
+                        cn, &c_id, res, listing.provider_id
+                    );
 
-                db::get_single_chain_expired_cids(client, c_id, bn)
+                    v.extend(db::get_single_chain_expired_cids(client, c_id, listing.provider_id, unpin_before)?);
+                }
+                Ok::<_, postgres::Error>((marked, v))
             })
             .await
         {
-            Ok(v) => {
+            Ok((marked, v)) => {
+                for c in marked {
+                    let end_block = c.end_block.unwrap();
+                    events::publish(
+                        psql.clone(),
+                        webhooks::EVENT_UNPIN_PENDING,
+                        provider.chain_id,
+                        c.cid.as_ref().unwrap(),
+                        Option::None,
+                        json!({
+                            "provider_id": c.provider_id,
+                            "end_block": end_block,
+                            "unpin_block": end_block + grace_blocks,
+                            "unpin_at": provider.block_timestamp(end_block + grace_blocks),
+                        }),
+                    )
+                    .await;
+                }
                 info!(
                     "CHAIN '{}' - '{}' > CIDs to unpin, total: {}",
                     &provider.chain_name,
                     &provider.chain_id,
                     v.len()
                 );
+                let nodes = { nodes.lock().unwrap().clone() };
+                let count = v.len() as i64;
                 for cid in v {
                     pin_unpin_cid(psql.clone(), nodes.clone(), cid, false).await;
                 }
+                history::record(psql.clone(), provider.chain_id, KIND_UNPIN_BATCH, "unpin", bn,
+                    start.elapsed().as_millis() as i64, count).await;
             }
             Err(e) => {
                 error!(
@@ -195,15 +365,193 @@ pub async fn unpin_cids(
     }
 }
 
+/// Repo usage of `node`
+pub async fn get_repo_stat(node: &IPFSNode) -> Result<IpfsRepoStat, reqwest::Error> {
+    let mut req = reqwest::Client::new().post(format!("{}/api/v0/repo/stat", node.api_url));
+    if let Some(login) = &node.login {
+        req = req.basic_auth(login, node.password.clone());
+    }
+    req.send()
+        .await?
+        .error_for_status()?
+        .json::<IpfsRepoStat>()
+        .await
+}
+
+/// Polls `repo/stat` of every node. A node above its high-water mark gets no new pins,
+/// chains whose nodes are all above it are flagged in monitoring and alerted on
+pub async fn watch_node_capacity(
+    psql: Arc<DbConn>,
+    nodes: Arc<Mutex<Arc<Vec<IPFSNode>>>>,
+    providers: Arc<Mutex<Arc<Vec<Web3Node>>>>,
+    capacity: Arc<Mutex<HashMap<String, NodeCapacity>>>,
+    mon: Arc<Mutex<HashMap<u64, Monitoring>>>,
+    update_interval: u64,
+) {
+    loop {
+        let nodes = { nodes.lock().unwrap().clone() };
+        let mut current = HashMap::new();
+        for n in nodes.iter() {
+            let last = { capacity.lock().unwrap().get(&n.api_url).cloned() };
+            let stat = match get_repo_stat(n).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("NODE '{}' > ERROR getting repo stat, keeping the last one > {}", n.api_url, e);
+                    if let Some(c) = last {
+                        current.insert(n.api_url.clone(), c);
+                    }
+                    continue;
+                }
+            };
+            let used_pct = match stat.storage_max {
+                0 => 0.0,
+                max => stat.repo_size as f64 * 100.0 / max as f64,
+            };
+            let high_water_pct = n.high_water_pct();
+            let full = used_pct >= high_water_pct;
+            let was_full = last.is_some_and(|c| c.full);
+            if full && !was_full {
+                warn!(
+                    "NODE '{}' > repo at '{:.1}%' of its storage, above the high-water mark '{}%', no new pins",
+                    n.api_url, used_pct, high_water_pct
+                );
+            } else if !full && was_full {
+                info!(
+                    "NODE '{}' > repo at '{:.1}%' of its storage, back under the high-water mark '{}%'",
+                    n.api_url, used_pct, high_water_pct
+                );
+            }
+            let m = metrics();
+            m.node_repo_size.with_label_values(&[&n.api_url]).set(stat.repo_size as i64);
+            m.node_storage_max.with_label_values(&[&n.api_url]).set(stat.storage_max as i64);
+            m.node_repo_objects.with_label_values(&[&n.api_url]).set(stat.num_objects as i64);
+            m.node_full.with_label_values(&[&n.api_url]).set(full as i64);
+            current.insert(
+                n.api_url.clone(),
+                NodeCapacity {
+                    node: n.api_url.clone(),
+                    repo_size: stat.repo_size,
+                    storage_max: stat.storage_max,
+                    num_objects: stat.num_objects,
+                    used_pct,
+                    high_water_pct,
+                    full,
+                    updated_at: chrono::Utc::now().timestamp_millis(),
+                },
+            );
+        }
+        {
+            *capacity.lock().unwrap() = current.clone();
+        }
+
+        let running = { providers.lock().unwrap().clone() };
+        for p in running.iter() {
+            let served: HashSet<String> = p
+                .listings
+                .iter()
+                .flat_map(|l| l.nodes_of(&nodes))
+                .map(|n| n.api_url)
+                .collect();
+            let full = !served.is_empty()
+                && served.iter().all(|n| current.get(n).is_some_and(|c| c.full));
+            let was_full = {
+                let mut data = mon.lock().unwrap();
+                let obj = data.entry(p.chain_id as u64).or_insert(Monitoring::default());
+                std::mem::replace(&mut obj.nodes_full, full)
+            };
+            metrics()
+                .chain_nodes_full
+                .with_label_values(&[&p.chain_id.to_string()])
+                .set(full as i64);
+            if full && !was_full {
+                error!(
+                    "CHAIN '{}' - '{}' > ALERT all '{}' nodes are above their high-water mark, new CIDs are not pinned",
+                    p.chain_name, p.chain_id, served.len()
+                );
+                let bn = { p.latest_block.lock().unwrap().unwrap_or(0) };
+                history::record(psql.clone(), p.chain_id, KIND_NODES_FULL, "nodes_full", bn, 0,
+                    served.len() as i64).await;
+            } else if !full && was_full {
+                info!(
+                    "CHAIN '{}' - '{}' > nodes accept new pins again",
+                    p.chain_name, p.chain_id
+                );
+            }
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_secs(update_interval)).await;
+    }
+}
+
+/// Cumulative size and block count of a CID's DAG as seen by `node`
+pub async fn get_dag_stat(node: &str, cid: &str) -> Result<IpfsDagStat, reqwest::Error> {
+    reqwest::Client::new()
+        .post(format!("{}/api/v0/dag/stat", node))
+        .query(&[("arg", cid), ("progress", "false")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<IpfsDagStat>()
+        .await
+}
+
+/// Stores the DAG size of a CID pinned on `node`, once as CIDs are immutable
+pub async fn store_dag_stat(psql: Arc<DbConn>, node: &str, cid: &str) {
+    let c = cid.to_owned();
+    match psql.run(move |client| db::has_cid_size(client, &c)).await {
+        Ok(true) => return,
+        Ok(false) => {}
+        Err(e) => {
+            error!("ERROR checking size of cid '{}': {}", cid, e);
+            return;
+        }
+    }
+    let stat = match get_dag_stat(node, cid).await {
+        Ok(v) => v,
+        Err(e) => {
+            warn!("ERROR getting DAG stat of cid '{}' from node '{}': {}", cid, node, e);
+            return;
+        }
+    };
+    let (c, now) = (cid.to_owned(), chrono::Utc::now().timestamp_millis());
+    if let Err(e) = psql
+        .run(move |client| db::set_cid_size(client, &c, stat.size as i64, stat.num_blocks, now))
+        .await
+    {
+        error!("ERROR storing size of cid '{}': {}", cid, e);
+    }
+}
+
+/// Fills in the size of CIDs pinned before sizes were recorded or whose DAG stat failed
+pub async fn size_pinned_cids(psql: Arc<DbConn>, update_interval: u64) {
+    loop {
+        match psql.run(|client| db::get_unsized_cids(client, 100)).await {
+            Ok(v) => {
+                if !v.is_empty() {
+                    info!("Fetching size of pinned CIDs, total: '{}'", v.len());
+                }
+                for c in v {
+                    if let (Some(node), Some(cid)) = (c.node, c.cid) {
+                        store_dag_stat(psql.clone(), &node, &cid).await;
+                    }
+                }
+            }
+            Err(e) => error!("ERROR collecting pinned CIDs without size: {}", e),
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(update_interval)).await;
+    }
+}
+
 async fn add_failed_pin_to_db(
     psql: Arc<DbConn>,
     chain_id: i64,
+    provider_id: i64,
     block: i64,
     cid: String,
     node: String,
 ) {
     psql.run(move |client| {
-        match db::add_failed_pin(client, chain_id, &node, &cid, block) {
+        match db::add_failed_pin(client, chain_id, provider_id, &node, &cid, block) {
             Ok(_) => {
                 warn!(
                     "CHAIN '{}' > FAILED to pin '{}' to NODE '{}' expiration block '{}'",
@@ -221,111 +569,210 @@ async fn add_failed_pin_to_db(
     .await;
 }
 
-async fn pin_cid_to_node(psql: Arc<DbConn>, c: CIDInfo, store_failed: bool) {
-    tokio::spawn(async move {
-        let client = reqwest::Client::new();
-        let node = c.node.unwrap();
-        let cid = c.cid.unwrap();
-        let chain_id = c.chain_id.unwrap();
-        let block = c.end_block.unwrap();
-        match client
-            .post(format!("{}/api/v0/pin/add?arg={}", &node, &cid))
-            .send()
-            .await
-        {
-            Ok(v) => {
-                if !v.status().is_success() {
-                    error!(
-                        "CHAIN '{}' > ERROR pinning cid '{}' to node '{}'",
-                        &chain_id, &cid, &node
-                    );
-                    if store_failed {
-                        add_failed_pin_to_db(psql, chain_id, block, cid, node).await;
-                    }
-                    return;
-                }
-
-                let (n, c_id) = (node.clone(), cid.clone());
-                match psql
-                    .run(move |client| db::add_cid(client, chain_id, n, c_id, block))
-                    .await
-                {
-                    Ok(_) => {
-                        info!(
-                            "CHAIN '{}' > PINNED '{}' to 'NODE' {} till block '{}'",
-                            &chain_id, &cid, &node, &block
-                        )
-                    }
-                    Err(e) => {
-                        error!(
-                            "CHAIN '{}' > ERROR inserting cid '{}' to pinned_cids: '{}'",
-                            &chain_id, &cid, e
-                        )
-                    }
-                };
-            }
+pub async fn pin_cid_to_node(psql: Arc<DbConn>, c: CIDInfo, store_failed: bool) {
+    tokio::spawn(async move { pin_cid_to_node_now(psql, c, store_failed).await });
+}
 
-            Err(e) => {
+/// Pins the CID and waits for the result, returns `true` on success
+pub async fn pin_cid_to_node_now(psql: Arc<DbConn>, c: CIDInfo, store_failed: bool) -> bool {
+    let client = reqwest::Client::new();
+    let node = c.node.unwrap();
+    let cid = c.cid.unwrap();
+    let chain_id = c.chain_id.unwrap();
+    let provider_id = c.provider_id.unwrap();
+    let block = c.end_block.unwrap();
+    let mut req = client.post(format!("{}/api/v0/pin/add", &node)).query(&[("arg", &cid)]);
+    if let Some(login) = &c.node_login {
+        req = req.basic_auth(login, c.node_pass.clone());
+    }
+    let res = req.send().await;
+    let ok = matches!(&res, Ok(v) if v.status().is_success());
+    metrics()
+        .pins
+        .with_label_values(&[&node, metrics::result(ok)])
+        .inc();
+    match res {
+        Ok(v) => {
+            if !v.status().is_success() {
                 error!(
-                    "CHAIN '{}' > ERROR pinning cid '{}' to node '{}' : '{}'",
-                    &chain_id, &cid, &node, e
+                    "CHAIN '{}' > ERROR pinning cid '{}' to node '{}'",
+                    &chain_id, &cid, &node
                 );
+                events::publish(
+                    psql.clone(),
+                    webhooks::EVENT_PIN_FAILED,
+                    chain_id,
+                    &cid,
+                    Option::None,
+                    json!({ "node": &node, "provider_id": provider_id, "end_block": block, "error": format!("status {}", v.status()) }),
+                )
+                .await;
                 if store_failed {
-                    add_failed_pin_to_db(psql, chain_id, block, cid, node).await;
+                    add_failed_pin_to_db(psql, chain_id, provider_id, block, cid, node).await;
                 }
+                return false;
             }
-        }
-    });
-}
 
-async fn unpin_cid_from_node(psql: Arc<DbConn>, c: CIDInfo) {
-    tokio::spawn(async move {
-        let client = reqwest::Client::new();
-        let node = c.node.unwrap();
-        let cid = c.cid.unwrap();
-        let chain_id = c.chain_id.unwrap();
-        let block = c.end_block.unwrap();
-        match client
-            .post(format!("{}/api/v0/pin/rm?arg={}", &node, &cid))
-            .send()
-            .await
-        {
-            Ok(v) => {
-                if !v.status().is_success() {
-                    error!("ERROR unpinning cid {} from node {}", &cid, &node);
-                    return;
+            let (n, c_id) = (node.clone(), cid.clone());
+            match psql
+                .run(move |client| {
+                    db::delete_failed_pin(client, chain_id, provider_id, &n, &c_id)?;
+                    db::add_cid(client, chain_id, provider_id, n, c_id, block)
+                })
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "CHAIN '{}' > PINNED '{}' to 'NODE' {} till block '{}'",
+                        &chain_id, &cid, &node, &block
+                    );
+                    events::publish(
+                        psql.clone(),
+                        webhooks::EVENT_PINNED,
+                        chain_id,
+                        &cid,
+                        Option::None,
+                        json!({ "node": &node, "provider_id": provider_id, "end_block": block }),
+                    )
+                    .await;
+                    store_dag_stat(psql.clone(), &node, &cid).await;
                 }
-                let (n, c_id) = (node.clone(), cid.clone());
-                match psql
-                    .run(move |client| db::delete_cid(client, chain_id, n, c_id, block))
-                    .await
-                {
-                    Ok(_) => {
-                        info!(
-                            "CHAIN '{}' > UNPINNED '{}' from NODE '{}'",
-                            &chain_id, &cid, &node
-                        )
-                    }
-                    Err(e) => {
-                        error!(
-                            "CHAIN '{}' > ERROR deleting cid '{}' from pinned_cids: '{}'",
-                            &chain_id, &cid, e
-                        )
-                    }
-                };
+                Err(e) => {
+                    error!(
+                        "CHAIN '{}' > ERROR inserting cid '{}' to pinned_cids: '{}'",
+                        &chain_id, &cid, e
+                    )
+                }
+            };
+            true
+        }
+
+        Err(e) => {
+            error!(
+                "CHAIN '{}' > ERROR pinning cid '{}' to node '{}' : '{}'",
+                &chain_id, &cid, &node, e
+            );
+            events::publish(
+                psql.clone(),
+                webhooks::EVENT_PIN_FAILED,
+                chain_id,
+                &cid,
+                Option::None,
+                json!({ "node": &node, "provider_id": provider_id, "end_block": block, "error": e.to_string() }),
+            )
+            .await;
+            if store_failed {
+                add_failed_pin_to_db(psql, chain_id, provider_id, block, cid, node).await;
             }
+            false
+        }
+    }
+}
 
-            Err(e) => {
-                error!("ERROR unpinning cid {} from node {} : {}", &cid, &node, e);
+pub async fn unpin_cid_from_node(psql: Arc<DbConn>, c: CIDInfo) {
+    tokio::spawn(async move { unpin_cid_from_node_now(psql, c).await });
+}
+
+/// Unpins the CID and waits for the result, returns `true` on success
+pub async fn unpin_cid_from_node_now(psql: Arc<DbConn>, c: CIDInfo) -> bool {
+    let client = reqwest::Client::new();
+    let node = c.node.unwrap();
+    let cid = c.cid.unwrap();
+    let chain_id = c.chain_id.unwrap();
+    let provider_id = c.provider_id.unwrap();
+    let block = c.end_block.unwrap();
+    let mut req = client.post(format!("{}/api/v0/pin/rm", &node)).query(&[("arg", &cid)]);
+    if let Some(login) = &c.node_login {
+        req = req.basic_auth(login, c.node_pass.clone());
+    }
+    let res = req.send().await;
+    let ok = matches!(&res, Ok(v) if v.status().is_success());
+    metrics()
+        .unpins
+        .with_label_values(&[&node, metrics::result(ok)])
+        .inc();
+    match res {
+        Ok(v) => {
+            if !v.status().is_success() {
+                error!("ERROR unpinning cid {} from node {}", &cid, &node);
+                return false;
             }
+            let (n, c_id) = (node.clone(), cid.clone());
+            match psql
+                .run(move |client| db::delete_cid(client, chain_id, provider_id, n, c_id, block))
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        "CHAIN '{}' > UNPINNED '{}' from NODE '{}'",
+                        &chain_id, &cid, &node
+                    );
+                    events::publish(
+                        psql.clone(),
+                        webhooks::EVENT_UNPINNED,
+                        chain_id,
+                        &cid,
+                        Option::None,
+                        json!({ "node": &node, "provider_id": provider_id, "end_block": block }),
+                    )
+                    .await;
+                }
+                Err(e) => {
+                    error!(
+                        "CHAIN '{}' > ERROR deleting cid '{}' from pinned_cids: '{}'",
+                        &chain_id, &cid, e
+                    )
+                }
+            };
+            true
         }
-    });
+
+        Err(e) => {
+            error!("ERROR unpinning cid {} from node {} : {}", &cid, &node, e);
+            false
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct IPFSService {
     pub retry_failed_cids_sec: u64,
     pub update_nodes_sec: u64,
+    pub node_capacity_sec: u64,
+}
+
+impl IPFSService {
+    /// Spawns the pin, retry and unpin loops of the chain
+    pub fn watch_chain(
+        &self,
+        provider: &Web3Node,
+        db: Arc<DbConn>,
+        nodes: Arc<Mutex<Arc<Vec<IPFSNode>>>>,
+        drained: Arc<Mutex<HashSet<String>>>,
+        capacity: Arc<Mutex<HashMap<String, NodeCapacity>>>,
+    ) {
+        let (p, psql, n, d, c, ut) = (
+            provider.clone(),
+            db.clone(),
+            nodes.clone(),
+            drained.clone(),
+            capacity.clone(),
+            self.update_nodes_sec,
+        );
+        tokio::spawn(async move { pin_chain_cids(p, psql, n, d, c, ut).await });
+        // spawn failed pins retry
+        let (p, psql, d, c, ut) = (
+            provider.clone(),
+            db.clone(),
+            drained,
+            capacity,
+            self.retry_failed_cids_sec,
+        );
+        tokio::spawn(async move { retry_failed_cids(p, psql, d, c, ut).await });
+        // spawn unpin
+        let (p, psql, n, ut) = (provider.clone(), db, nodes, self.update_nodes_sec);
+        tokio::spawn(async move { unpin_cids(p, psql, n, ut).await });
+    }
 }
 
 #[rocket::async_trait]
@@ -338,36 +785,40 @@ impl Fairing for IPFSService {
     }
 
     async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
-        let db = Arc::new(DbConn::get_one(&rocket).await.expect("database mounted."));
+        let db = Arc::new(DbConn::get_one(rocket).await.expect("database mounted."));
 
         let state = rocket.state::<State>().unwrap();
-        // let nodes = rocket.state::<Arc<Vec<IPFSNode>>>().unwrap();
-        // let providers = rocket.state::<Arc<Vec<types::Web3Node>>>().unwrap().clone();
         // let shutdown = rocket.shutdown();
 
-        for provider in &*state.providers.clone() {
-            let (p, psql, n, ut) = (
-                provider.clone(),
-                db.clone(),
-                state.nodes.clone(),
-                self.update_nodes_sec,
-            );
-            tokio::spawn(async move { pin_chain_cids(p, psql, n, ut).await });
-            // spawn failed pins retry
-            let (p, psql, ut) = (
-                provider.clone(),
-                db.clone(),
-                self.retry_failed_cids_sec.clone(),
-            );
-            tokio::spawn(async move { retry_failed_cids(p, psql, ut).await });
-            // spawn unpin
-            let (p, psql, n, ut) = (
-                provider.clone(),
+        match db.run(db::get_drained_nodes).await {
+            Ok(v) => {
+                if !v.is_empty() {
+                    warn!("NODES {:?} > drained, no new pins", v);
+                }
+                state.drained_nodes.lock().unwrap().extend(v);
+            }
+            Err(e) => error!("ERROR loading drained nodes > {}", e),
+        }
+
+        for provider in state.providers().iter() {
+            self.watch_chain(
+                provider,
                 db.clone(),
                 state.nodes.clone(),
-                self.update_nodes_sec,
+                state.drained_nodes.clone(),
+                state.node_capacity.clone(),
             );
-            tokio::spawn(async move { unpin_cids(p, psql, n, ut).await });
         }
+        let (psql, n, p, c, m, ut) = (
+            db.clone(),
+            state.nodes.clone(),
+            state.providers.clone(),
+            state.node_capacity.clone(),
+            state.monitoring.clone(),
+            self.node_capacity_sec,
+        );
+        tokio::spawn(async move { watch_node_capacity(psql, n, p, c, m, ut).await });
+        let ut = self.update_nodes_sec;
+        tokio::spawn(async move { size_pinned_cids(db, ut).await });
     }
 }
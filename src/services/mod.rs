@@ -1,3 +1,7 @@
+pub mod config_watcher;
 pub mod contract_watcher;
+pub mod events;
+pub mod history;
 pub mod ipfs_watcher;
 pub mod providers;
+pub mod webhooks;
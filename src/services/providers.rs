@@ -1,4 +1,8 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use rocket::{
     fairing::{Fairing, Info, Kind},
@@ -6,7 +10,13 @@ use rocket::{
 };
 use web3::{transports::WebSocket, Error, Web3};
 
-use crate::types::{config::Provider, monitoring::Monitoring, State, Web3Node};
+use crate::services::history;
+use crate::utils::metrics::metrics;
+use crate::types::{
+    config::{PaymentCheck, Provider},
+    monitoring::{Monitoring, KIND_SOCKET_RECONNECT},
+    DbConn, State, Web3Node,
+};
 
 #[derive(Debug, Clone)]
 pub struct Providers {}
@@ -28,24 +38,135 @@ impl Providers {
             let chain_id = socket.eth().chain_id().await?.as_u64() as i64;
             let latest_block = self.get_block_num(&socket).await? as i64;
 
+            let contracts = provider.contracts();
+            let start_block = contracts
+                .iter()
+                .filter_map(|c| c.from_block)
+                .min()
+                .unwrap_or(provider.start_block);
+
             providers_manage.push(Web3Node {
-                contract_address: provider.contract_address.to_owned(),
+                contracts,
                 url: provider.provider.to_owned(),
                 chain_name: provider.chain_name.to_owned(),
-                start_block: provider.start_block,
+                start_block,
                 block_time_sec: provider.block_time_sec,
                 block_update_sec: provider.block_update_sec,
-                provider_id: provider.provider_id,
+                listings: provider.listings(),
                 chain_id,
                 log_update_sec: provider.log_update_sec,
                 batch_size: provider.batch_size,
                 web3: Arc::new(Mutex::new(socket)),
                 latest_block: Arc::new(Mutex::new(Some(latest_block))),
+                head_samples: Arc::new(Mutex::new(VecDeque::new())),
+                paid_wei: Arc::new(Mutex::new(HashMap::new())),
+                paused: Arc::new(Mutex::new(false)),
+                stopped: Arc::new(Mutex::new(false)),
+                resync_from: Arc::new(Mutex::new(HashMap::new())),
                 skip_old: provider.skip_old,
+                payment_check: provider.payment_check.unwrap_or(PaymentCheck::Flag),
+                unpin_grace_sec: provider.unpin_grace_sec.unwrap_or(0),
             });
         }
         Ok(providers_manage)
     }
+
+    /// Keeps `p.latest_block` up to date, reconnecting the socket when needed
+    pub fn watch_block(
+        &self,
+        p: Web3Node,
+        psql: Arc<DbConn>,
+        mon: Arc<Mutex<HashMap<u64, Monitoring>>>,
+    ) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut socket_create_time = chrono::Utc::now().timestamp_millis();
+            loop {
+                if p.is_stopped() {
+                    info!(
+                        "CHAIN '{}' - '{}' > Stopped watching the latest block",
+                        p.chain_name, p.chain_id
+                    );
+                    return;
+                }
+                let web3 = { p.web3.clone().lock().unwrap().clone() };
+
+                let start = Instant::now();
+                let bn = match web3.eth().block_number().await {
+                    Ok(v) => v.as_u64() as i64,
+                    Err(e) => {
+                        error!("Error getting block number for {}: {:?}", &p.chain_name, e);
+                        // r_off.notify();
+                        info!(
+                            "CHAIN '{}' - '{}' > Creating new connection",
+                            &p.chain_name, p.chain_id
+                        );
+                        let new_socket = match this.create_provider(&p.url).await {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!(
+                                    "CHAIN '{}' - '{}' > failed to create a new socket '{}', will try again in '{} sec.'",
+                                    p.chain_name, p.chain_id, e, p.block_update_sec
+                                );
+                                tokio::time::sleep(tokio::time::Duration::from_secs(
+                                    p.block_update_sec,
+                                ))
+                                .await;
+                                continue;
+                            }
+                        };
+                        {
+                            let mut socket = p.web3.lock().unwrap();
+                            *socket = new_socket;
+                        }
+                        socket_create_time = chrono::Utc::now().timestamp_millis();
+                        let last_block = { p.latest_block.lock().unwrap().unwrap_or(0) };
+                        history::record(
+                            psql.clone(),
+                            p.chain_id,
+                            KIND_SOCKET_RECONNECT,
+                            "socket",
+                            last_block,
+                            start.elapsed().as_millis() as i64,
+                            1,
+                        )
+                        .await;
+                        continue;
+                    }
+                };
+
+                {
+                    let mut data = p.latest_block.lock().unwrap();
+                    *data = Some(bn);
+                }
+                p.sample_head(bn, chrono::Utc::now().timestamp_millis());
+                metrics()
+                    .chain_head_block
+                    .with_label_values(&[&p.chain_id.to_string(), &p.chain_name])
+                    .set(bn);
+
+                {
+                    let mut data = mon.lock().unwrap();
+                    let obj = data
+                        .entry(p.chain_id as u64)
+                        .or_insert(Monitoring::default());
+                    if obj.current_block != bn as u64 {
+                        obj.head_change_time = chrono::Utc::now().timestamp_millis();
+                    }
+                    obj.current_block = bn as u64;
+                    obj.socket_create_time = socket_create_time;
+                    obj.block_time_ms = p.block_time_ms();
+                    obj.chain_name = p.chain_name.clone();
+                }
+
+                info!(
+                    "CHAIN '{}' - '{}' > Socket is alive at block '{}'",
+                    p.chain_name, p.chain_id, bn
+                );
+                tokio::time::sleep(tokio::time::Duration::from_secs(p.block_update_sec)).await;
+            }
+        });
+    }
 }
 
 #[rocket::async_trait]
@@ -59,71 +180,10 @@ impl Fairing for Providers {
 
     async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
         let state = rocket.state::<State>().unwrap();
-        let providers = state.providers.clone();
-        // let mon = state.monitoring.clone();
-        // let shutdown = rocket.shutdown();
-
-        for provider in &*providers {
-            let (p, this, mon) = (provider.clone(), self.clone(), state.monitoring.clone());
-            tokio::spawn(async move {
-                let mut socket_create_time = chrono::Utc::now().timestamp_millis();
-                loop {
-                    let web3 = { p.web3.clone().lock().unwrap().clone() };
-
-                    let bn = match web3.eth().block_number().await {
-                        Ok(v) => v.as_u64() as i64,
-                        Err(e) => {
-                            error!("Error getting block number for {}: {:?}", &p.chain_name, e);
-                            // r_off.notify();
-                            info!(
-                                "CHAIN '{}' - '{}' > Creating new connection",
-                                &p.chain_name, p.chain_id
-                            );
-                            let new_socket = match this.create_provider(&p.url).await {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    error!(
-                                        "CHAIN '{}' - '{}' > failed to create a new socket '{}', will try again in '{} sec.'",
-                                        p.chain_name, p.chain_id, e, p.block_update_sec
-                                    );
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(
-                                        p.block_update_sec,
-                                    ))
-                                    .await;
-                                    continue;
-                                }
-                            };
-                            {
-                                let mut socket = p.web3.lock().unwrap();
-                                *socket = new_socket;
-                            }
-                            socket_create_time = chrono::Utc::now().timestamp_millis();
-                            continue;
-                        }
-                    };
-
-                    {
-                        let mut data = p.latest_block.lock().unwrap();
-                        *data = Some(bn);
-                    }
-
-                    {
-                        let mut data = mon.lock().unwrap();
-                        let obj = data
-                            .entry(p.chain_id as u64)
-                            .or_insert(Monitoring::default());
-                        obj.current_block = bn as u64;
-                        obj.socket_create_time = socket_create_time;
-                        obj.chain_name = p.chain_name.clone();
-                    }
+        let db = Arc::new(DbConn::get_one(rocket).await.expect("database mounted."));
 
-                    info!(
-                        "CHAIN '{}' - '{}' > Socket is alive at block '{}'",
-                        p.chain_name, p.chain_id, bn
-                    );
-                    tokio::time::sleep(tokio::time::Duration::from_secs(p.block_update_sec)).await;
-                }
-            });
+        for provider in state.providers().iter() {
+            self.watch_block(provider.clone(), db.clone(), state.monitoring.clone());
         }
     }
 }
@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio, Orbit, Rocket,
+};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::db;
+use crate::types::{config::Webhook, db::WebhookDelivery, DbConn, State, Web3Node};
+
+pub const EVENT_CID_INDEXED: &str = "cid_indexed";
+pub const EVENT_PINNED: &str = "pinned";
+pub const EVENT_PIN_FAILED: &str = "pin_failed";
+pub const EVENT_EXPIRING: &str = "expiring";
+pub const EVENT_UNPIN_PENDING: &str = "unpin_pending";
+pub const EVENT_UNPINNED: &str = "unpinned";
+pub const EVENTS: [&str; 6] = [
+    EVENT_CID_INDEXED,
+    EVENT_PINNED,
+    EVENT_PIN_FAILED,
+    EVENT_EXPIRING,
+    EVENT_UNPIN_PENDING,
+    EVENT_UNPINNED,
+];
+
+/// A delivery is dropped after this many failed attempts, the row is kept for inspection
+const MAX_ATTEMPTS: i32 = 10;
+const DELIVERY_INTERVAL_SEC: u64 = 5;
+const DELIVERY_TIMEOUT_SEC: u64 = 10;
+const EXPIRING_INTERVAL_SEC: u64 = 60;
+/// Delivered rows are deleted after 7 days
+const DELIVERED_RETENTION_MS: i64 = 7 * 86_400_000;
+
+// replaced on config reload, see `services::config_watcher`
+static SUBSCRIPTIONS: Mutex<Vec<Webhook>> = Mutex::new(Vec::new());
+
+pub fn set_subscriptions(webhooks: Vec<Webhook>) {
+    *SUBSCRIPTIONS.lock().unwrap() = webhooks;
+}
+
+fn subscriptions() -> Vec<Webhook> {
+    SUBSCRIPTIONS.lock().unwrap().clone()
+}
+
+/// Hex encoded HMAC-SHA256 of `body`
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Stored with every delivery of `w`, keyed by its secret so a delivery is only ever signed with
+/// the secret it was queued for and subscriptions sharing a URL stay apart
+fn subscription_id(w: &Webhook) -> String {
+    sign(w.secret.as_deref().unwrap_or_default(), &w.url)
+}
+
+/// Queues `event` for every matching subscription, delivery happens in `WebhookService`.
+///
+/// `donor` is looked up from the indexed events when not given and a subscription filters on it
+pub async fn enqueue(
+    psql: Arc<DbConn>,
+    event: &str,
+    chain_id: i64,
+    cid: &str,
+    donor: Option<String>,
+    data: Value,
+) {
+    let subs: Vec<Webhook> = subscriptions()
+        .into_iter()
+        .filter(|w| w.events.iter().any(|e| e.eq(event)))
+        .filter(|w| w.chain_id.is_none_or(|c| c == chain_id))
+        .collect();
+    if subs.is_empty() {
+        return;
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let payload = json!({
+        "event": event,
+        "chain_id": chain_id,
+        "cid": cid,
+        "donor": donor,
+        "ts": now,
+        "data": data,
+    })
+    .to_string();
+    let (event, cid) = (event.to_owned(), cid.to_owned());
+    let res = psql
+        .run(move |client| {
+            let donors = match donor {
+                Some(v) => vec![v.to_lowercase()],
+                None if subs.iter().any(|w| w.donor.is_some()) => {
+                    db::get_cid_donors(client, chain_id, &cid)?
+                }
+                None => vec![],
+            };
+            for w in subs {
+                if let Some(d) = &w.donor {
+                    let d = d.to_lowercase();
+                    if !donors
+                        .iter()
+                        .any(|v| v.trim_start_matches("0x").eq(d.trim_start_matches("0x")))
+                    {
+                        continue;
+                    }
+                }
+                db::add_webhook_delivery(client, &w.url, &subscription_id(&w), &event, &payload, now)?;
+            }
+            Ok::<(), postgres::Error>(())
+        })
+        .await;
+    if let Err(e) = res {
+        error!("CHAIN '{}' > ERROR queueing webhook: {}", chain_id, e);
+    }
+}
+
+async fn deliver(psql: Arc<DbConn>, d: WebhookDelivery) {
+    let secret = match subscriptions().into_iter().find(|w| subscription_id(w).eq(&d.subscription)) {
+        Some(w) => w.secret,
+        None => {
+            // removed, or its secret changed, since the delivery was queued
+            warn!("WEBHOOK '{}' > DROPPED '{}', its subscription is gone", &d.url, &d.event);
+            let id = d.id;
+            if let Err(e) = psql
+                .run(move |client| {
+                    db::set_webhook_abandoned(client, id, MAX_ATTEMPTS, "subscription removed")
+                })
+                .await
+            {
+                error!("WEBHOOK '{}' > ERROR updating delivery '{}': {}", &d.url, id, e);
+            }
+            return;
+        }
+    };
+    let mut req = reqwest::Client::new()
+        .post(&d.url)
+        .timeout(std::time::Duration::from_secs(DELIVERY_TIMEOUT_SEC))
+        .header("Content-Type", "application/json")
+        .header("X-Hosq-Event", &d.event)
+        .header("X-Hosq-Delivery", d.id.to_string());
+    if let Some(s) = secret {
+        req = req.header(
+            "X-Hosq-Signature",
+            format!("sha256={}", sign(&s, &d.payload)),
+        );
+    }
+    let res = match req.body(d.payload.clone()).send().await {
+        Ok(v) if v.status().is_success() => Ok(()),
+        Ok(v) => Err(format!("status {}", v.status())),
+        Err(e) => Err(e.to_string()),
+    };
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let id = d.id;
+    let stored = match res {
+        Ok(_) => {
+            psql.run(move |client| db::set_webhook_delivered(client, id, now))
+                .await
+        }
+        Err(e) => {
+            // 10s, 20s, 40s ... up to an hour
+            let backoff_ms = std::cmp::min(10_000 * 2i64.pow(d.attempts as u32), 3_600_000);
+            warn!(
+                "WEBHOOK '{}' > FAILED to deliver '{}' attempt '{}': {}",
+                &d.url,
+                &d.event,
+                d.attempts + 1,
+                &e
+            );
+            psql.run(move |client| db::set_webhook_failed(client, id, now + backoff_ms, &e))
+                .await
+        }
+    };
+    if let Err(e) = stored {
+        error!(
+            "WEBHOOK '{}' > ERROR updating delivery '{}': {}",
+            &d.url, id, e
+        );
+    }
+}
+
+/// Fires `expiring` once for every pinned CID entering the last `expiring_sec` of its pin.
+///
+/// CIDs entering the window while hosq is down are not reported
+async fn watch_expiring(
+    psql: Arc<DbConn>,
+    providers: Arc<Mutex<Arc<Vec<Web3Node>>>>,
+    expiring_sec: u64,
+) {
+    // chain_id -> last block checked up to
+    let mut checked: HashMap<i64, i64> = HashMap::new();
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(EXPIRING_INTERVAL_SEC)).await;
+        if subscriptions().is_empty() {
+            continue;
+        }
+        let running = { providers.lock().unwrap().clone() };
+        for p in running.iter() {
+            if p.is_paused() {
+                continue;
+            }
+            let bn = { *p.latest_block.lock().unwrap() };
+            let bn = match bn {
+                Some(v) => v,
+                None => continue,
+            };
+            let to = bn + p.blocks_within(expiring_sec);
+            let from = *checked.get(&p.chain_id).unwrap_or(&to);
+            checked.insert(p.chain_id, to);
+            if to <= from {
+                continue;
+            }
+            let c_id = p.chain_id;
+            let cids = match psql
+                .run(move |client| db::get_expiring_cids(client, c_id, from, to, Option::None))
+                .await
+            {
+                Ok(v) => v,
+                Err(e) => {
+                    error!(
+                        "CHAIN '{}' - '{}' > ERROR getting expiring CIDs: {}",
+                        &p.chain_name, &p.chain_id, e
+                    );
+                    continue;
+                }
+            };
+            for c in cids {
+                let end_block = c.end_block.unwrap();
+                enqueue(
+                    psql.clone(),
+                    EVENT_EXPIRING,
+                    p.chain_id,
+                    c.cid.as_ref().unwrap(),
+                    Option::None,
+                    json!({
+                        "end_block": end_block,
+                        "current_block": bn,
+                        "expires_at": p.block_timestamp(end_block),
+                    }),
+                )
+                .await;
+            }
+        }
+    }
+}
+
+/// Delivers queued webhooks, retrying failed ones with an exponential backoff
+#[derive(Debug, Clone)]
+pub struct WebhookService {
+    pub expiring_sec: u64,
+}
+
+#[rocket::async_trait]
+impl Fairing for WebhookService {
+    fn info(&self) -> Info {
+        Info {
+            name: "Run webhook delivery service",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let psql = Arc::new(DbConn::get_one(rocket).await.expect("database mounted."));
+        let state = rocket.state::<State>().unwrap();
+
+        if !state.only_api {
+            let (db, providers, sec) = (psql.clone(), state.providers.clone(), self.expiring_sec);
+            tokio::spawn(async move { watch_expiring(db, providers, sec).await });
+        }
+
+        tokio::spawn(async move {
+            loop {
+                let now = chrono::Utc::now().timestamp_millis();
+                // claimed rows are hidden from other instances for a minute
+                let lease_until = now + 60_000;
+                match psql
+                    .run(move |client| {
+                        db::delete_delivered_webhooks(client, now - DELIVERED_RETENTION_MS)?;
+                        db::claim_webhook_deliveries(client, now, lease_until, MAX_ATTEMPTS, 100)
+                    })
+                    .await
+                {
+                    Ok(v) => {
+                        join_all(v.into_iter().map(|d| deliver(psql.clone(), d))).await;
+                    }
+                    Err(e) => error!("ERROR collecting webhook deliveries: {}", e),
+                };
+                tokio::time::sleep(tokio::time::Duration::from_secs(DELIVERY_INTERVAL_SEC)).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(url: &str, secret: Option<&str>) -> Webhook {
+        Webhook {
+            url: url.to_owned(),
+            events: vec![EVENT_PINNED.to_owned()],
+            chain_id: Option::None,
+            donor: Option::None,
+            secret: secret.map(|s| s.to_owned()),
+        }
+    }
+
+    #[test]
+    fn sign_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn subscription_ids() {
+        let url = "https://example.com/hosq";
+        let id = subscription_id(&webhook(url, Some("a")));
+        assert_eq!(id, subscription_id(&webhook(url, Some("a"))));
+        assert_ne!(id, subscription_id(&webhook(url, Some("b"))));
+        assert_ne!(id, subscription_id(&webhook(url, None)));
+        assert_ne!(id, subscription_id(&webhook("https://example.com/other", Some("a"))));
+    }
+}
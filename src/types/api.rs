@@ -0,0 +1,93 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::types::db::StatsWindow;
+
+/// Body of every failed request: `{"error": {"code": "not_found", "message": "..."}}`
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct NonceResponse {
+    pub nonce: String,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct AdminTokenResponse {
+    pub token: String,
+    pub scopes: Vec<String>,
+    pub expires_in_sec: u64,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct PinResponse {
+    pub cid: String,
+    pub end_block: i64,
+    pub added: bool, // false when the CID is already indexed on the chain
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct NodeCount {
+    pub nodes: i64,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ForcePinResponse {
+    pub pinned: bool,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct UnpinResponse {
+    pub unpinned: usize,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct RequeueResponse {
+    pub requeued: usize,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct PauseResponse {
+    pub paused: bool,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ResyncResponse {
+    pub from_block: i64,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ReprocessResponse {
+    pub reprocessed: usize,
+    pub failed: usize, // still quarantined
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct DrainResponse {
+    pub pins: usize, // pins being moved in the background
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct UndrainResponse {
+    pub undrained: bool,
+}
+
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct ProviderStats {
+    pub chain_id: i64,
+    pub provider_id: i64,
+    pub block: i64, // chain head the windows end at
+    pub block_price_gwei: Option<i64>,
+    pub active_cids: i64, // pinned now
+    pub active_donors: i64,
+    pub bytes_pinned: i64,
+    pub windows: Vec<StatsWindow>,
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Allows pinning CIDs without expiration through `/cid/pin`
+pub const SCOPE_PIN: &str = "pin";
+/// Allows the `/admin` operational API
+pub const SCOPE_ADMIN: &str = "admin";
+pub const SCOPES: [&str; 2] = [SCOPE_PIN, SCOPE_ADMIN];
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub message: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LoginResponse {
+    pub token: String,
+    pub address: String,
+    pub chain_id: i64,
+    pub expires_in_sec: u64,
+}
+
+/// Caller verified through a SIWE session, see `routes::auth`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthUser {
+    pub address: String,
+    pub chain_id: i64,
+}
+
+/// Session minted with the admin secret, see `routes::auth::admin_token`.
+#[derive(Debug, Clone)]
+pub struct AdminToken {
+    pub scopes: Vec<String>,
+}
+
+impl AdminToken {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s.eq(scope))
+    }
+}
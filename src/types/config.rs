@@ -1,12 +1,23 @@
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct IPFSNode {
     pub api_url: String,
     pub gateway: String,
     pub login: Option<String>,
     pub password: Option<String>,
+    /// percent of `StorageMax` the repo can use before the node gets no new pins, 90 by default
+    pub high_water_pct: Option<f64>,
+}
+
+pub const DEFAULT_HIGH_WATER_PCT: f64 = 90.0;
+
+impl IPFSNode {
+    pub fn high_water_pct(&self) -> f64 {
+        self.high_water_pct.unwrap_or(DEFAULT_HIGH_WATER_PCT)
+    }
 }
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
     pub providers: Option<Vec<Provider>>,
@@ -15,18 +26,279 @@ pub struct Config {
     pub admin_secret: String,
     pub update_nodes_sec: u64,
     pub only_api: Option<bool>,
+    pub session_ttl_sec: Option<u64>,
+    /// longest `ttl_sec` an admin token can be minted with, 3600 by default
+    pub admin_token_max_ttl_sec: Option<u64>,
+    pub siwe_domain: Option<String>,
+    pub watch_config_sec: Option<u64>,
+    pub monitoring_retention_days: Option<u64>,
+    pub readiness: Option<Readiness>,
+    pub webhooks: Option<Vec<Webhook>>,
+    pub webhook_expiring_sec: Option<u64>,
+    /// how often `repo/stat` of the IPFS nodes is polled, 60 by default
+    pub node_capacity_sec: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Subscription to pin lifecycle events, see `services::webhooks`
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Webhook {
+    pub url: String,
+    /// any of `cid_indexed`, `pinned`, `pin_failed`, `expiring`, `unpin_pending`, `unpinned`
+    pub events: Vec<String>,
+    pub chain_id: Option<i64>,
+    pub donor: Option<String>,
+    /// signs the body with HMAC-SHA256, sent in `X-Hosq-Signature`
+    pub secret: Option<String>,
+}
+
+/// Thresholds used by `/readyz`
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Readiness {
+    /// a chain head older than `head_stale_blocks * block_time_sec` is stale
+    pub head_stale_blocks: u64,
+    /// max blocks an event watcher can be behind the chain head
+    pub max_indexer_lag_blocks: i64,
+}
+
+impl Readiness {
+    pub fn default() -> Self {
+        Self {
+            head_stale_blocks: 10,
+            max_indexer_lag_blocks: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Provider {
-    pub contract_address: String,
+    /// single deployment indexed from `start_block`, use `contracts` for several
+    pub contract_address: Option<String>,
+    pub contracts: Option<Vec<Contract>>,
     pub provider: String,
     pub chain_name: String,
     pub start_block: i64,
     pub block_time_sec: u64,
     pub block_update_sec: u64,
     pub log_update_sec: u64,
-    pub provider_id: i64,
+    /// single provider listing served, use `listings` for several
+    pub provider_id: Option<i64>,
+    pub listings: Option<Vec<Listing>>,
     pub batch_size: i64,
     pub skip_old: Option<bool>,
+    /// default pin policy of the listings
+    pub pin_policy: Option<PinPolicy>,
+    pub payment_check: Option<PaymentCheck>,
+    /// seconds expired CIDs stay pinned as `pending_unpin` before they are unpinned, `0` by default
+    pub unpin_grace_sec: Option<u64>,
+}
+
+/// One deployment of the registry contract, all deployments of a chain form one registry
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Contract {
+    pub address: String,
+    /// first block indexed, defaults to the provider's `start_block`
+    pub from_block: Option<i64>,
+    /// last block indexed, `None` for the live deployment
+    pub to_block: Option<i64>,
+    /// ABI its events are decoded with, defaults to `1`
+    pub abi_version: Option<u32>,
+}
+
+/// Provider id served on the chain, pinned to its own nodes and with its own pin policy if set
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Listing {
+    pub provider_id: i64,
+    /// `api_url` of the `ipfs_nodes` its CIDs are pinned to, all of them by default
+    pub nodes: Option<Vec<String>>,
+    pub pin_policy: Option<PinPolicy>,
+}
+
+impl Listing {
+    pub fn pin_policy(&self) -> PinPolicy {
+        self.pin_policy.clone().unwrap_or(PinPolicy::default())
+    }
+
+    /// Nodes of the listing among `nodes`
+    pub fn nodes_of(&self, nodes: &[IPFSNode]) -> Vec<IPFSNode> {
+        nodes
+            .iter()
+            .filter(|n| self.nodes.as_ref().is_none_or(|l| l.contains(&n.api_url)))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Provider {
+    /// Listings served with `pin_policy` filled in from the provider's one
+    pub fn listings(&self) -> Vec<Listing> {
+        let listings = match (&self.listings, self.provider_id) {
+            (Some(v), _) => v.clone(),
+            (None, Some(id)) => vec![Listing {
+                provider_id: id,
+                nodes: Option::None,
+                pin_policy: Option::None,
+            }],
+            (None, None) => vec![],
+        };
+        listings
+            .into_iter()
+            .map(|l| Listing {
+                pin_policy: l.pin_policy.or_else(|| self.pin_policy.clone()),
+                ..l
+            })
+            .collect()
+    }
+
+    /// Deployments to index with `from_block` and `abi_version` filled in
+    pub fn contracts(&self) -> Vec<Contract> {
+        let contracts = match (&self.contracts, &self.contract_address) {
+            (Some(v), _) => v.clone(),
+            (None, Some(a)) => vec![Contract {
+                address: a.clone(),
+                from_block: Option::None,
+                to_block: Option::None,
+                abi_version: Option::None,
+            }],
+            (None, None) => vec![],
+        };
+        contracts
+            .into_iter()
+            .map(|c| Contract {
+                from_block: Option::Some(c.from_block.unwrap_or(self.start_block)),
+                abi_version: Option::Some(c.abi_version.unwrap_or(1)),
+                ..c
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Whether a log emitted by `address` at `block` belongs to this deployment
+    pub fn covers(&self, address: &str, block: i64) -> bool {
+        let (a, b) = (self.address.trim_start_matches("0x"), address.trim_start_matches("0x"));
+        a.eq_ignore_ascii_case(b)
+            && self.from_block.is_none_or(|from| block >= from)
+            && self.to_block.is_none_or(|to| block <= to)
+    }
+}
+
+/// What to do with an `UpdateValidBlock` event whose `end_block` is beyond what its transaction paid
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentCheck {
+    /// index the event as is, without fetching its transaction
+    Off,
+    /// index the event as is and mark it `underpaid`
+    Flag,
+    /// index the event with `end_block` lowered to the paid one
+    Cap,
+}
+
+/// Trial duration given to a manually pinned CID depending on its size
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct SizeTier {
+    /// upper bound of the tier, `None` for no limit
+    pub max_bytes: Option<u64>,
+    /// how long the CID stays pinned, `None` to keep it forever
+    pub duration_sec: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct PinPolicy {
+    pub size_tiers: Vec<SizeTier>,
+}
+
+impl PinPolicy {
+    /// Up to 10 MiB are kept forever, anything bigger for 7 days
+    pub fn default() -> Self {
+        Self {
+            size_tiers: vec![
+                SizeTier {
+                    max_bytes: Some(10_485_760),
+                    duration_sec: None,
+                },
+                SizeTier {
+                    max_bytes: None,
+                    duration_sec: Some(604_800),
+                },
+            ],
+        }
+    }
+
+    /// Smallest tier that fits `size`, `None` if the CID is too big for a trial pin
+    pub fn tier_for(&self, size: u64) -> Option<&SizeTier> {
+        self.size_tiers
+            .iter()
+            .filter(|t| t.max_bytes.is_none_or(|m| size <= m))
+            .min_by_key(|t| t.max_bytes.unwrap_or(u64::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier(max_bytes: Option<u64>, duration_sec: Option<u64>) -> SizeTier {
+        SizeTier {
+            max_bytes,
+            duration_sec,
+        }
+    }
+
+    #[test]
+    fn tier_boundaries() {
+        let policy = PinPolicy::default();
+        assert_eq!(policy.tier_for(0).unwrap().duration_sec, None);
+        assert_eq!(policy.tier_for(10_485_760).unwrap().duration_sec, None);
+        assert_eq!(policy.tier_for(10_485_761).unwrap().duration_sec, Some(604_800));
+        assert_eq!(policy.tier_for(u64::MAX).unwrap().duration_sec, Some(604_800));
+    }
+
+    #[test]
+    fn smallest_fitting_tier_in_any_order() {
+        let policy = PinPolicy {
+            size_tiers: vec![
+                tier(Some(1_000), Some(60)),
+                tier(Some(100), Some(3_600)),
+                tier(Some(10_000), Some(1)),
+            ],
+        };
+        assert_eq!(policy.tier_for(100), Some(&tier(Some(100), Some(3_600))));
+        assert_eq!(policy.tier_for(101), Some(&tier(Some(1_000), Some(60))));
+        assert_eq!(policy.tier_for(10_000), Some(&tier(Some(10_000), Some(1))));
+        // too big for a trial pin without an unbounded tier
+        assert_eq!(policy.tier_for(10_001), None);
+        assert_eq!(PinPolicy { size_tiers: vec![] }.tier_for(0), None);
+    }
+
+    fn contract(from_block: Option<i64>, to_block: Option<i64>) -> Contract {
+        Contract {
+            address: "0x9ce4cd6D7f5e8b14c7a3e8e6A257A86Bd5a6EeA0".to_owned(),
+            from_block,
+            to_block,
+            abi_version: Option::None,
+        }
+    }
+
+    #[test]
+    fn contract_block_range() {
+        let address = "9ce4cd6d7f5e8b14c7a3e8e6a257a86bd5a6eea0";
+        let c = contract(Some(100), Some(200));
+        assert!(!c.covers(address, 99));
+        assert!(c.covers(address, 100));
+        assert!(c.covers(address, 200));
+        assert!(!c.covers(address, 201));
+
+        let live = contract(Some(201), None);
+        assert!(!live.covers(address, 200));
+        assert!(live.covers(address, i64::MAX));
+        assert!(contract(None, None).covers(address, 0));
+    }
+
+    #[test]
+    fn contract_address() {
+        let c = contract(None, None);
+        assert!(c.covers("0x9CE4CD6D7F5E8B14C7A3E8E6A257A86BD5A6EEA0", 1));
+        assert!(!c.covers("0x0000000000000000000000000000000000000000", 1));
+    }
 }
@@ -1,37 +1,53 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventUpdateValidBlock {
     pub chain_id: i64,
-    pub cid: String,
+    pub provider_id: i64,
+    pub cid: String,          // canonical, see `utils::cid`
+    pub cid_original: String, // as emitted or requested, for display
     pub donor: String,
     pub update_block: i64,
     pub end_block: i64,
     pub manual_add: Option<bool>,
+    pub claimed_end_block: Option<i64>, // as emitted, set when `end_block` was capped
+    pub paid_wei: Option<String>,       // decimal, paid by the transaction for this event
+    pub paid_end_block: Option<i64>,    // block the payment covers up to
+    pub payment: Option<String>,        // one of `contract_watcher::PAYMENT_*`, `None` for manual adds
+    pub tx_hash: Option<String>,        // with `log_index`, `None` for manual adds
+    pub log_index: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EventAddProvider {
+/// Row of `provider_events`, update events only set the field they change
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProviderEvent {
     pub chain_id: i64,
-    pub update_block: i64,
-    pub owner: String,
     pub provider_id: i64,
-    pub block_price_gwei: i64,
-    pub api_url: String,
-    pub name: String,
+    pub event: String, // event name, e.g. `UpdateProviderBlockPrice`
+    pub update_block: i64,
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub owner: Option<String>,
+    pub block_price_gwei: Option<i64>,
+    pub api_url: Option<String>,
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PinnedCIDs {
     pub update_block: i64,
     pub donor: String,
     pub end_block: i64,
     pub cid: String,
+    pub cid_original: String,
     pub node_count: i64,
     pub failed_node_count: i64,
+    pub pending_unpin: bool, // expired but still pinned during the chain's grace period
+    pub expires_at: Option<i64>, // estimated unix time (sec.) of `end_block`, see `Web3Node::block_timestamp`
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct EventAddProviderResponse {
     pub provider_id: i64,
     pub block_price_gwei: i64,
@@ -40,7 +56,7 @@ pub struct EventAddProviderResponse {
     pub update_block: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct CIDInfo {
     pub pinned_chain_id: Option<i64>,
     pub pinned_node_count: Option<i64>,
@@ -48,4 +64,124 @@ pub struct CIDInfo {
     pub failed_chain_id: Option<i64>,
     pub failed_node_count: Option<i64>,
     pub failed_end_block: Option<i64>,
+    pub expires_at: Option<i64>, // estimated unix time (sec.) of `pinned_end_block`
+}
+
+/// CID whose pin ends soon, see `/v0/cid/expiring`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ExpiringCID {
+    pub cid: String,
+    pub end_block: i64,
+    pub blocks_left: i64,
+    pub expires_at: Option<i64>, // estimated unix time (sec.) of `end_block`
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NodePin {
+    pub chain_id: i64,
+    pub provider_id: i64,
+    pub node: String,
+    pub cid: String,
+    pub end_block: i64,
+    pub pinned: bool, // false for failed pins
+}
+
+/// Contract event dropped by `utils::sanitize`, row of `rejected_events`
+#[derive(Debug, Clone)]
+pub struct RejectedEvent {
+    pub chain_id: i64,
+    pub event: String,
+    pub update_block: Option<i64>,
+    pub tx_hash: Option<String>,
+    pub data: String, // hex encoded log data
+    pub reason: String,
+    pub ts: i64, // unix millis
+}
+
+/// Row of `quarantined_logs`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuarantinedLog {
+    pub id: i64,
+    pub chain_id: i64,
+    pub event: String,
+    pub block_number: Option<i64>,
+    pub tx_hash: Option<String>,
+    pub topics: Vec<String>,
+    pub data: String, // hex encoded
+    #[serde(skip)]
+    pub log: serde_json::Value, // the whole log, replayed on reprocess
+    pub error: String,
+    pub ts: i64, // unix millis
+    pub reprocessed_at: Option<i64>,
+}
+
+/// `UpdateValidBlock` event with the outcome of its payment verification
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PaymentEvent {
+    pub chain_id: i64,
+    pub cid: String,
+    pub donor: String,
+    pub update_block: i64,
+    pub end_block: i64,
+    pub claimed_end_block: Option<i64>,
+    pub paid_wei: Option<String>,
+    pub paid_end_block: Option<i64>,
+    pub payment: String,
+}
+
+/// `UpdateValidBlock` events indexed from `from_block` to the chain head.
+///
+/// A top up only counts the blocks past the CID's previous `end_block`, `revenue_wei` prices the
+/// blocks sold at the provider's `block_price_gwei` when they were bought, `paid_wei` sums verified payments
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StatsWindow {
+    pub window_sec: u64, // 0 since the first indexed block
+    pub from_block: i64,
+    pub events: i64,
+    pub cids: i64,
+    pub donors: i64,
+    pub blocks_sold: i64,
+    pub revenue_wei: String, // decimal
+    pub paid_wei: String,    // decimal
+}
+
+/// Bytes pinned for a chain, CIDs pinned on several nodes count once
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChainStorage {
+    pub chain_id: i64,
+    pub cids: i64,
+    pub size: i64,
+    pub num_blocks: i64,
+    pub unsized_cids: i64, // pinned CIDs whose size is not known yet
+}
+
+/// Bytes pinned on a node, CIDs pinned for several chains count once
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NodeStorage {
+    pub node: String,
+    pub cids: i64,
+    pub size: i64,
+    pub num_blocks: i64,
+    pub unsized_cids: i64,
+}
+
+/// Bytes pinned for the CIDs a donor paid for on a chain
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DonorStorage {
+    pub chain_id: i64,
+    pub donor: String,
+    pub cids: i64,
+    pub size: i64,
+    pub num_blocks: i64,
+    pub unsized_cids: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub url: String,
+    pub subscription: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
 }
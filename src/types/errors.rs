@@ -3,6 +3,8 @@ use std::fmt;
 #[derive(Debug)]
 pub enum CustomError {
     Inequality(String), // InvalidAbiString
+    InvalidConfig(Vec<String>),
+    Malformed(String), // log that can not be decoded
 }
 
 impl std::error::Error for CustomError {}
@@ -11,6 +13,8 @@ impl fmt::Display for CustomError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             CustomError::Inequality(m) => write!(f, "Mismatching values: {}", m),
+            CustomError::InvalidConfig(p) => write!(f, "Invalid config:\n{}", p.join("\n")),
+            CustomError::Malformed(m) => write!(f, "Malformed log: {}", m),
             // CustomError::InvalidAbiString => write!(f, "Invalid abi encoded string"),
         }
     }
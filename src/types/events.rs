@@ -0,0 +1,15 @@
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// Pin lifecycle event pushed to `/v0/events/stream` subscribers
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct PinEvent {
+    pub event: String,
+    pub chain_id: i64,
+    pub cid: String,
+    pub donors: Vec<String>,
+    pub ts: i64, // unix millis
+    #[schema(value_type = Object)]
+    pub data: Value,
+}
@@ -1,20 +1,25 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     sync::{Arc, Mutex},
 };
 
 use rocket_sync_db_pools::{database, postgres};
 use serde::{Deserialize, Serialize};
 use web3::transports::WebSocket;
+use web3::types::{H256, U256};
 
+pub mod api;
+pub mod auth;
 pub mod config;
 pub mod db;
 pub mod errors;
+pub mod events;
 pub mod monitoring;
 
 #[database("pg")]
 // #[derive(Debug)]
 pub struct DbConn(postgres::Client);
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct IPFSAddResponse {
     #[serde(alias = "Name")]
@@ -40,26 +45,130 @@ pub struct IPFSAddResponse {
 //     }
 // }
 
+/// Chain heads kept to estimate the block time, see `Web3Node::block_time_ms`
+pub const HEAD_SAMPLES: usize = 256;
+/// Blocks the samples must span before the observed block time replaces `block_time_sec`
+pub const MIN_SAMPLED_BLOCKS: i64 = 10;
+
+/// Keeps the last `HEAD_SAMPLES` heads, a head not above the last one is ignored
+fn push_head(samples: &mut VecDeque<(i64, i64)>, block: i64, now: i64) {
+    if samples.back().is_none_or(|(b, _)| block > *b) {
+        samples.push_back((block, now));
+        if samples.len() > HEAD_SAMPLES {
+            samples.pop_front();
+        }
+    }
+}
+
+/// Average block time over `samples`, `block_time_sec` until they span `MIN_SAMPLED_BLOCKS`
+fn sampled_block_time_ms(samples: &VecDeque<(i64, i64)>, block_time_sec: u64) -> f64 {
+    match (samples.front(), samples.back()) {
+        (Some((b0, t0)), Some((b1, t1))) if b1 - b0 >= MIN_SAMPLED_BLOCKS && t1 > t0 => {
+            (t1 - t0) as f64 / (b1 - b0) as f64
+        }
+        _ => (block_time_sec * 1000) as f64,
+    }
+}
+
+/// Unix time (sec.) of `block` counted from a `(block, unix millis)` head
+fn estimate_timestamp((head, at): (i64, i64), block: i64, block_time_ms: f64) -> i64 {
+    let ms = at as f64 + (block - head) as f64 * block_time_ms;
+    (ms / 1000.0) as i64
+}
+
 #[derive(Debug, Clone)]
 pub struct Web3Node {
-    pub contract_address: String,
+    pub contracts: Vec<config::Contract>, // see `config::Provider::contracts`
     pub url: String,
     pub chain_name: String,
-    pub start_block: i64,
+    pub start_block: i64, // earliest `from_block` of the contracts
     pub block_time_sec: u64,
     pub block_update_sec: u64,
-    pub provider_id: i64,
+    pub listings: Vec<config::Listing>, // see `config::Provider::listings`
     pub chain_id: i64, //postgres takes i64
     pub batch_size: i64,
     pub log_update_sec: u64,
     pub skip_old: Option<bool>,
+    pub payment_check: config::PaymentCheck,
+    pub unpin_grace_sec: u64,
     pub web3: Arc<Mutex<web3::Web3<WebSocket>>>,
     pub latest_block: Arc<Mutex<Option<i64>>>,
+    pub head_samples: Arc<Mutex<VecDeque<(i64, i64)>>>, // (block, unix millis) heads seen, oldest first
+    pub paused: Arc<Mutex<bool>>,
+    pub stopped: Arc<Mutex<bool>>, // set when the chain is removed from the config
+    pub resync_from: Arc<Mutex<HashMap<String, i64>>>, // event signature -> block
+    pub paid_wei: Arc<Mutex<HashMap<H256, U256>>>, // tx hash -> wei paid per `UpdateValidBlock` log
+}
+
+impl Web3Node {
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Makes every task watching this chain exit on its next iteration
+    pub fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+    }
+
+    /// Listing of `provider_id` if it is served on this chain
+    pub fn listing(&self, provider_id: i64) -> Option<&config::Listing> {
+        self.listings.iter().find(|l| l.provider_id == provider_id)
+    }
+
+    /// Whether a log comes from one of the chain's contracts within the blocks it is indexed for
+    pub fn is_registry_log(&self, l: &web3::types::Log) -> bool {
+        let (address, block) = (hex::encode(l.address.as_bytes()), l.block_number);
+        match block {
+            Some(b) => self.contracts.iter().any(|c| c.covers(&address, b.as_u64() as i64)),
+            None => true, // quarantined by the handler
+        }
+    }
+
+    /// Records the chain head seen at `now` (unix millis)
+    pub fn sample_head(&self, block: i64, now: i64) {
+        push_head(&mut self.head_samples.lock().unwrap(), block, now);
+    }
+
+    /// Average block time over the sampled heads, `block_time_sec` until they span enough blocks
+    pub fn block_time_ms(&self) -> f64 {
+        sampled_block_time_ms(&self.head_samples.lock().unwrap(), self.block_time_sec)
+    }
+
+    /// Estimated unix time (sec.) of `block`, `None` for `-1` (never expires) or before the first head
+    pub fn block_timestamp(&self, block: i64) -> Option<i64> {
+        if block == -1 {
+            return Option::None;
+        }
+        let head = { self.head_samples.lock().unwrap().back().cloned() };
+        let (head, at) = match head {
+            Some(v) => v,
+            None => {
+                let bn = { *self.latest_block.lock().unwrap() };
+                (bn?, chrono::Utc::now().timestamp_millis())
+            }
+        };
+        Option::Some(estimate_timestamp((head, at), block, self.block_time_ms()))
+    }
+
+    /// Blocks expected within `sec` seconds
+    pub fn blocks_within(&self, sec: u64) -> i64 {
+        (sec as f64 * 1000.0 / self.block_time_ms()) as i64
+    }
+
+    /// Block the watcher of `event` was asked to restart from, if any
+    pub fn take_resync(&self, event: &str) -> Option<i64> {
+        self.resync_from.lock().unwrap().remove(event)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct CIDInfo {
     pub chain_id: Option<i64>,
+    pub provider_id: Option<i64>,
     pub cid: Option<String>,
     pub end_block: Option<i64>,
     pub node: Option<String>,       // used for failed pin service
@@ -67,6 +176,16 @@ pub struct CIDInfo {
     pub node_pass: Option<String>,  // used for failed pin service
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IpfsRepoStat {
+    #[serde(alias = "RepoSize")]
+    pub repo_size: u64,
+    #[serde(alias = "StorageMax")]
+    pub storage_max: u64,
+    #[serde(alias = "NumObjects")]
+    pub num_objects: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct IpfsDagStat {
     #[serde(alias = "NumBlocks")]
@@ -82,8 +201,72 @@ pub struct IpfsDagStat {
 // }
 #[derive(Debug)]
 pub struct State {
-    pub nodes: Arc<Vec<config::IPFSNode>>,
-    pub providers: Arc<Vec<Web3Node>>,
+    // swapped on config reload, see `services::config_watcher`
+    pub nodes: Arc<Mutex<Arc<Vec<config::IPFSNode>>>>,
+    pub providers: Arc<Mutex<Arc<Vec<Web3Node>>>>,
     pub admin_secret: String,
+    pub session_ttl_sec: u64,
+    pub admin_token_max_ttl_sec: u64,
+    pub siwe_domain: Option<String>,
     pub monitoring: Arc<Mutex<HashMap<u64, monitoring::Monitoring>>>, // block_numbers: Vec<BlockNum>
+    pub drained_nodes: Arc<Mutex<HashSet<String>>>, // api_url of nodes not receiving new pins
+    pub node_capacity: Arc<Mutex<HashMap<String, monitoring::NodeCapacity>>>, // api_url -> last `repo/stat`
+    pub readiness: config::Readiness,
+    pub only_api: bool, // no watchers running, chains are not checked by `/readyz`
+}
+
+impl State {
+    /// Snapshot of the currently configured IPFS nodes
+    pub fn nodes(&self) -> Arc<Vec<config::IPFSNode>> {
+        self.nodes.lock().unwrap().clone()
+    }
+
+    /// Snapshot of the currently watched chains
+    pub fn providers(&self) -> Arc<Vec<Web3Node>> {
+        self.providers.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_time_ms() {
+        let mut samples = VecDeque::new();
+        assert_eq!(sampled_block_time_ms(&samples, 15), 15_000.0);
+
+        push_head(&mut samples, 100, 1_000_000);
+        push_head(&mut samples, 109, 1_018_000);
+        // 9 blocks are not enough
+        assert_eq!(sampled_block_time_ms(&samples, 15), 15_000.0);
+        push_head(&mut samples, 110, 1_020_000);
+        assert_eq!(sampled_block_time_ms(&samples, 15), 2_000.0);
+
+        // reorgs and repeated heads are not sampled
+        push_head(&mut samples, 110, 1_030_000);
+        push_head(&mut samples, 105, 1_030_000);
+        assert_eq!(samples.len(), 3);
+        assert_eq!(sampled_block_time_ms(&samples, 15), 2_000.0);
+    }
+
+    #[test]
+    fn head_samples_are_bounded() {
+        let mut samples = VecDeque::new();
+        for b in 0..(HEAD_SAMPLES as i64 + 10) {
+            push_head(&mut samples, b, b * 1_000);
+        }
+        assert_eq!(samples.len(), HEAD_SAMPLES);
+        assert_eq!(samples.front(), Some(&(10, 10_000)));
+        assert_eq!(sampled_block_time_ms(&samples, 15), 1_000.0);
+    }
+
+    #[test]
+    fn block_timestamp() {
+        let head = (1_000, 1_700_000_000_000);
+        assert_eq!(estimate_timestamp(head, 1_000, 2_000.0), 1_700_000_000);
+        assert_eq!(estimate_timestamp(head, 1_030, 2_000.0), 1_700_000_060);
+        assert_eq!(estimate_timestamp(head, 970, 2_000.0), 1_699_999_940);
+        assert_eq!(estimate_timestamp(head, 1_001, 12_500.5), 1_700_000_012);
+    }
 }
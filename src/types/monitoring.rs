@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+
 use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct Event {
     pub event: String,
     pub last_update: i64,
@@ -9,12 +12,17 @@ pub struct Event {
     pub count: usize,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct Monitoring {
     pub current_block: u64,
     pub socket_create_time: i64,
+    pub head_change_time: i64, // last time `current_block` moved
+    pub block_time_ms: f64,    // observed average, see `Web3Node::block_time_ms`
     pub chain_name: String,
     pub events: Vec<Event>,
+    pub indexed_blocks: HashMap<String, i64>, // event -> block indexed up to
+    pub quarantined_logs: u64,                // since start, see `quarantined_logs` table
+    pub nodes_full: bool, // every node serving the chain is above its high-water mark
 }
 
 impl Monitoring {
@@ -22,8 +30,45 @@ impl Monitoring {
         Self {
             current_block: 0,
             socket_create_time: 0,
+            head_change_time: 0,
+            block_time_ms: 0.0,
             chain_name: "".to_owned(),
             events: vec![],
+            indexed_blocks: HashMap::new(),
+            quarantined_logs: 0,
+            nodes_full: false,
         }
     }
 }
+
+pub const KIND_WATCHER: &str = "watcher";
+pub const KIND_PIN_BATCH: &str = "pin_batch";
+pub const KIND_UNPIN_BATCH: &str = "unpin_batch";
+pub const KIND_RETRY_BATCH: &str = "retry_batch";
+pub const KIND_SOCKET_RECONNECT: &str = "socket_reconnect";
+pub const KIND_NODES_FULL: &str = "nodes_full";
+
+/// Repo usage of an IPFS node, see `ipfs_watcher::watch_node_capacity`
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct NodeCapacity {
+    pub node: String,
+    pub repo_size: u64,
+    pub storage_max: u64,
+    pub num_objects: u64,
+    pub used_pct: f64,
+    pub high_water_pct: f64,
+    pub full: bool,      // above `high_water_pct`, gets no new pins
+    pub updated_at: i64, // unix millis of the last successful poll
+}
+
+/// Row of `monitoring_history`
+#[derive(Debug, Serialize, Clone, ToSchema)]
+pub struct HistoryEvent {
+    pub chain_id: i64,
+    pub kind: String,
+    pub event: String,
+    pub ts: i64, // unix millis
+    pub update_block: i64,
+    pub duration_ms: i64,
+    pub count: i64,
+}
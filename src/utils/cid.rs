@@ -0,0 +1,31 @@
+use cid::Cid;
+
+/// Form CIDs are stored and compared in: CIDv1 in base32, e.g. `bafy...`.
+///
+/// Accepts CIDv0 (`Qm...`), CIDv1 in any multibase and `/ipfs/<cid>` paths
+pub fn canonical(cid: &str) -> Result<String, cid::Error> {
+    let c = cid.trim().parse::<Cid>()?;
+    Ok(c.into_v1()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::canonical;
+
+    const V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const V1: &str = "bafybeie5nqv6kd3qnfjupgvz34woh3oksc3iau6abmyajn7qvtf6d2ho34";
+
+    #[test]
+    fn v0_and_v1_are_equivalent() {
+        assert_eq!(canonical(V0).unwrap(), V1);
+        assert_eq!(canonical(V1).unwrap(), V1);
+        assert_eq!(canonical(&format!(" {} ", V0)).unwrap(), V1);
+        assert_eq!(canonical(&format!("/ipfs/{}", V0)).unwrap(), V1);
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        assert!(canonical("").is_err());
+        assert!(canonical("not-a-cid").is_err());
+    }
+}
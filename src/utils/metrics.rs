@@ -0,0 +1,167 @@
+use std::sync::OnceLock;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+/// Prometheus metrics served at `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    /// labels: chain_id, chain_name
+    pub chain_head_block: IntGaugeVec,
+    /// labels: chain_id, event
+    pub indexed_block: IntGaugeVec,
+    /// labels: chain_id, event
+    pub log_batch_duration: HistogramVec,
+    /// labels: node, result
+    pub pins: IntCounterVec,
+    /// labels: node, result
+    pub unpins: IntCounterVec,
+    /// labels: chain_id
+    pub failed_pins_queue: IntGaugeVec,
+    /// labels: chain_id, event
+    pub rejected_events: IntCounterVec,
+    /// labels: chain_id, event
+    pub quarantined_logs: IntCounterVec,
+    /// labels: chain_id, payment
+    pub payments: IntCounterVec,
+    /// labels: node
+    pub node_repo_size: IntGaugeVec,
+    /// labels: node
+    pub node_storage_max: IntGaugeVec,
+    /// labels: node
+    pub node_repo_objects: IntGaugeVec,
+    /// labels: node
+    pub node_full: IntGaugeVec,
+    /// labels: chain_id
+    pub chain_nodes_full: IntGaugeVec,
+    /// labels: route, result
+    pub proxy_request_duration: HistogramVec,
+    pub upload_bytes: IntCounter,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// `result` label value
+pub fn result(ok: bool) -> &'static str {
+    if ok {
+        "success"
+    } else {
+        "failure"
+    }
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("hosq".to_owned()), None).unwrap();
+        let m = Self {
+            chain_head_block: IntGaugeVec::new(
+                Opts::new("chain_head_block", "Latest block seen on the chain"),
+                &["chain_id", "chain_name"],
+            )
+            .unwrap(),
+            indexed_block: IntGaugeVec::new(
+                Opts::new("indexed_block", "Block up to which an event is indexed"),
+                &["chain_id", "event"],
+            )
+            .unwrap(),
+            log_batch_duration: HistogramVec::new(
+                HistogramOpts::new("log_batch_duration_seconds", "Time to fetch and store a batch of logs")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+                &["chain_id", "event"],
+            )
+            .unwrap(),
+            pins: IntCounterVec::new(Opts::new("pins_total", "Pin requests sent to nodes"), &["node", "result"])
+                .unwrap(),
+            unpins: IntCounterVec::new(
+                Opts::new("unpins_total", "Unpin requests sent to nodes"),
+                &["node", "result"],
+            )
+            .unwrap(),
+            failed_pins_queue: IntGaugeVec::new(
+                Opts::new("failed_pins_queue", "Failed pins waiting to be retried"),
+                &["chain_id"],
+            )
+            .unwrap(),
+            rejected_events: IntCounterVec::new(
+                Opts::new("rejected_events_total", "Contract events dropped by validation"),
+                &["chain_id", "event"],
+            )
+            .unwrap(),
+            quarantined_logs: IntCounterVec::new(
+                Opts::new("quarantined_logs_total", "Contract logs that could not be decoded"),
+                &["chain_id", "event"],
+            )
+            .unwrap(),
+            payments: IntCounterVec::new(
+                Opts::new("payments_total", "UpdateValidBlock events by payment verification outcome"),
+                &["chain_id", "payment"],
+            )
+            .unwrap(),
+            node_repo_size: IntGaugeVec::new(
+                Opts::new("node_repo_size_bytes", "Repo size reported by the node's repo/stat"),
+                &["node"],
+            )
+            .unwrap(),
+            node_storage_max: IntGaugeVec::new(
+                Opts::new("node_storage_max_bytes", "StorageMax reported by the node's repo/stat"),
+                &["node"],
+            )
+            .unwrap(),
+            node_repo_objects: IntGaugeVec::new(
+                Opts::new("node_repo_objects", "Objects in the node's repo"),
+                &["node"],
+            )
+            .unwrap(),
+            node_full: IntGaugeVec::new(
+                Opts::new("node_full", "1 when the node is above its high-water mark and gets no new pins"),
+                &["node"],
+            )
+            .unwrap(),
+            chain_nodes_full: IntGaugeVec::new(
+                Opts::new("chain_nodes_full", "1 when every node serving the chain is above its high-water mark"),
+                &["chain_id"],
+            )
+            .unwrap(),
+            proxy_request_duration: HistogramVec::new(
+                HistogramOpts::new("proxy_request_duration_seconds", "Latency of requests proxied to IPFS")
+                    .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 120.0]),
+                &["route", "result"],
+            )
+            .unwrap(),
+            upload_bytes: IntCounter::new("upload_bytes_total", "Bytes uploaded through the proxy").unwrap(),
+            registry,
+        };
+        m.registry.register(Box::new(m.chain_head_block.clone())).unwrap();
+        m.registry.register(Box::new(m.indexed_block.clone())).unwrap();
+        m.registry.register(Box::new(m.log_batch_duration.clone())).unwrap();
+        m.registry.register(Box::new(m.pins.clone())).unwrap();
+        m.registry.register(Box::new(m.unpins.clone())).unwrap();
+        m.registry.register(Box::new(m.failed_pins_queue.clone())).unwrap();
+        m.registry.register(Box::new(m.rejected_events.clone())).unwrap();
+        m.registry.register(Box::new(m.quarantined_logs.clone())).unwrap();
+        m.registry.register(Box::new(m.payments.clone())).unwrap();
+        m.registry.register(Box::new(m.node_repo_size.clone())).unwrap();
+        m.registry.register(Box::new(m.node_storage_max.clone())).unwrap();
+        m.registry.register(Box::new(m.node_repo_objects.clone())).unwrap();
+        m.registry.register(Box::new(m.node_full.clone())).unwrap();
+        m.registry.register(Box::new(m.chain_nodes_full.clone())).unwrap();
+        m.registry.register(Box::new(m.proxy_request_duration.clone())).unwrap();
+        m.registry.register(Box::new(m.upload_bytes.clone())).unwrap();
+        m
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buf = vec![];
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            error!("Error encoding metrics > {}", e);
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
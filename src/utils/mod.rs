@@ -1 +1,6 @@
-pub mod proxy;
\ No newline at end of file
+pub mod cid;
+pub mod metrics;
+pub mod pagination;
+pub mod proxy;
+pub mod sanitize;
+pub mod siwe;
\ No newline at end of file
@@ -0,0 +1,222 @@
+use postgres::{types::ToSql, Row};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+pub const DEFAULT_LIMIT: i64 = 100;
+pub const MAX_LIMIT: i64 = 500;
+
+/// One page of a list endpoint, `next_cursor` is `None` on the last page
+#[derive(Debug, Serialize, ToSchema)]
+#[aliases(
+    PinnedCIDsPage = Page<crate::types::db::PinnedCIDs>,
+    ProvidersPage = Page<crate::types::db::EventAddProviderResponse>,
+    ProviderHistoryPage = Page<crate::types::db::ProviderEvent>,
+    HistoryPage = Page<crate::types::monitoring::HistoryEvent>
+)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum KeyType {
+    Int,
+    Text,
+}
+
+/// Sort expression over the columns of the listed query, the last key must make rows unique
+pub type SortKey = (&'static str, KeyType);
+
+/// `NULL` sorts as `''` or `-1` so it can be compared with and stored in a cursor
+fn sort_expr((e, t): &SortKey) -> String {
+    match t {
+        KeyType::Int => format!("COALESCE(({})::BIGINT, -1)", e),
+        KeyType::Text => format!("COALESCE(({})::TEXT, '')", e),
+    }
+}
+
+pub fn limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+/// `asc` (default) or `desc`, `None` for anything else
+pub fn is_desc(order: &Option<String>) -> Option<bool> {
+    match order.as_deref() {
+        None | Some("asc") => Some(false),
+        Some("desc") => Some(true),
+        _ => None,
+    }
+}
+
+fn encode_cursor(values: &[Value]) -> String {
+    hex::encode(Value::Array(values.to_vec()).to_string())
+}
+
+fn decode_cursor(cursor: &str) -> Option<Vec<Value>> {
+    let raw = hex::decode(cursor).ok()?;
+    match serde_json::from_slice(&raw).ok()? {
+        Value::Array(v) => Some(v),
+        _ => None,
+    }
+}
+
+/// Keyset paginated `SELECT` over a base query.
+///
+/// Parameters of the base query have to be bound first so their placeholders come first
+#[derive(Default)]
+pub struct PagedQuery {
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+    filters: Vec<String>,
+}
+
+impl PagedQuery {
+    /// Adds a parameter, returns its placeholder
+    pub fn bind<T: ToSql + Sync + Send + 'static>(&mut self, v: T) -> String {
+        self.params.push(Box::new(v));
+        format!("${}", self.params.len())
+    }
+
+    /// Condition over the columns of the base query
+    pub fn filter(&mut self, f: String) {
+        self.filters.push(f);
+    }
+
+    /// Restricts to rows after `cursor`, returns `false` if the cursor does not fit `keys`
+    pub fn after(&mut self, cursor: &str, keys: &[SortKey], desc: bool) -> bool {
+        let values = match decode_cursor(cursor) {
+            Some(v) if v.len() == keys.len() => v,
+            _ => return false,
+        };
+        let mut placeholders = vec![];
+        for ((_, t), v) in keys.iter().zip(values) {
+            let p = match (t, v) {
+                (KeyType::Int, Value::Number(n)) if n.is_i64() => {
+                    format!("{}::BIGINT", self.bind(n.as_i64().unwrap()))
+                }
+                (KeyType::Text, Value::String(s)) => format!("{}::TEXT", self.bind(s)),
+                _ => return false,
+            };
+            placeholders.push(p);
+        }
+        let exprs: Vec<String> = keys.iter().map(sort_expr).collect();
+        self.filter(format!(
+            "({}) {} ({})",
+            exprs.join(", "),
+            if desc { "<" } else { ">" },
+            placeholders.join(", ")
+        ));
+        true
+    }
+
+    /// Runs the query, `map` gets the rows of the base query
+    pub fn run<T>(
+        self,
+        client: &mut postgres::Client,
+        base: &str,
+        keys: &[SortKey],
+        desc: bool,
+        limit: i64,
+        map: impl Fn(&Row) -> T,
+    ) -> Result<Page<T>, postgres::Error> {
+        let dir = if desc { "DESC" } else { "ASC" };
+        let selected: Vec<String> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, k)| format!("{} AS sort_key_{}", sort_expr(k), i))
+            .collect();
+        let order: Vec<String> = keys.iter().map(|k| format!("{} {}", sort_expr(k), dir)).collect();
+        let filters = if self.filters.is_empty() {
+            "TRUE".to_owned()
+        } else {
+            self.filters.join(" AND ")
+        };
+        let sql = format!(
+            "SELECT q.*, {} FROM ({}) q WHERE {} ORDER BY {} LIMIT {}",
+            selected.join(", "),
+            base,
+            filters,
+            order.join(", "),
+            limit + 1
+        );
+        let params: Vec<&(dyn ToSql + Sync)> = self
+            .params
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+            .collect();
+        let mut rows = client.query(sql.as_str(), &params)?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last().map(|r| {
+                let values: Vec<Value> = keys
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, t))| {
+                        let name = format!("sort_key_{}", i);
+                        match t {
+                            KeyType::Int => Value::from(r.get::<_, i64>(name.as_str())),
+                            KeyType::Text => Value::from(r.get::<_, String>(name.as_str())),
+                        }
+                    })
+                    .collect();
+                encode_cursor(&values)
+            })
+        } else {
+            None
+        };
+
+        Ok(Page {
+            items: rows.iter().map(map).collect(),
+            next_cursor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: [SortKey; 2] = [("end_block", KeyType::Int), ("cid", KeyType::Text)];
+
+    #[test]
+    fn cursor_round_trip() {
+        let values = vec![Value::from(-1), Value::from("bafy")];
+        let cursor = encode_cursor(&values);
+        assert_eq!(decode_cursor(&cursor), Some(values));
+
+        let mut q = PagedQuery::default();
+        assert!(q.after(&cursor, &KEYS, false));
+        assert_eq!(
+            q.filters,
+            vec!["(COALESCE((end_block)::BIGINT, -1), COALESCE((cid)::TEXT, '')) > ($1::BIGINT, $2::TEXT)"]
+        );
+        assert_eq!(q.params.len(), 2);
+    }
+
+    #[test]
+    fn tampered_cursors() {
+        for cursor in [
+            "zz".to_owned(),
+            hex::encode("not json"),
+            hex::encode(r#"{"end_block": 1}"#),
+            encode_cursor(&[Value::from(1)]),
+            encode_cursor(&[Value::from("1"), Value::from("bafy")]),
+            encode_cursor(&[Value::from(1.5), Value::from("bafy")]),
+            encode_cursor(&[Value::from(1), Value::from(2)]),
+        ] {
+            let mut q = PagedQuery::default();
+            assert!(!q.after(&cursor, &KEYS, true), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(limit(None), DEFAULT_LIMIT);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(MAX_LIMIT + 1)), MAX_LIMIT);
+        assert_eq!(is_desc(&Some("desc".to_owned())), Some(true));
+        assert_eq!(is_desc(&None), Some(false));
+        assert_eq!(is_desc(&Some("up".to_owned())), None);
+    }
+}
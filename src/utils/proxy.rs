@@ -17,6 +17,8 @@ use rocket::{
 };
 use serde_json::Value;
 
+use crate::utils::metrics::metrics;
+
 struct ProxySynchronizer {
     sender: Option<Sender>,
 }
@@ -54,7 +56,7 @@ impl AsyncWrite for ProxySynchronizer {
             _ => {}
         }
 
-        match sender.try_send_data(hyper::body::Bytes::copy_from_slice(&buf[..])) {
+        match sender.try_send_data(hyper::body::Bytes::copy_from_slice(buf)) {
             Ok(_) => std::task::Poll::Ready(Ok(buf.len())),
             Err(e) => std::task::Poll::Ready(Err(std::io::Error::new(
                 ErrorKind::UnexpectedEof,
@@ -83,20 +85,21 @@ pub async fn upload_to_ipfs(r: &Request<'_>, data: Data<'_>) -> Result<Value, an
         Some(v) => v,
         None => return Err(anyhow!("Error Getting rocket state")),
     };
-    let node_index = if state.nodes.len() == 1 {
+    let nodes = state.nodes();
+    let node_index = if nodes.len() == 1 {
         0
     } else {
         let mut rng: StdRng = rand::SeedableRng::from_entropy();
-        rng.gen_range(0..state.nodes.len() - 1)
+        rng.gen_range(0..nodes.len() - 1)
     };
     let uri_string = match r.query_value::<bool>("dir") {
-            Some(b) if Ok(true)==b => format!(
+            Some(Ok(true)) => format!(
                 "{}/api/v0/add?progress=false&pin=false&wrap-with-directory=true&cid-version=1&silent=true",
-                state.nodes[node_index].api_url
+                nodes[node_index].api_url
             ),
             _ => format!(
                 "{}/api/v0/add?progress=false&pin=false&cid-version=1&quieter=true",
-                state.nodes[node_index].api_url
+                nodes[node_index].api_url
             )
         };
 
@@ -111,17 +114,17 @@ pub async fn upload_to_ipfs(r: &Request<'_>, data: Data<'_>) -> Result<Value, an
     for h in r.headers().clone().into_iter() {
         headers.insert(
             HeaderName::from_str(h.name().as_str())?,
-            HeaderValue::from_str(&h.value().to_owned())?,
+            HeaderValue::from_str(h.value())?,
         );
     }
     let mut ps = ProxySynchronizer::new();
     let data = data.open(100.mebibytes());
     let (proxy_req, data_in) = join!(web_client.request(proxy_req.body(ps.get_body())?), async {
-        data.stream_to(ps).await?;
-        Ok::<(), anyhow::Error>(())
+        let n = data.stream_to(ps).await?;
+        Ok::<u64, anyhow::Error>(n.written)
     });
 
-    data_in?;
+    metrics().upload_bytes.inc_by(data_in?);
 
     Ok(serde_json::from_slice::<Value>(
         &match proxy_req?.body_mut().data().await {
@@ -136,15 +139,16 @@ pub async fn get_from_ipfs(r: &Request<'_>) -> Result<Response, anyhow::Error> {
         Some(v) => v,
         None => return Err(anyhow!("Error Getting rocket state")),
     };
-    let node_index = if state.nodes.len() == 1 {
+    let nodes = state.nodes();
+    let node_index = if nodes.len() == 1 {
         0
     } else {
         let mut rng: StdRng = rand::SeedableRng::from_entropy();
-        rng.gen_range(0..state.nodes.len() - 1)
+        rng.gen_range(0..nodes.len() - 1)
     };
     let uri_string = format!(
         "{}/ipfs/{}",
-        state.nodes[node_index].gateway,
+        nodes[node_index].gateway,
         &match r.segments::<PathBuf>(1..) {
             Ok(v) => v,
             Err(e) => return Err(anyhow!("{e:?}")),
@@ -157,7 +161,7 @@ pub async fn get_from_ipfs(r: &Request<'_>) -> Result<Response, anyhow::Error> {
     for h in r.headers().clone().into_iter() {
         ipfs_headers.append(
             reqwest::header::HeaderName::from_str(h.name().as_str())?,
-            reqwest::header::HeaderValue::from_str(&h.value().to_owned())?,
+            reqwest::header::HeaderValue::from_str(h.value())?,
         );
     }
 
@@ -0,0 +1,116 @@
+use anyhow::{anyhow, bail};
+
+/// Limits for strings decoded from contract events, anyone can emit those
+pub const MAX_CID_LEN: usize = 256;
+pub const MAX_API_URL_LEN: usize = 2048;
+pub const MAX_NAME_LEN: usize = 128;
+
+fn strip_control(v: &str) -> String {
+    v.chars().filter(|c| !c.is_control()).collect()
+}
+
+fn check_len(field: &str, v: &str, max: usize) -> Result<(), anyhow::Error> {
+    if v.is_empty() {
+        bail!("'{}' is empty", field);
+    }
+    if v.len() > max {
+        bail!(
+            "'{}' is '{}' bytes, at most '{}' allowed",
+            field,
+            v.len(),
+            max
+        );
+    }
+    Ok(())
+}
+
+/// Canonical CID, see `utils::cid`
+pub fn cid(v: &str) -> Result<String, anyhow::Error> {
+    let v = strip_control(v);
+    check_len("cid", v.trim(), MAX_CID_LEN)?;
+    super::cid::canonical(&v).map_err(|e| anyhow!("invalid CID: {}", e))
+}
+
+/// `http(s)` URL with a host, without trailing `/`
+pub fn api_url(v: &str) -> Result<String, anyhow::Error> {
+    let v = strip_control(v);
+    let v = v.trim();
+    check_len("api_url", v, MAX_API_URL_LEN)?;
+    let url = reqwest::Url::parse(v).map_err(|e| anyhow!("invalid api_url: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("api_url scheme '{}' is not http(s)", url.scheme());
+    }
+    if url.host_str().is_none() {
+        bail!("api_url has no host");
+    }
+    Ok(v.trim_end_matches('/').to_owned())
+}
+
+/// Lower case `0x` prefixed address
+pub fn address(v: &str) -> Result<String, anyhow::Error> {
+    let v = strip_control(v);
+    let hex = v.trim().trim_start_matches("0x");
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("invalid address '{}'", v.trim());
+    }
+    Ok(format!("0x{}", hex.to_lowercase()))
+}
+
+/// Provider name without control characters
+pub fn name(v: &str) -> Result<String, anyhow::Error> {
+    let v = strip_control(v);
+    let v = v.trim();
+    check_len("name", v, MAX_NAME_LEN)?;
+    Ok(v.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CID_V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const CID_V1: &str = "bafybeie5nqv6kd3qnfjupgvz34woh3oksc3iau6abmyajn7qvtf6d2ho34";
+
+    #[test]
+    fn cids() {
+        assert_eq!(cid(&format!(" {}\u{0}\n", CID_V0)).unwrap(), CID_V1);
+        assert_eq!(cid(CID_V1).unwrap(), CID_V1);
+        assert!(cid("").is_err());
+        assert!(cid("\u{7}").is_err());
+        assert!(cid("not a cid").is_err());
+        assert!(cid(&"a".repeat(MAX_CID_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn api_urls() {
+        assert_eq!(api_url(" https://node.example/ipfs/\r\n").unwrap(), "https://node.example/ipfs");
+        assert_eq!(api_url("http://10.0.0.1:5001").unwrap(), "http://10.0.0.1:5001");
+        assert!(api_url("").is_err());
+        assert!(api_url("ftp://node.example").is_err());
+        assert!(api_url("javascript:alert(1)").is_err());
+        assert!(api_url("node.example").is_err());
+        assert!(api_url(&format!("https://node.example/{}", "a".repeat(MAX_API_URL_LEN))).is_err());
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            address("0x2C7536E3605D9C16a7a3D7b1898e529396a65c23").unwrap(),
+            "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+        );
+        assert_eq!(
+            address("2c7536e3605d9c16a7a3d7b1898e529396a65c23").unwrap(),
+            "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+        );
+        assert!(address("0x2c7536e3605d9c16a7a3d7b1898e529396a65c2").is_err());
+        assert!(address("0x2c7536e3605d9c16a7a3d7b1898e529396a65cZZ").is_err());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(name(" my\u{1b}[31m node\t").unwrap(), "my[31m node");
+        assert!(name(" \u{0} ").is_err());
+        assert!(name(&"n".repeat(MAX_NAME_LEN + 1)).is_err());
+        assert!(name(&"n".repeat(MAX_NAME_LEN)).is_ok());
+    }
+}
@@ -0,0 +1,211 @@
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use web3::signing::{keccak256, recover};
+
+const HEADER_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+/// EIP-4361 (Sign-In With Ethereum) message, only the fields hosq cares about.
+///
+/// `statement` and `issued_at` are parsed to validate the message but not checked
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: String,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: i64,
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+}
+
+fn parse_time(v: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    Ok(DateTime::parse_from_rfc3339(v)?.with_timezone(&Utc))
+}
+
+impl SiweMessage {
+    pub fn parse(message: &str) -> Result<Self, anyhow::Error> {
+        let mut lines = message.lines();
+        let domain = match lines.next() {
+            Some(v) if v.ends_with(HEADER_SUFFIX) => v.trim_end_matches(HEADER_SUFFIX).to_owned(),
+            _ => return Err(anyhow!("missing SIWE header line")),
+        };
+        let address = match lines.next() {
+            Some(v) if v.starts_with("0x") && v.len() == 42 => v.to_lowercase(),
+            _ => return Err(anyhow!("missing or invalid address line")),
+        };
+
+        let (mut statement, mut uri, mut version, mut chain_id, mut nonce) =
+            (None, None, None, None, None);
+        let (mut issued_at, mut expiration_time, mut not_before) = (None, None, None);
+        for line in lines {
+            if line.is_empty() || line.starts_with("- ") {
+                continue;
+            }
+            match line.split_once(": ") {
+                Some(("URI", v)) => uri = Some(v.to_owned()),
+                Some(("Version", v)) => version = Some(v.to_owned()),
+                Some(("Chain ID", v)) => chain_id = Some(v.parse::<i64>()?),
+                Some(("Nonce", v)) => nonce = Some(v.to_owned()),
+                Some(("Issued At", v)) => issued_at = Some(parse_time(v)?),
+                Some(("Expiration Time", v)) => expiration_time = Some(parse_time(v)?),
+                Some(("Not Before", v)) => not_before = Some(parse_time(v)?),
+                Some(("Request ID", _)) => {}
+                _ if line == "Resources:" => {}
+                _ if uri.is_none() && statement.is_none() => statement = Some(line.to_owned()),
+                _ => return Err(anyhow!("unexpected line '{}'", line)),
+            }
+        }
+
+        Ok(Self {
+            domain,
+            address,
+            statement,
+            uri: uri.ok_or_else(|| anyhow!("missing 'URI'"))?,
+            version: version.ok_or_else(|| anyhow!("missing 'Version'"))?,
+            chain_id: chain_id.ok_or_else(|| anyhow!("missing 'Chain ID'"))?,
+            nonce: nonce.ok_or_else(|| anyhow!("missing 'Nonce'"))?,
+            issued_at: issued_at.ok_or_else(|| anyhow!("missing 'Issued At'"))?,
+            expiration_time,
+            not_before,
+        })
+    }
+
+    /// Checks that the message was issued for `domain`, in the only version defined by EIP-4361,
+    /// and that its `URI` points to the same origin.
+    pub fn verify_origin(&self, domain: &str) -> Result<(), anyhow::Error> {
+        if !self.domain.eq(domain) {
+            return Err(anyhow!("message for domain '{}', expected '{}'", self.domain, domain));
+        }
+        if !self.version.eq("1") {
+            return Err(anyhow!("unsupported version '{}'", self.version));
+        }
+        let uri = reqwest::Url::parse(&self.uri).map_err(|e| anyhow!("invalid URI > {}", e))?;
+        let authority = match (uri.host_str(), uri.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_owned(),
+            (None, _) => return Err(anyhow!("URI '{}' has no host", self.uri)),
+        };
+        if !authority.eq(domain) {
+            return Err(anyhow!("URI '{}' is not on domain '{}'", self.uri, domain));
+        }
+        Ok(())
+    }
+
+    /// Checks the time bounds of the message against the current time.
+    pub fn is_valid_now(&self) -> bool {
+        let now = Utc::now();
+        if let Some(exp) = self.expiration_time {
+            if exp <= now {
+                return false;
+            }
+        }
+        if let Some(nb) = self.not_before {
+            if nb > now {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Recovers the address that produced an EIP-191 `personal_sign` signature over `message`,
+/// returned as lowercase `0x` prefixed hex.
+pub fn recover_signer(message: &str, signature: &str) -> Result<String, anyhow::Error> {
+    let sig = hex::decode(signature.trim_start_matches("0x"))
+        .map_err(|e| anyhow!("invalid signature hex: {}", e))?;
+    if sig.len() != 65 {
+        return Err(anyhow!("signature must be 65 bytes, got {}", sig.len()));
+    }
+    let recovery_id = match sig[64] {
+        v @ 27..=28 => (v - 27) as i32,
+        v @ 0..=1 => v as i32,
+        v => return Err(anyhow!("invalid signature recovery byte {}", v)),
+    };
+
+    let mut eth_message =
+        format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    eth_message.extend_from_slice(message.as_bytes());
+    let address = recover(&keccak256(&eth_message), &sig[..64], recovery_id)
+        .map_err(|e| anyhow!("failed to recover signer: {:?}", e))?;
+    Ok(format!("{:?}", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "localhost:11666 wants you to sign in with your Ethereum account:
+0x2c7536e3605d9c16a7a3d7b1898e529396a65c23
+
+Sign in to hosq
+
+URI: http://localhost:11666
+Version: 1
+Chain ID: 1
+Nonce: abcdefgh12345678
+Issued At: 2023-01-01T00:00:00Z";
+    const SIGNATURE: &str = "0x2010ccfdb951b61f7f6862d8d9c555f3e5901f1dcc7f5968cf2e79149ae6ab56563568a02cb71c5ed344218433c05d499c6f6e96bf007aa75365c96400fef3d61b";
+    const SIGNER: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+
+    #[test]
+    fn parse_message() {
+        let msg = SiweMessage::parse(MESSAGE).unwrap();
+        assert_eq!(msg.domain, "localhost:11666");
+        assert_eq!(msg.address, SIGNER);
+        assert_eq!(msg.statement.as_deref(), Some("Sign in to hosq"));
+        assert_eq!(msg.uri, "http://localhost:11666");
+        assert_eq!(msg.version, "1");
+        assert_eq!(msg.chain_id, 1);
+        assert_eq!(msg.nonce, "abcdefgh12345678");
+        assert!(msg.expiration_time.is_none());
+        assert!(msg.is_valid_now());
+    }
+
+    #[test]
+    fn parse_invalid_message() {
+        assert!(SiweMessage::parse("").is_err());
+        assert!(SiweMessage::parse(&MESSAGE.replace("Nonce: abcdefgh12345678\n", "")).is_err());
+        assert!(SiweMessage::parse(&MESSAGE.replace("Chain ID: 1", "Chain ID: one")).is_err());
+        assert!(SiweMessage::parse(&MESSAGE.replace(SIGNER, "0x2c75")).is_err());
+    }
+
+    #[test]
+    fn recover_known_signer() {
+        assert_eq!(recover_signer(MESSAGE, SIGNATURE).unwrap(), SIGNER);
+        let tampered = MESSAGE.replace("Chain ID: 1", "Chain ID: 5");
+        assert_ne!(recover_signer(&tampered, SIGNATURE).unwrap(), SIGNER);
+        assert!(recover_signer(MESSAGE, &SIGNATURE[..SIGNATURE.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn expired_message() {
+        let expired = format!("{}\nExpiration Time: 2023-01-02T00:00:00Z", MESSAGE);
+        assert!(!SiweMessage::parse(&expired).unwrap().is_valid_now());
+        let not_yet = format!("{}\nNot Before: 2999-01-01T00:00:00Z", MESSAGE);
+        assert!(!SiweMessage::parse(&not_yet).unwrap().is_valid_now());
+    }
+
+    #[test]
+    fn verify_origin() {
+        let msg = SiweMessage::parse(MESSAGE).unwrap();
+        assert!(msg.verify_origin("localhost:11666").is_ok());
+        assert!(msg.verify_origin("hosq.example.com").is_err());
+
+        let other_uri = MESSAGE.replace("URI: http://localhost:11666", "URI: https://evil.example.com");
+        assert!(SiweMessage::parse(&other_uri).unwrap().verify_origin("localhost:11666").is_err());
+        let other_version = MESSAGE.replace("Version: 1", "Version: 2");
+        assert!(SiweMessage::parse(&other_version).unwrap().verify_origin("localhost:11666").is_err());
+    }
+
+    #[test]
+    fn invalid_signature_hex() {
+        assert!(recover_signer(MESSAGE, "0xabc").is_err());
+        assert!(recover_signer(MESSAGE, &format!("0x{}", "zz".repeat(65))).is_err());
+        assert!(recover_signer(MESSAGE, &format!("0x{}", "é0".repeat(65))).is_err());
+        assert!(recover_signer(MESSAGE, "").is_err());
+    }
+}
@@ -1,18 +1,653 @@
-use std::io::Read;
+use std::collections::{HashMap, HashSet};
 
-use crate::types::config::Config;
+use serde_yaml::{Mapping, Value};
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
 
-fn get_file_content(path: &String) -> String {
-    let mut f = std::fs::File::open(path).expect("error reading the yaml file");
-    let mut content: String = String::new();
-    f.read_to_string(&mut content)
-        .expect("Unable to read yml data");
-    content
+use crate::services::{contract_watcher, webhooks};
+use crate::types::{config::Config, errors::CustomError};
+
+/// Optional top level fields that can be set with `HOSQ_*` variables without being in the file
+const TOP_LEVEL_FIELDS: [&str; 14] = [
+    "update_nodes_sec",
+    "retry_failed_cids_sec",
+    "admin_secret",
+    "only_api",
+    "session_ttl_sec",
+    "admin_token_max_ttl_sec",
+    "siwe_domain",
+    "watch_config_sec",
+    "monitoring_retention_days",
+    "readiness",
+    "webhooks",
+    "webhook_expiring_sec",
+    "ipfs_nodes",
+    "node_capacity_sec",
+];
+
+/// Byte offset of the comment of a line, a `#` at its start or after a space outside of quotes
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = Option::None;
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = Option::None,
+            (Some(_), _) => {}
+            (None, '\'') | (None, '"') => quote = Option::Some(c),
+            (None, '#') if prev.is_whitespace() => return Option::Some(i),
+            _ => {}
+        }
+        prev = c;
+    }
+    Option::None
+}
+
+/// Replaces `${NAME}` with the value of the environment variable `NAME`, comments are kept as is
+fn interpolate(content: &str, problems: &mut Vec<String>) -> String {
+    let mut out = String::with_capacity(content.len());
+    for (i, line) in content.lines().enumerate() {
+        let (mut rest, comment) = line.split_at(comment_start(line).unwrap_or(line.len()));
+        while let Some(start) = rest.find("${") {
+            out.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(v) => start + v,
+                None => {
+                    problems.push(format!("line {}: unterminated '${{'", i + 1));
+                    break;
+                }
+            };
+            let name = &rest[start + 2..end];
+            match std::env::var(name) {
+                Ok(v) => out.push_str(&v),
+                Err(_) => problems.push(format!(
+                    "line {}: environment variable '{}' is not set",
+                    i + 1,
+                    name
+                )),
+            }
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        out.push_str(comment);
+        out.push('\n');
+    }
+    out
+}
+
+/// `HOSQ_<PATH>` overrides every field of the file, e.g. `HOSQ_ADMIN_SECRET` or
+/// `HOSQ_PROVIDERS_0_BATCH_SIZE`, values are parsed as YAML scalars
+fn apply_env_overrides(prefix: &str, value: &mut Value, problems: &mut Vec<String>) {
+    match value {
+        Value::Mapping(m) => {
+            let keys: Vec<Value> = m.iter().map(|(k, _)| k.clone()).collect();
+            for k in keys {
+                if let (Some(name), Some(v)) = (k.as_str().map(|s| s.to_owned()), m.get_mut(&k)) {
+                    apply_env_overrides(&format!("{}_{}", prefix, name.to_uppercase()), v, problems);
+                }
+            }
+        }
+        Value::Sequence(s) => {
+            for (i, v) in s.iter_mut().enumerate() {
+                apply_env_overrides(&format!("{}_{}", prefix, i), v, problems);
+            }
+        }
+        _ => {
+            if let Ok(v) = std::env::var(prefix) {
+                match serde_yaml::from_str::<Value>(&v) {
+                    Ok(v) => *value = v,
+                    Err(e) => problems.push(format!("{}: {}", prefix, e)),
+                }
+            }
+        }
+    }
+}
+
+/// `<field>_file: <path>` sets `<field>` to the trimmed content of the file, for secrets
+fn read_secret_files(value: &mut Value, problems: &mut Vec<String>) {
+    match value {
+        Value::Mapping(m) => {
+            let mut secrets = Mapping::new();
+            for (k, v) in m.iter_mut() {
+                match (k.as_str(), v.as_str()) {
+                    (Some(key), Some(path)) if key.ends_with("_file") => {
+                        match std::fs::read_to_string(path) {
+                            Ok(s) => {
+                                secrets.insert(
+                                    Value::String(key.trim_end_matches("_file").to_owned()),
+                                    Value::String(s.trim().to_owned()),
+                                );
+                            }
+                            Err(e) => problems.push(format!("{}: can't read '{}': {}", key, path, e)),
+                        }
+                    }
+                    _ => read_secret_files(v, problems),
+                }
+            }
+            for (k, v) in secrets {
+                m.insert(k, v);
+            }
+        }
+        Value::Sequence(s) => s.iter_mut().for_each(|v| read_secret_files(v, problems)),
+        _ => {}
+    }
+}
+
+enum Frame {
+    Mapping { path: String, key: Option<String> },
+    Sequence { path: String, index: usize },
+}
+
+/// Line of every node of the file by its path, e.g. `providers.0.contracts.1.address`
+#[derive(Default)]
+struct Lines {
+    stack: Vec<Frame>,
+    lines: HashMap<String, usize>,
+}
+
+impl Lines {
+    fn parse(content: &str) -> Lines {
+        let mut lines = Lines::default();
+        // syntax errors are reported by serde_yaml, lines found until then are kept
+        let _ = Parser::new(content.chars()).load(&mut lines, false);
+        lines
+    }
+
+    /// Line (1 based) of `path`, or of its closest parent in the file when it is not set there
+    fn of(&self, path: &str) -> String {
+        let mut path = path;
+        loop {
+            if let Some(l) = self.lines.get(path) {
+                return l.to_string();
+            }
+            match path.rfind('.') {
+                Some(i) => path = &path[..i],
+                None => return "?".to_owned(),
+            }
+        }
+    }
+
+    /// Path of the node starting with this event, `None` for mapping keys
+    fn enter(&mut self, ev: &Event, line: usize) -> Option<String> {
+        let path = match self.stack.last_mut() {
+            None => String::new(),
+            Some(Frame::Mapping { key: key @ None, path }) => {
+                let k = match ev {
+                    Event::Scalar(v, ..) => v.clone(),
+                    _ => "?".to_owned(),
+                };
+                let p = join(path, &k);
+                self.lines.insert(p, line);
+                *key = Option::Some(k);
+                return Option::None;
+            }
+            Some(Frame::Mapping { key, path }) => join(path, &key.take().unwrap_or_default()),
+            Some(Frame::Sequence { path, index }) => {
+                *index += 1;
+                let p = join(path, &(*index - 1).to_string());
+                self.lines.insert(p.clone(), line);
+                p
+            }
+        };
+        Option::Some(path)
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+impl MarkedEventReceiver for Lines {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            // complex keys get an empty path, they only have to keep the stack balanced
+            Event::MappingStart(_) => {
+                let path = self.enter(&ev, mark.line()).unwrap_or_default();
+                self.stack.push(Frame::Mapping { path, key: Option::None });
+            }
+            Event::SequenceStart(_) => {
+                let path = self.enter(&ev, mark.line()).unwrap_or_default();
+                self.stack.push(Frame::Sequence { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            Event::Scalar(..) | Event::Alias(_) => {
+                self.enter(&ev, mark.line());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_hex_address(v: &str) -> bool {
+    let v = v.trim_start_matches("0x");
+    v.len() == 40 && v.chars().all(|c| c.is_ascii_hexdigit())
 }
 
-pub fn get_conf(path: &String) -> Config {
-    let content = get_file_content(path);
-    let deserialized_point: Config = serde_yaml::from_str(&content).expect("error parsing yaml");
-    // println!("{:?}", deserialized_point);
-    deserialized_point
+fn validate(conf: &Config, content: &str, problems: &mut Vec<String>) {
+    let lines = Lines::parse(content);
+    let mut check = |ok: bool, path: &str, msg: String| {
+        if !ok {
+            problems.push(format!("line {}: {}", lines.of(path), msg));
+        }
+    };
+
+    check(conf.update_nodes_sec > 0, "update_nodes_sec", "'update_nodes_sec' must be > 0".to_owned());
+    check(
+        conf.retry_failed_cids_sec > 0,
+        "retry_failed_cids_sec",
+        "'retry_failed_cids_sec' must be > 0".to_owned(),
+    );
+    check(!conf.admin_secret.is_empty(), "admin_secret", "'admin_secret' is empty".to_owned());
+    if let Some(r) = &conf.readiness {
+        check(
+            r.head_stale_blocks > 0,
+            "readiness.head_stale_blocks",
+            "'readiness.head_stale_blocks' must be > 0".to_owned(),
+        );
+        check(
+            r.max_indexer_lag_blocks > 0,
+            "readiness.max_indexer_lag_blocks",
+            "'readiness.max_indexer_lag_blocks' must be > 0".to_owned(),
+        );
+    }
+
+    let nodes = conf.ipfs_nodes.clone().unwrap_or_default();
+    check(!nodes.is_empty(), "ipfs_nodes", "at least one IPFS node is required".to_owned());
+    let mut seen = HashSet::new();
+    for (i, n) in nodes.iter().enumerate() {
+        for (key, url) in [("api_url", &n.api_url), ("gateway", &n.gateway)] {
+            check(
+                url.starts_with("http://") || url.starts_with("https://"),
+                &format!("ipfs_nodes.{}.{}", i, key),
+                format!("ipfs_nodes[{}].{} '{}' must be an http(s) URL", i, key, url),
+            );
+        }
+        check(
+            seen.insert(n.api_url.trim_end_matches('/').to_owned()),
+            &format!("ipfs_nodes.{}.api_url", i),
+            format!("ipfs_nodes[{}].api_url '{}' is a duplicate", i, n.api_url),
+        );
+        check(
+            n.high_water_pct() > 0.0 && n.high_water_pct() <= 100.0,
+            &format!("ipfs_nodes.{}.high_water_pct", i),
+            format!("ipfs_nodes[{}].high_water_pct must be > 0 and <= 100", i),
+        );
+    }
+    check(
+        conf.node_capacity_sec != Some(0),
+        "node_capacity_sec",
+        "'node_capacity_sec' must be > 0".to_owned(),
+    );
+
+    for (i, w) in conf.webhooks.iter().flatten().enumerate() {
+        check(
+            w.url.starts_with("http://") || w.url.starts_with("https://"),
+            &format!("webhooks.{}.url", i),
+            format!("webhooks[{}].url '{}' must be an http(s) URL", i, w.url),
+        );
+        check(!w.events.is_empty(), &format!("webhooks.{}.events", i), format!("webhooks[{}].events is empty", i));
+        for (j, e) in w.events.iter().enumerate() {
+            check(
+                webhooks::EVENTS.contains(&e.as_str()),
+                &format!("webhooks.{}.events.{}", i, j),
+                format!("webhooks[{}] unknown event '{}'", i, e),
+            );
+        }
+        if let Some(d) = &w.donor {
+            check(
+                is_hex_address(d),
+                &format!("webhooks.{}.donor", i),
+                format!("webhooks[{}].donor '{}' is not a valid address", i, d),
+            );
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for (i, p) in conf.providers.iter().flatten().enumerate() {
+        check(
+            p.contract_address.is_some() != p.contracts.is_some(),
+            &format!("providers.{}", i),
+            format!("providers[{}] needs either contract_address or contracts", i),
+        );
+        let contracts = p.contracts();
+        check(
+            p.contracts.is_none() || !contracts.is_empty(),
+            &format!("providers.{}.contracts", i),
+            format!("providers[{}].contracts is empty", i),
+        );
+        for (j, c) in contracts.iter().enumerate() {
+            // a single `contract_address` is reported on its own line
+            let path = match p.contracts {
+                Some(_) => format!("providers.{}.contracts.{}", i, j),
+                None => format!("providers.{}.contract_address", i),
+            };
+            check(
+                is_hex_address(&c.address),
+                &format!("{}.address", path),
+                format!("providers[{}].contracts[{}].address '{}' is not a valid address", i, j, c.address),
+            );
+            check(
+                c.from_block.is_none_or(|b| b >= 0),
+                &format!("{}.from_block", path),
+                format!("providers[{}].contracts[{}].from_block must be >= 0", i, j),
+            );
+            if let (Some(from), Some(to)) = (c.from_block, c.to_block) {
+                check(
+                    from <= to,
+                    &format!("{}.to_block", path),
+                    format!("providers[{}].contracts[{}].to_block must be >= from_block", i, j),
+                );
+            }
+            check(
+                c.abi_version.is_none_or(|v| contract_watcher::ABI_VERSIONS.contains(&v)),
+                &format!("{}.abi_version", path),
+                format!("providers[{}].contracts[{}].abi_version {:?} is not supported", i, j, c.abi_version),
+            );
+        }
+        check(
+            p.provider.starts_with("ws://") || p.provider.starts_with("wss://"),
+            &format!("providers.{}.provider", i),
+            format!("providers[{}].provider '{}' must be a ws(s) URL", i, p.provider),
+        );
+        check(
+            p.batch_size > 0,
+            &format!("providers.{}.batch_size", i),
+            format!("providers[{}].batch_size must be > 0", i),
+        );
+        check(
+            p.start_block >= 0,
+            &format!("providers.{}.start_block", i),
+            format!("providers[{}].start_block must be >= 0", i),
+        );
+        for (key, v) in [
+            ("block_time_sec", p.block_time_sec),
+            ("block_update_sec", p.block_update_sec),
+            ("log_update_sec", p.log_update_sec),
+        ] {
+            check(v > 0, &format!("providers.{}.{}", i, key), format!("providers[{}].{} must be > 0", i, key));
+        }
+        if let Some(policy) = &p.pin_policy {
+            check(
+                !policy.size_tiers.is_empty(),
+                &format!("providers.{}.pin_policy.size_tiers", i),
+                format!("providers[{}].pin_policy.size_tiers is empty", i),
+            );
+        }
+        check(
+            p.provider_id.is_some() != p.listings.is_some(),
+            &format!("providers.{}", i),
+            format!("providers[{}] needs either provider_id or listings", i),
+        );
+        let listings = p.listings();
+        check(
+            p.listings.is_none() || !listings.is_empty(),
+            &format!("providers.{}.listings", i),
+            format!("providers[{}].listings is empty", i),
+        );
+        let mut ids = HashSet::new();
+        for (j, l) in listings.iter().enumerate() {
+            // a single `provider_id` is reported on its own line
+            let path = match p.listings {
+                Some(_) => format!("providers.{}.listings.{}", i, j),
+                None => format!("providers.{}", i),
+            };
+            check(
+                ids.insert(l.provider_id),
+                &format!("{}.provider_id", path),
+                format!("providers[{}].listings[{}].provider_id '{}' is a duplicate", i, j, l.provider_id),
+            );
+            for (k, n) in l.nodes.iter().flatten().enumerate() {
+                check(
+                    nodes.iter().any(|v| v.api_url.eq(n)),
+                    &format!("{}.nodes.{}", path, k),
+                    format!("providers[{}].listings[{}] node '{}' is not in ipfs_nodes", i, j, n),
+                );
+            }
+            check(
+                l.nodes.as_ref().is_none_or(|v| !v.is_empty()),
+                &format!("{}.nodes", path),
+                format!("providers[{}].listings[{}].nodes is empty", i, j),
+            );
+            if let Some(policy) = &l.pin_policy {
+                check(
+                    !policy.size_tiers.is_empty(),
+                    &format!("{}.pin_policy.size_tiers", path),
+                    format!("providers[{}].listings[{}].pin_policy.size_tiers is empty", i, j),
+                );
+            }
+        }
+        let mut ids: Vec<i64> = ids.into_iter().collect();
+        ids.sort();
+        check(
+            seen.insert((
+                p.provider.clone(),
+                contracts.iter().map(|c| c.address.to_lowercase()).collect::<Vec<_>>(),
+                ids,
+            )),
+            &format!("providers.{}", i),
+            format!("providers[{}] is a duplicate", i),
+        );
+    }
+}
+
+/// Reads, interpolates and validates the config.
+///
+/// Every problem found is reported with the line it comes from
+pub fn load_conf(path: &String) -> Result<Config, CustomError> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| CustomError::InvalidConfig(vec![format!("{}: {}", path, e)]))?;
+
+    let mut problems = vec![];
+    let content = interpolate(&raw, &mut problems);
+    let mut value: Value = match serde_yaml::from_str(&content) {
+        Ok(v) => v,
+        Err(e) => {
+            // a missing variable often is what broke the syntax, keep it reported
+            problems.push(e.to_string());
+            return Err(CustomError::InvalidConfig(problems));
+        }
+    };
+    read_secret_files(&mut value, &mut problems);
+    apply_env_overrides("HOSQ", &mut value, &mut problems);
+    if let Value::Mapping(m) = &mut value {
+        for field in TOP_LEVEL_FIELDS {
+            let key = Value::String(field.to_owned());
+            if m.get(&key).is_none() {
+                if let Ok(v) = std::env::var(format!("HOSQ_{}", field.to_uppercase())) {
+                    match serde_yaml::from_str::<Value>(&v) {
+                        Ok(v) => {
+                            m.insert(key, v);
+                        }
+                        Err(e) => problems.push(format!("HOSQ_{}: {}", field.to_uppercase(), e)),
+                    }
+                }
+            }
+        }
+    }
+
+    let conf: Config = match serde_yaml::from_value(value) {
+        Ok(v) => v,
+        Err(e) => {
+            // values carry no position, the file alone tells the line when it is the culprit
+            let problem = match serde_yaml::from_str::<Config>(&content).err().and_then(|f| {
+                f.location().map(|l| (l, f))
+            }) {
+                Some((l, f)) => format!("line {}: {}", l.line(), f),
+                None => format!("with the HOSQ_* overrides: {}", e),
+            };
+            problems.push(problem);
+            return Err(CustomError::InvalidConfig(problems));
+        }
+    };
+    validate(&conf, &content, &mut problems);
+
+    if problems.is_empty() {
+        Ok(conf)
+    } else {
+        Err(CustomError::InvalidConfig(problems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "update_nodes_sec: 5
+retry_failed_cids_sec: 30
+admin_secret: secret
+providers:
+  -
+    contracts:
+      - address: 9ce4cd6D7f5e8b14c7a3e8e6A257A86Bd5a6EeA0
+        to_block: 100
+      - address: '0x0000000000000000000000000000000000000000'
+        from_block: 50
+        to_block: 10
+        abi_version: 2
+    provider: ws://lab:8545
+    start_block: 0
+    block_time_sec: 15
+    block_update_sec: 5
+    log_update_sec: 5
+    chain_name: private
+    listings:
+      - provider_id: 1
+      - provider_id: 2
+        nodes: [http://localhost:5002]
+        pin_policy:
+          size_tiers: []
+    batch_size: 20
+ipfs_nodes:
+  -
+    api_url: http://localhost:5001
+    gateway: http://localhost:8080
+";
+
+    fn problems(content: &str) -> Vec<String> {
+        let conf: Config = serde_yaml::from_str(content).unwrap();
+        let mut problems = vec![];
+        validate(&conf, content, &mut problems);
+        problems
+    }
+
+    fn load(name: &str, content: &str) -> Result<Config, Vec<String>> {
+        let path = std::env::temp_dir().join(format!("hosq-{}-{}.yml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let r = load_conf(&path.to_string_lossy().into_owned());
+        std::fs::remove_file(&path).unwrap();
+        r.map_err(|e| match e {
+            CustomError::InvalidConfig(v) => v,
+            e => vec![e.to_string()],
+        })
+    }
+
+    #[test]
+    fn validation_lines() {
+        assert_eq!(
+            problems(CONFIG),
+            vec![
+                "line 11: providers[0].contracts[1].to_block must be >= from_block".to_owned(),
+                "line 12: providers[0].contracts[1].abi_version Some(2) is not supported".to_owned(),
+                "line 22: providers[0].listings[1] node 'http://localhost:5002' is not in ipfs_nodes"
+                    .to_owned(),
+                "line 24: providers[0].listings[1].pin_policy.size_tiers is empty".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn validation_lines_of_single_fields() {
+        let content = CONFIG
+            .replace(
+                "    contracts:
+      - address: 9ce4cd6D7f5e8b14c7a3e8e6A257A86Bd5a6EeA0
+        to_block: 100
+      - address: '0x0000000000000000000000000000000000000000'
+        from_block: 50
+        to_block: 10
+        abi_version: 2
+",
+                "    contract_address: 0x00\n",
+            )
+            .replace("batch_size: 20", "batch_size: 0")
+            .replace("admin_secret: secret", "admin_secret: ''");
+        assert_eq!(
+            problems(&content),
+            vec![
+                "line 3: 'admin_secret' is empty".to_owned(),
+                "line 6: providers[0].contracts[0].address '0x00' is not a valid address".to_owned(),
+                "line 19: providers[0].batch_size must be > 0".to_owned(),
+                "line 16: providers[0].listings[1] node 'http://localhost:5002' is not in ipfs_nodes"
+                    .to_owned(),
+                "line 18: providers[0].listings[1].pin_policy.size_tiers is empty".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_field_line() {
+        let content = CONFIG.replace("    block_time_sec: 15\n", "    block_time_sec: fast\n");
+        let problems = load("invalid-field", &content).unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("line 15: "), "{}", problems[0]);
+    }
+
+    #[test]
+    fn interpolate_env() {
+        std::env::set_var("HOSQ_TEST_INTERPOLATE", "s3cret");
+        let mut problems = vec![];
+        let out = interpolate(
+            "admin_secret: ${HOSQ_TEST_INTERPOLATE} # not ${HOSQ_TEST_UNSET}\n\
+             # admin_secret: ${HOSQ_TEST_UNSET}\n\
+             name: 'a # ${HOSQ_TEST_INTERPOLATE}'\n\
+             url: ${HOSQ_TEST_UNSET}\n",
+            &mut problems,
+        );
+        assert_eq!(
+            out,
+            "admin_secret: s3cret # not ${HOSQ_TEST_UNSET}\n\
+             # admin_secret: ${HOSQ_TEST_UNSET}\n\
+             name: 'a # s3cret'\n\
+             url: \n"
+        );
+        assert_eq!(problems, vec!["line 4: environment variable 'HOSQ_TEST_UNSET' is not set"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(comment_start("# all"), Some(0));
+        assert_eq!(comment_start("a: b # c"), Some(5));
+        assert_eq!(comment_start("a: b#c"), None);
+        assert_eq!(comment_start("a: \"b # c\" # d"), Some(11));
+    }
+
+    #[test]
+    fn env_overrides() {
+        std::env::set_var("HOSQ_OVERRIDE_PROVIDERS_0_BATCH_SIZE", "50");
+        std::env::set_var("HOSQ_OVERRIDE_ADMIN_SECRET", "from-env");
+        let mut value: Value = serde_yaml::from_str(CONFIG).unwrap();
+        let mut problems = vec![];
+        apply_env_overrides("HOSQ_OVERRIDE", &mut value, &mut problems);
+        let conf: Config = serde_yaml::from_value(value).unwrap();
+        assert!(problems.is_empty());
+        assert_eq!(conf.admin_secret, "from-env");
+        assert_eq!(conf.providers.unwrap()[0].batch_size, 50);
+        assert_eq!(conf.update_nodes_sec, 5);
+    }
+
+    #[test]
+    fn top_level_env_fields() {
+        std::env::set_var("HOSQ_NODE_CAPACITY_SEC", "120");
+        let content = CONFIG.replace("abi_version: 2", "abi_version: 1").replace("to_block: 10\n", "to_block: 60\n");
+        let content = content.replace("        nodes: [http://localhost:5002]\n        pin_policy:\n          size_tiers: []\n", "");
+        let conf = load("top-level", &content).unwrap();
+        assert_eq!(conf.node_capacity_sec, Some(120));
+    }
 }
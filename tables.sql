@@ -2,45 +2,199 @@ drop table if exists event_update_valid_block;
 create table event_update_valid_block
 (
     chain_id bigint not null,
-    cid text not null,
+    cid text not null, -- canonical CIDv1 base32
+    cid_original text not null, -- as emitted in the event
     donor text not null,
     update_block bigint not null,
     end_block bigint not null,
     manual_add boolean default false,
-    -- provider_id bigint not null,
+    claimed_end_block bigint, -- as emitted, set when end_block was capped to the payment
+    paid_wei numeric,
+    paid_end_block bigint,
+    payment text, -- paid, underpaid, capped, unverified or unchecked, null for manual adds
+    provider_id bigint not null,
+    tx_hash text, -- null for manual adds
+    log_index bigint,
     -- ts timestamp without time zone,
-    primary key (update_block, donor, end_block, cid, chain_id, manual_add)
+    id bigserial,
+    primary key (id),
+    unique (chain_id, tx_hash, log_index)
 );
+-- manual adds have no log to key them on
+create unique index event_update_valid_block_manual on event_update_valid_block
+    (chain_id, provider_id, cid, donor, update_block, end_block) where manual_add;
+create index event_update_valid_block_payment on event_update_valid_block (chain_id, payment);
 
-drop table if exists event_add_provider;
-create table event_add_provider
+-- a table before provider_events, see `migrations/001_baseline_upgrade.sql` to keep its rows
+do $$
+begin
+    if exists (select 1 from pg_class where relname = 'event_add_provider' and relkind = 'r') then
+        drop table event_add_provider;
+    else
+        drop view if exists event_add_provider;
+    end if;
+end $$;
+drop table if exists provider_events;
+create table provider_events
 (
+    id bigserial,
     chain_id bigint not null,
-    update_block bigint not null,
-    owner text not null,
     provider_id bigint not null,
-    block_price_gwei bigint not null,
-    api_url text not null,
-    name text not null,
-    primary key (chain_id, update_block, owner, provider_id, block_price_gwei, api_url, name)
+    event text not null, -- AddProvider, UpdateProviderBlockPrice, ...
+    update_block bigint not null,
+    tx_hash text not null,
+    log_index bigint not null,
+    -- AddProvider sets all of them, updates only the one they change
+    owner text,
+    block_price_gwei bigint,
+    api_url text,
+    name text,
+    primary key (id),
+    unique (chain_id, tx_hash, log_index)
 );
+create index provider_events_provider on provider_events (chain_id, provider_id, update_block);
+
+-- current state of every added provider, each field from its latest event
+create view event_add_provider as
+select p.chain_id,
+       p.provider_id,
+       (select max(e.update_block) from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id) as update_block,
+       (select e.owner from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.owner is not null
+        order by e.update_block desc, e.log_index desc limit 1) as owner,
+       (select e.block_price_gwei from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.block_price_gwei is not null
+        order by e.update_block desc, e.log_index desc limit 1) as block_price_gwei,
+       (select e.api_url from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.api_url is not null
+        order by e.update_block desc, e.log_index desc limit 1) as api_url,
+       (select e.name from provider_events e
+        where e.chain_id = p.chain_id and e.provider_id = p.provider_id and e.name is not null
+        order by e.update_block desc, e.log_index desc limit 1) as name
+from (select distinct chain_id, provider_id from provider_events where event = 'AddProvider') p;
 
 drop table if exists pinned_cids;
 create table pinned_cids
 (
     chain_id bigint not null,
+    provider_id bigint not null, -- listing the pin serves
     node text NOT NULL,
     cid TEXT NOT NULL,
     end_block BIGINT NOT NULL,
-    primary key (chain_id, node, cid, end_block)
+    pending_unpin boolean not null default false, -- expired, still pinned until the chain's grace period passed
+    primary key (chain_id, provider_id, node, cid, end_block)
 );
 
 drop table if exists failed_pins;
 create table failed_pins
 (
     chain_id bigint not null,
+    provider_id bigint not null, -- listing the pin serves
     node text NOT NULL,
     cid TEXT NOT NULL,
     end_block BIGINT NOT NULL,
-    primary key (chain_id, node, cid, end_block)
+    primary key (chain_id, provider_id, node, cid, end_block)
+);
+
+-- nodes drained through `/v0/admin/node/drain`, loaded back on start
+drop table if exists drained_nodes;
+create table drained_nodes
+(
+    node text not null, -- api_url
+    drained_at timestamp without time zone not null default now(),
+    primary key (node)
+);
+
+drop table if exists auth_nonces;
+create table auth_nonces
+(
+    nonce text not null,
+    created_at timestamp without time zone not null default now(),
+    primary key (nonce)
+);
+
+drop table if exists sessions;
+create table sessions
+(
+    token text not null,
+    address text not null,
+    chain_id bigint not null,
+    scopes text[] not null default '{}',
+    expires_at timestamp without time zone not null,
+    primary key (token)
+);
+
+drop table if exists monitoring_history;
+create table monitoring_history
+(
+    id bigserial,
+    chain_id bigint not null,
+    kind text not null,
+    event text not null,
+    ts bigint not null,
+    update_block bigint not null,
+    duration_ms bigint not null,
+    count bigint not null,
+    primary key (id)
+);
+create index monitoring_history_chain_ts on monitoring_history (chain_id, ts);
+
+drop table if exists webhook_outbox;
+create table webhook_outbox
+(
+    id bigserial,
+    url text not null,
+    subscription text not null, -- see `webhooks::subscription_id`
+    event text not null,
+    payload text not null,
+    attempts int not null default 0,
+    next_attempt_at bigint not null,
+    last_error text,
+    delivered_at bigint,
+    primary key (id)
+);
+create index webhook_outbox_pending on webhook_outbox (next_attempt_at) where delivered_at is null;
+
+drop table if exists rejected_events;
+create table rejected_events
+(
+    id bigserial,
+    chain_id bigint not null,
+    event text not null,
+    update_block bigint,
+    tx_hash text,
+    data text not null,
+    reason text not null,
+    ts bigint not null,
+    primary key (id)
+);
+create index rejected_events_chain_ts on rejected_events (chain_id, ts);
+
+drop table if exists quarantined_logs;
+create table quarantined_logs
+(
+    id bigserial,
+    chain_id bigint not null,
+    event text not null,
+    block_number bigint,
+    tx_hash text,
+    topics text[] not null,
+    data text not null,
+    log jsonb not null,
+    error text not null,
+    ts bigint not null,
+    reprocessed_at bigint,
+    primary key (id)
+);
+create index quarantined_logs_pending on quarantined_logs (chain_id) where reprocessed_at is null;
+
+drop table if exists cid_sizes;
+create table cid_sizes
+(
+    cid text not null,
+    size bigint not null,
+    num_blocks bigint not null,
+    updated_at bigint not null,
+    primary key (cid)
 );